@@ -4,6 +4,7 @@ use argon2::{
     },
     Argon2,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
 
@@ -15,6 +16,14 @@ pub struct User {
     pub salt: String,
 }
 
+/// Reads the username and password of an `Authorization: Basic` header.
+pub fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?.trim();
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
 #[derive(Clone)]
 pub struct AuthService {
     pool: PgPool,
@@ -85,6 +94,52 @@ impl AuthService {
         Ok(users)
     }
 
+    /// Returns a user's account without the password hash and salt.
+    pub async fn get_user(&self, id: i32) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(user.map(|user| User {
+            password_hash: String::new(),
+            salt: String::new(),
+            ..user
+        }))
+    }
+
+    /// Returns the secret token of a user's private feeds, creating it on
+    /// first use, or `None` if the user does not exist.
+    pub async fn feed_token(&self, id: i32) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(
+            "UPDATE users SET feed_token = coalesce(feed_token, gen_random_uuid()::TEXT)
+            WHERE id = $1
+            RETURNING feed_token",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Replaces the feed token, so subscriptions using the old one stop
+    /// working.
+    pub async fn reset_feed_token(&self, id: i32) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(
+            "UPDATE users SET feed_token = gen_random_uuid()::TEXT WHERE id = $1 RETURNING feed_token",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Returns the id of the user a feed token belongs to.
+    pub async fn feed_token_user(&self, token: &str) -> Result<Option<i32>, sqlx::Error> {
+        sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE feed_token = $1")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
     pub async fn deregister(&self, id: i32) -> Result<(), sqlx::Error> {
         let result = sqlx::query("DELETE FROM users WHERE id = $1")
             .bind(id)
@@ -98,3 +153,23 @@ impl AuthService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_basic_credentials() {
+        assert_eq!(
+            basic_credentials("Basic YWxleDpzZWNyZXQ6cGFzcw=="),
+            Some(("alex".to_string(), "secret:pass".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_other_authorization_headers() {
+        assert_eq!(basic_credentials("Bearer YWxleDpzZWNyZXQ="), None);
+        assert_eq!(basic_credentials("Basic not base64!"), None);
+        assert_eq!(basic_credentials("Basic YWxleA=="), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! Reads a Calibre library from its `metadata.db`.
+
+use std::path::Path;
+
+use rocket::serde::json::{json, serde_json::Map, Value};
+use rusqlite::{Connection, OpenFlags, Row};
+
+/// Calibre stores an unknown publication date as this year.
+const UNDEFINED_YEAR: i64 = 101;
+
+const BOOKS_QUERY: &str = "SELECT b.uuid, b.title, b.path, b.has_cover, b.pubdate,
+        b.series_index,
+        coalesce(
+            (SELECT i.val FROM identifiers i WHERE i.book = b.id AND i.type = 'isbn'),
+            nullif(b.isbn, '')
+        ) AS isbn,
+        (SELECT group_concat(name, '; ') FROM (
+            SELECT a.name FROM books_authors_link l JOIN authors a ON a.id = l.author
+            WHERE l.book = b.id ORDER BY l.id
+        )) AS authors,
+        (SELECT s.name FROM books_series_link l JOIN series s ON s.id = l.series
+            WHERE l.book = b.id) AS series,
+        (SELECT group_concat(name, char(31)) FROM (
+            SELECT t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag
+            WHERE l.book = b.id ORDER BY t.name
+        )) AS tags,
+        (SELECT r.rating FROM books_ratings_link l JOIN ratings r ON r.id = l.rating
+            WHERE l.book = b.id) AS rating,
+        (SELECT p.name FROM books_publishers_link l JOIN publishers p ON p.id = l.publisher
+            WHERE l.book = b.id) AS publisher,
+        (SELECT g.lang_code FROM books_languages_link l JOIN languages g ON g.id = l.lang_code
+            WHERE l.book = b.id ORDER BY l.item_order LIMIT 1) AS language,
+        (SELECT c.text FROM comments c WHERE c.book = b.id) AS comments,
+        EXISTS (SELECT 1 FROM data d WHERE d.book = b.id) AS has_files
+    FROM books b
+    ORDER BY b.id";
+
+/// A book of a Calibre library: the catalog fields to import and the
+/// owner's rating out of five stars.
+pub struct CalibreBook {
+    pub fields: Value,
+    pub rating: Option<i64>,
+}
+
+/// Reads every book of the library whose `metadata.db` is at `path`. With a
+/// `cover_url`, books with a cover point at `<cover_url>/<book folder>/cover.jpg`,
+/// for libraries served over HTTP.
+pub fn read_library(path: &Path, cover_url: Option<&str>) -> rusqlite::Result<Vec<CalibreBook>> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare(BOOKS_QUERY)?;
+    let books = statement
+        .query_map([], |row| calibre_book(row, cover_url))?
+        .collect();
+    books
+}
+
+fn calibre_book(row: &Row, cover_url: Option<&str>) -> rusqlite::Result<CalibreBook> {
+    let uuid: String = row.get("uuid")?;
+    let mut fields = Map::new();
+    fields.insert("source_key".to_string(), json!(format!("calibre:{}", uuid)));
+    fields.insert("title".to_string(), json!(row.get::<_, String>("title")?));
+    let optional = [
+        ("author", row.get::<_, Option<String>>("authors")?),
+        ("isbn", row.get("isbn")?),
+        ("publisher", row.get("publisher")?),
+        ("language", row.get("language")?),
+        (
+            "description",
+            row.get::<_, Option<String>>("comments")?
+                .map(|comments| plain_text(&comments)),
+        ),
+    ];
+    for (field, value) in optional {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            fields.insert(field.to_string(), json!(value));
+        }
+    }
+
+    let year = row
+        .get::<_, Option<String>>("pubdate")?
+        .and_then(|date| date.get(..4)?.parse::<i64>().ok())
+        .filter(|year| *year > UNDEFINED_YEAR);
+    if let Some(year) = year {
+        fields.insert("year".to_string(), json!(year));
+    }
+    if let Some(series) = row.get::<_, Option<String>>("series")? {
+        let volume: f64 = row.get("series_index")?;
+        fields.insert(
+            "series".to_string(),
+            json!([{ "name": series, "volume": volume }]),
+        );
+    }
+    if let Some(tags) = row.get::<_, Option<String>>("tags")? {
+        let subjects: Vec<&str> = tags.split('\u{1f}').collect();
+        fields.insert("subjects".to_string(), json!(subjects));
+    }
+    if row.get("has_files")? {
+        fields.insert("format".to_string(), json!("ebook"));
+    }
+    if let (Some(cover_url), true) = (cover_url, row.get::<_, bool>("has_cover")?) {
+        let folder: String = row.get("path")?;
+        fields.insert(
+            "cover".to_string(),
+            json!(format!(
+                "{}/{}/cover.jpg",
+                cover_url.trim_end_matches('/'),
+                encode_path(&folder)
+            )),
+        );
+    }
+
+    // Calibre rates out of ten so that half stars can be stored.
+    let rating = row
+        .get::<_, Option<i64>>("rating")?
+        .filter(|rating| *rating > 0)
+        .map(|rating| ((rating + 1) / 2).clamp(1, 5));
+    Ok(CalibreBook {
+        fields: Value::Object(fields),
+        rating,
+    })
+}
+
+/// The owner's ratings as library entries for a reads import, given the
+/// rows of the catalog import of `books`. Each entry names the imported book
+/// by id, with its title and author for display. Rows are numbered by line,
+/// so row n is the n-th book of the library.
+pub fn rating_entries(books: &[CalibreBook], rows: &Value) -> Vec<Value> {
+    rows.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|row| {
+            let book = books.get((row["row"].as_u64()? as usize).checked_sub(1)?)?;
+            Some(json!({
+                "source": "calibre",
+                "book": {
+                    "id": row["book_id"].as_str()?,
+                    "title": book.fields["title"],
+                    "author": book.fields["author"],
+                },
+                "status": "read",
+                "rating": book.rating?,
+                "read_at": null,
+                "review": null,
+                "shelves": [],
+            }))
+        })
+        .collect()
+}
+
+/// Calibre keeps descriptions as HTML; the catalog shows plain text.
+fn plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Percent-encodes a folder path for use in a URL, keeping its slashes.
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str, rating: Option<i64>) -> CalibreBook {
+        CalibreBook {
+            fields: json!({ "title": title, "author": "Ursula K. Le Guin" }),
+            rating,
+        }
+    }
+
+    #[test]
+    fn turns_descriptions_into_plain_text() {
+        assert_eq!(
+            plain_text(
+                "<div><p>A wizard&nbsp;of <b>Earthsea</b></p><p>Ged &amp; the shadow</p></div>"
+            ),
+            "A wizard of Earthsea Ged & the shadow"
+        );
+    }
+
+    #[test]
+    fn encodes_folders_for_cover_urls() {
+        assert_eq!(
+            encode_path("Ursula K. Le Guin/A Wizard of Earthsea (12)"),
+            "Ursula%20K.%20Le%20Guin/A%20Wizard%20of%20Earthsea%20%2812%29"
+        );
+    }
+
+    #[test]
+    fn sends_rated_books_by_id_with_their_title_and_author() {
+        let books = [book("A Wizard of Earthsea", Some(5)), book("Tehanu", None)];
+        let rows = json!([
+            { "row": 1, "book_id": "b1" },
+            { "row": 2, "book_id": "b2" },
+            { "row": 3, "book_id": "b3" },
+        ]);
+        let entries = rating_entries(&books, &rows);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0]["book"],
+            json!({
+                "id": "b1",
+                "title": "A Wizard of Earthsea",
+                "author": "Ursula K. Le Guin",
+            })
+        );
+        assert_eq!(entries[0]["rating"], 5);
+    }
+}
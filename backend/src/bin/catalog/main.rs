@@ -0,0 +1,439 @@
+//! Command-line client for catalog maintenance through the book-recommender
+//! API.
+//!
+//! ```text
+//! catalog import <file> [--format csv|jsonl] [--dry-run]
+//! catalog calibre <metadata.db> [--user <id>] [--cover-url <url>] [--dry-run]
+//! catalog openlibrary <editions dump> --authors <authors dump> [--works <works dump>]
+//!     [--language <code>]... [--subject <subject>]... [--batch-size <n>] [--dry-run]
+//! catalog export [--format csv|jsonl|marcxml|bibtex|ris] [--genre <genre>] [--author <author>]
+//!     [--output <file>]
+//! catalog retry <import id>
+//! ```
+//!
+//! The API is read from `CATALOG_API_URL` and defaults to the local server.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+use rocket::serde::json::{json, Value};
+
+mod calibre;
+mod openlibrary;
+
+const DEFAULT_API_URL: &str = "http://localhost:8000/api";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const DEFAULT_SEED_BATCH_SIZE: usize = 500;
+
+/// Keeps seed batches below the API's default 1 MiB limit on JSON bodies.
+const MAX_SEED_BATCH_BYTES: usize = 768 * 1024;
+
+const USAGE: &str = "usage: catalog import <file> [--format csv|jsonl] [--dry-run]
+       catalog calibre <metadata.db> [--user <id>] [--cover-url <url>] [--dry-run]
+       catalog openlibrary <editions dump> --authors <authors dump> [--works <works dump>]
+           [--language <code>]... [--subject <subject>]... [--batch-size <n>] [--dry-run]
+       catalog export [--format csv|jsonl|marcxml|bibtex|ris] [--genre <genre>] [--author <author>]
+           [--output <file>]
+       catalog retry <import id>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("import") => import(&args[1..]),
+        Some("calibre") => import_calibre(&args[1..]),
+        Some("openlibrary") => import_openlibrary(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("retry") => retry(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn api_url() -> String {
+    env::var("CATALOG_API_URL")
+        .unwrap_or_else(|_| DEFAULT_API_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn count(value: &Value, field: &str) -> i64 {
+    value[field].as_i64().unwrap_or_default()
+}
+
+fn json(response: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    response
+        .map_err(|e| e.to_string())?
+        .into_json()
+        .map_err(|e| e.to_string())
+}
+
+fn get(url: &str) -> Result<Value, String> {
+    json(ureq::get(url).call())
+}
+
+/// Uploads a CSV or JSON Lines file as an import job and follows its progress
+/// until it finishes, then lists the rows that could not be imported.
+fn import(args: &[String]) -> Result<(), String> {
+    let mut file: Option<&str> = None;
+    let mut format: Option<String> = None;
+    let mut dry_run = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--format" => format = args.next().cloned(),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let file = file.ok_or(USAGE)?;
+    let format = format
+        .or_else(|| {
+            Path::new(file)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| match extension {
+                    "ndjson" => "jsonl".to_string(),
+                    extension => extension.to_lowercase(),
+                })
+        })
+        .ok_or("could not tell the format from the file name, pass --format")?;
+    let contents = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+
+    let api = api_url();
+    let id = submit(&format!("{}/imports", api), &format, dry_run, &contents)?;
+    follow(&api, id).map(|_| ())
+}
+
+/// Queues an import of `contents` at `url` and returns the id of the job.
+fn submit(url: &str, format: &str, dry_run: bool, contents: &str) -> Result<i64, String> {
+    let job: Value = match ureq::post(url)
+        .query("format", format)
+        .query("dry_run", &dry_run.to_string())
+        .send_string(contents)
+    {
+        Ok(response) => response.into_json().map_err(|e| e.to_string())?,
+        Err(ureq::Error::Status(_, response)) => {
+            let errors: Value = response.into_json().unwrap_or_default();
+            let messages: Vec<&str> = errors
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|error| error["message"].as_str())
+                .collect();
+            return Err(format!("import rejected: {}", messages.join("; ")));
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    let id = count(&job, "id");
+    println!(
+        "Import {} queued{}",
+        id,
+        if dry_run { " (dry run)" } else { "" }
+    );
+    Ok(id)
+}
+
+/// Imports the books of a Calibre library into the catalog. Each book is
+/// keyed by its Calibre UUID, so running the import again updates the books
+/// it created instead of adding them twice. With `--user`, the books the
+/// owner rated are then added to that user's reads with their ratings.
+fn import_calibre(args: &[String]) -> Result<(), String> {
+    let mut file: Option<&str> = None;
+    let mut user: Option<i64> = None;
+    let mut cover_url: Option<&str> = None;
+    let mut dry_run = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--user" => {
+                let id = args.next().and_then(|id| id.parse().ok());
+                user = Some(id.ok_or(USAGE)?);
+            }
+            "--cover-url" => cover_url = Some(args.next().ok_or(USAGE)?),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let file = file.ok_or(USAGE)?;
+    let books = calibre::read_library(Path::new(file), cover_url)
+        .map_err(|e| format!("{}: {}", file, e))?;
+    if books.is_empty() {
+        println!("The library has no books");
+        return Ok(());
+    }
+    let contents: Vec<String> = books.iter().map(|book| book.fields.to_string()).collect();
+
+    let api = api_url();
+    let id = submit(
+        &format!("{}/imports", api),
+        "jsonl",
+        dry_run,
+        &contents.join("\n"),
+    )?;
+    follow(&api, id)?;
+    let Some(user) = user else {
+        return Ok(());
+    };
+    if dry_run {
+        println!("Ratings are imported for user {} without --dry-run", user);
+        return Ok(());
+    }
+
+    let rows = get(&format!("{}/imports/{}/rows", api, id))?;
+    let entries: Vec<String> = calibre::rating_entries(&books, &rows)
+        .iter()
+        .map(Value::to_string)
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let id = submit(
+        &format!("{}/users/{}/imports", api, user),
+        "library",
+        false,
+        &entries.join("\n"),
+    )?;
+    follow(&api, id).map(|_| ())
+}
+
+/// Seeds the catalog from Open Library dumps. Works and authors are staged
+/// on disk first, then the editions are streamed and sent in batches. Each
+/// book is keyed by its edition, so the load can be repeated with a newer
+/// dump to add and enrich books.
+fn import_openlibrary(args: &[String]) -> Result<(), String> {
+    let mut editions: Option<&str> = None;
+    let mut works: Option<&str> = None;
+    let mut authors: Option<&str> = None;
+    let mut filter = openlibrary::Filter {
+        languages: vec![],
+        subjects: vec![],
+    };
+    let mut batch_size = DEFAULT_SEED_BATCH_SIZE;
+    let mut dry_run = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--works" => works = Some(args.next().ok_or(USAGE)?),
+            "--authors" => authors = Some(args.next().ok_or(USAGE)?),
+            "--language" => filter
+                .languages
+                .push(args.next().ok_or(USAGE)?.to_lowercase()),
+            "--subject" => filter
+                .subjects
+                .push(args.next().ok_or(USAGE)?.to_lowercase()),
+            "--batch-size" => {
+                let size = args.next().and_then(|size| size.parse().ok());
+                batch_size = size.filter(|size| *size > 0).ok_or(USAGE)?;
+            }
+            _ if editions.is_none() => editions = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let (Some(editions), Some(authors)) = (editions, authors) else {
+        return Err(USAGE.to_string());
+    };
+
+    let mut staging = openlibrary::Staging::create(
+        &env::temp_dir().join(format!("openlibrary-staging-{}.db", std::process::id())),
+    )?;
+    if let Some(works) = works {
+        eprintln!("Staging works…");
+        let staged = staging.stage_works(Path::new(works), &filter)?;
+        eprintln!("{} works staged", staged);
+    }
+    eprintln!("Staging authors…");
+    let staged = staging.stage_authors(Path::new(authors))?;
+    eprintln!("{} authors staged", staged);
+
+    let api = api_url();
+    let mut totals = json!({ "created": 0, "enriched": 0, "unchanged": 0, "invalid": 0 });
+    let mut sent = 0;
+    let (read, loaded) = openlibrary::load_editions(
+        Path::new(editions),
+        &staging,
+        &filter,
+        batch_size,
+        MAX_SEED_BATCH_BYTES,
+        |batch| {
+            if !dry_run {
+                let report = json(ureq::post(&format!("{}/books/seed", api)).send_json(batch))?;
+                for field in ["created", "enriched", "unchanged"] {
+                    totals[field] = json!(count(&totals, field) + count(&report, field));
+                }
+                let invalid = report["invalid"].as_array().map_or(0, Vec::len) as i64;
+                totals["invalid"] = json!(count(&totals, "invalid") + invalid);
+            }
+            sent += batch.len();
+            eprint!(
+                "\r{} books {}",
+                sent,
+                if dry_run { "matched" } else { "sent" }
+            );
+            Ok(())
+        },
+    )?;
+    eprintln!();
+
+    println!(
+        "{}{} editions read, {} matched the filter",
+        if dry_run { "Dry run: " } else { "" },
+        read,
+        loaded
+    );
+    if !dry_run {
+        println!(
+            "{} created, {} enriched, {} unchanged, {} invalid",
+            count(&totals, "created"),
+            count(&totals, "enriched"),
+            count(&totals, "unchanged"),
+            count(&totals, "invalid")
+        );
+    }
+    Ok(())
+}
+
+/// Downloads the catalog, or the books of a genre or author, to a file or to
+/// standard output. The format defaults to the one matching the file name,
+/// or CSV.
+fn export(args: &[String]) -> Result<(), String> {
+    let mut format: Option<String> = None;
+    let mut output: Option<&str> = None;
+    let mut filters: Vec<(&str, &str)> = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = Some(args.next().ok_or(USAGE)?.to_lowercase()),
+            "--output" => output = Some(args.next().ok_or(USAGE)?),
+            "--genre" => filters.push(("genre", args.next().ok_or(USAGE)?)),
+            "--author" => filters.push(("author", args.next().ok_or(USAGE)?)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let format = format
+        .or_else(|| {
+            let extension = Path::new(output?).extension()?.to_str()?.to_lowercase();
+            Some(
+                match extension.as_str() {
+                    "xml" | "marc" => "marcxml",
+                    "bib" => "bibtex",
+                    "ndjson" => "jsonl",
+                    extension => extension,
+                }
+                .to_string(),
+            )
+        })
+        .unwrap_or_else(|| "csv".to_string());
+
+    let mut request = ureq::get(&format!("{}/books/export", api_url())).query("format", &format);
+    for (name, value) in filters {
+        request = request.query(name, value);
+    }
+    let response = request.call().map_err(|e| e.to_string())?;
+    let mut body = response.into_reader();
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    io::copy(&mut body, &mut out).map_err(|e| e.to_string())?;
+    out.flush().map_err(|e| e.to_string())
+}
+
+/// Queues a failed import again and follows it from its last processed row.
+fn retry(args: &[String]) -> Result<(), String> {
+    let id: i64 = match args {
+        [id] => id.parse().map_err(|_| USAGE.to_string())?,
+        _ => return Err(USAGE.to_string()),
+    };
+    let api = api_url();
+    let retried = json(ureq::post(&format!("{}/imports/{}/retry", api, id)).call())?;
+    if retried != Value::Bool(true) {
+        return Err(format!("import {} has not failed", id));
+    }
+    follow(&api, id).map(|_| ())
+}
+
+/// Prints the progress of an import until it finishes, then its totals and
+/// the rows that could not be imported. Returns the finished job.
+fn follow(api: &str, id: i64) -> Result<Value, String> {
+    let job = loop {
+        let job = get(&format!("{}/imports/{}", api, id))?;
+        if job.is_null() {
+            return Err(format!("there is no import {}", id));
+        }
+        eprint!(
+            "\r{}/{} rows processed",
+            count(&job, "processed"),
+            count(&job, "total")
+        );
+        if matches!(job["status"].as_str(), Some("completed" | "failed")) {
+            eprintln!();
+            break job;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let totals = match job["user_id"].is_null() {
+        true => format!(
+            "{} created, {} updated, {} unchanged",
+            count(&job, "created"),
+            count(&job, "updated"),
+            count(&job, "unchanged")
+        ),
+        false => format!(
+            "{} matched, {} created, {} unmatched, {} skipped",
+            count(&job, "matched"),
+            count(&job, "created"),
+            count(&job, "unmatched"),
+            count(&job, "skipped")
+        ),
+    };
+    println!(
+        "{}{}, {} invalid",
+        if job["dry_run"] == Value::Bool(true) {
+            "Dry run: "
+        } else {
+            ""
+        },
+        totals,
+        count(&job, "failed")
+    );
+    if count(&job, "failed") > 0 {
+        let rows = get(&format!("{}/imports/{}/rows?outcome=invalid", api, id))?;
+        for row in rows.as_array().into_iter().flatten() {
+            for error in row["errors"].as_array().into_iter().flatten() {
+                println!(
+                    "row {}: {}: {}",
+                    row["row"],
+                    error["field"].as_str().unwrap_or_default(),
+                    error["message"].as_str().unwrap_or_default()
+                );
+            }
+        }
+    }
+    match job["status"].as_str() {
+        Some("failed") => Err(format!(
+            "import {} failed: {}; resume it with `catalog retry {}`",
+            id,
+            job["error"].as_str().unwrap_or("unknown error"),
+            id
+        )),
+        _ => Ok(job),
+    }
+}
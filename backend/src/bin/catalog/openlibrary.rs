@@ -0,0 +1,543 @@
+//! Streams Open Library data dumps into catalog books.
+//!
+//! Dumps are tab-separated files, usually gzipped, with one record per line:
+//! type, key, revision, last modified date and the record as JSON. Editions
+//! become books; their authors and works are looked up in a staging database
+//! on disk, so memory use does not grow with the size of the dumps.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+use rocket::serde::json::{
+    json,
+    serde_json::{self, Map},
+    Value,
+};
+use rusqlite::{params_from_iter, Connection, OptionalExtension};
+
+/// Rows written to the staging database per transaction.
+const STAGING_BATCH: usize = 10_000;
+
+/// Subjects kept per book; popular works carry hundreds.
+const MAX_SUBJECTS: usize = 10;
+
+const COVER_URL: &str = "https://covers.openlibrary.org/b/id";
+
+/// Which editions to load. An empty list lets everything through.
+pub struct Filter {
+    pub languages: Vec<String>,
+    pub subjects: Vec<String>,
+}
+
+impl Filter {
+    fn language(&self, languages: &[String]) -> bool {
+        self.languages.is_empty()
+            || languages
+                .iter()
+                .any(|language| self.languages.contains(language))
+    }
+
+    fn subject(&self, subjects: &[String]) -> bool {
+        self.subjects.is_empty()
+            || subjects.iter().any(|subject| {
+                let subject = subject.to_lowercase();
+                self.subjects.iter().any(|wanted| subject.contains(wanted))
+            })
+    }
+}
+
+/// Opens a dump, decompressing it if its name ends in `.gz`.
+fn open_dump(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    Ok(
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+            _ => Box::new(BufReader::new(file)),
+        },
+    )
+}
+
+/// Calls `f` with the JSON record of every line of a dump. Lines that are
+/// not records are skipped.
+fn for_each_record(
+    path: &Path,
+    mut f: impl FnMut(Value) -> Result<(), String>,
+) -> Result<(), String> {
+    let dump = open_dump(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for line in dump.lines() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        let Some(record) = line.splitn(5, '\t').nth(4) else {
+            continue;
+        };
+        if let Ok(record) = serde_json::from_str::<Value>(record) {
+            f(record)?;
+        }
+    }
+    Ok(())
+}
+
+fn text(value: &Value) -> Option<String> {
+    // Descriptions are either a string or a `/type/text` object.
+    value
+        .as_str()
+        .or_else(|| value["value"].as_str())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Keys of references like `[{"key": "/authors/OL1A"}]` or, in works,
+/// `[{"author": {"key": "/authors/OL1A"}}]`.
+fn reference_keys(value: &Value, nested: Option<&str>) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|reference| match nested {
+            Some(field) => reference[field]["key"].as_str(),
+            None => reference["key"].as_str(),
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+struct StagedWork {
+    title: Option<String>,
+    subjects: Vec<String>,
+    description: Option<String>,
+    authors: Vec<String>,
+}
+
+/// A throwaway SQLite database holding the works and authors that editions
+/// refer to. It is deleted when dropped.
+pub struct Staging {
+    connection: Connection,
+    path: PathBuf,
+}
+
+impl Staging {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let _ = fs::remove_file(path);
+        let connection = Connection::open(path).map_err(|e| e.to_string())?;
+        connection
+            .execute_batch(
+                "PRAGMA journal_mode = OFF;
+                PRAGMA synchronous = OFF;
+                CREATE TABLE works (key TEXT PRIMARY KEY, title TEXT, subjects TEXT,
+                    description TEXT, authors TEXT);
+                CREATE TABLE authors (key TEXT PRIMARY KEY, name TEXT NOT NULL);",
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(Staging {
+            connection,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Inserts rows in transactions of [`STAGING_BATCH`] rows.
+    fn stage(
+        &mut self,
+        dump: &Path,
+        statement: &str,
+        mut row: impl FnMut(&Value) -> Option<Vec<Option<String>>>,
+    ) -> Result<usize, String> {
+        let mut staged = 0;
+        let mut pending: Vec<Vec<Option<String>>> = vec![];
+        let mut flush = |pending: &mut Vec<Vec<Option<String>>>| -> Result<(), String> {
+            let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+            {
+                let mut insert = transaction
+                    .prepare_cached(statement)
+                    .map_err(|e| e.to_string())?;
+                for values in pending.drain(..) {
+                    insert
+                        .execute(params_from_iter(values))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            transaction.commit().map_err(|e| e.to_string())
+        };
+        for_each_record(dump, |record| {
+            if let Some(values) = row(&record) {
+                pending.push(values);
+                staged += 1;
+                if pending.len() >= STAGING_BATCH {
+                    flush(&mut pending)?;
+                }
+            }
+            Ok(())
+        })?;
+        flush(&mut pending)?;
+        Ok(staged)
+    }
+
+    /// Stages the works that pass the subject filter.
+    pub fn stage_works(&mut self, dump: &Path, filter: &Filter) -> Result<usize, String> {
+        self.stage(
+            dump,
+            "INSERT OR REPLACE INTO works VALUES (?1, ?2, ?3, ?4, ?5)",
+            |work| {
+                let subjects = strings(&work["subjects"]);
+                if !filter.subject(&subjects) {
+                    return None;
+                }
+                Some(vec![
+                    work["key"].as_str().map(str::to_string),
+                    text(&work["title"]),
+                    Some(json!(subjects).to_string()),
+                    text(&work["description"]),
+                    Some(json!(reference_keys(&work["authors"], Some("author"))).to_string()),
+                ])
+            },
+        )
+    }
+
+    pub fn stage_authors(&mut self, dump: &Path) -> Result<usize, String> {
+        self.stage(
+            dump,
+            "INSERT OR REPLACE INTO authors (key, name) VALUES (?1, ?2)",
+            |author| {
+                Some(vec![
+                    author["key"].as_str().map(str::to_string),
+                    Some(text(&author["name"])?),
+                ])
+            },
+        )
+    }
+
+    fn work(&self, key: &str) -> Result<Option<StagedWork>, String> {
+        let list = |value: Option<String>| -> Vec<String> {
+            value
+                .and_then(|value| serde_json::from_str(&value).ok())
+                .unwrap_or_default()
+        };
+        self.connection
+            .prepare_cached(
+                "SELECT title, subjects, description, authors FROM works WHERE key = ?1",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_row([key], |row| {
+                        Ok(StagedWork {
+                            title: row.get(0)?,
+                            subjects: list(row.get(1)?),
+                            description: row.get(2)?,
+                            authors: list(row.get(3)?),
+                        })
+                    })
+                    .optional()
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    fn author_names(&self, keys: &[String]) -> Result<Vec<String>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT name FROM authors WHERE key = ?1")
+            .map_err(|e| e.to_string())?;
+        let mut names = vec![];
+        for key in keys {
+            if let Some(name) = statement
+                .query_row([key], |row| row.get::<_, String>(0))
+                .optional()
+                .map_err(|e| e.to_string())?
+            {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The first four-digit number of a free-text date like "March 1999".
+fn year(date: &str) -> Option<i64> {
+    date.split(|c: char| !c.is_ascii_digit())
+        .find(|part| part.len() == 4)
+        .and_then(|part| part.parse().ok())
+}
+
+fn format(physical_format: &str) -> Option<&'static str> {
+    let physical_format = physical_format.to_lowercase();
+    if physical_format.contains("audio") {
+        Some("audiobook")
+    } else if physical_format.contains("hardcover") || physical_format.contains("hardback") {
+        Some("hardcover")
+    } else if physical_format.contains("paperback") || physical_format.contains("mass market") {
+        Some("paperback")
+    } else if physical_format.contains("ebook") || physical_format.contains("electronic") {
+        Some("ebook")
+    } else {
+        None
+    }
+}
+
+/// Maps an edition to the JSON fields of a book, or `None` if it does not
+/// pass the filter or lacks a title or author. Missing details are taken
+/// from the edition's work.
+fn edition_fields(
+    edition: &Value,
+    staging: &Staging,
+    filter: &Filter,
+) -> Result<Option<Value>, String> {
+    let languages: Vec<String> = reference_keys(&edition["languages"], None)
+        .iter()
+        .map(|key| key.trim_start_matches("/languages/").to_string())
+        .collect();
+    if !filter.language(&languages) {
+        return Ok(None);
+    }
+    let work = match reference_keys(&edition["works"], None).first() {
+        Some(key) => staging.work(key)?,
+        None => None,
+    };
+    let mut subjects = strings(&edition["subjects"]);
+    if subjects.is_empty() {
+        subjects = work
+            .as_ref()
+            .map(|work| work.subjects.clone())
+            .unwrap_or_default();
+    }
+    if !filter.subject(&subjects) {
+        return Ok(None);
+    }
+    let Some(title) = text(&edition["title"]).or_else(|| work.as_ref()?.title.clone()) else {
+        return Ok(None);
+    };
+    let mut author_keys = reference_keys(&edition["authors"], None);
+    if author_keys.is_empty() {
+        author_keys = work
+            .as_ref()
+            .map(|work| work.authors.clone())
+            .unwrap_or_default();
+    }
+    let authors = staging.author_names(&author_keys)?;
+    if authors.is_empty() {
+        return Ok(None);
+    }
+
+    let mut fields = Map::new();
+    let Some(key) = edition["key"].as_str() else {
+        return Ok(None);
+    };
+    fields.insert(
+        "source_key".to_string(),
+        json!(format!("openlibrary:{}", key)),
+    );
+    fields.insert("title".to_string(), json!(title));
+    fields.insert("author".to_string(), json!(authors.join("; ")));
+    subjects.truncate(MAX_SUBJECTS);
+    if !subjects.is_empty() {
+        fields.insert("subjects".to_string(), json!(subjects));
+    }
+    let optional = [
+        (
+            "isbn",
+            strings(&edition["isbn_13"])
+                .into_iter()
+                .chain(strings(&edition["isbn_10"]))
+                .next()
+                .map(Value::from),
+        ),
+        (
+            "publisher",
+            strings(&edition["publishers"])
+                .into_iter()
+                .next()
+                .map(Value::from),
+        ),
+        (
+            "year",
+            edition["publish_date"]
+                .as_str()
+                .and_then(year)
+                .map(Value::from),
+        ),
+        (
+            "pages",
+            edition["number_of_pages"].as_i64().map(Value::from),
+        ),
+        ("language", languages.into_iter().next().map(Value::from)),
+        (
+            "format",
+            edition["physical_format"]
+                .as_str()
+                .and_then(format)
+                .map(Value::from),
+        ),
+        (
+            "cover",
+            edition["covers"]
+                .as_array()
+                .and_then(|covers| covers.iter().filter_map(Value::as_i64).find(|id| *id > 0))
+                .map(|id| Value::from(format!("{}/{}-L.jpg", COVER_URL, id))),
+        ),
+        (
+            "description",
+            text(&edition["description"])
+                .or_else(|| work.as_ref()?.description.clone())
+                .map(Value::from),
+        ),
+    ];
+    for (field, value) in optional {
+        if let Some(value) = value {
+            fields.insert(field.to_string(), value);
+        }
+    }
+    Ok(Some(Value::Object(fields)))
+}
+
+/// Streams the editions dump and calls `send` with batches of at most
+/// `batch_size` books and about `max_bytes` of JSON. Returns how many
+/// editions were read and how many passed the filter.
+pub fn load_editions(
+    dump: &Path,
+    staging: &Staging,
+    filter: &Filter,
+    batch_size: usize,
+    max_bytes: usize,
+    mut send: impl FnMut(&[Value]) -> Result<(), String>,
+) -> Result<(usize, usize), String> {
+    let mut read = 0;
+    let mut loaded = 0;
+    let mut batch: Vec<Value> = vec![];
+    let mut bytes = 0;
+    for_each_record(dump, |edition| {
+        read += 1;
+        let Some(fields) = edition_fields(&edition, staging, filter)? else {
+            return Ok(());
+        };
+        loaded += 1;
+        bytes += fields.to_string().len();
+        batch.push(fields);
+        if batch.len() >= batch_size || bytes >= max_bytes {
+            send(&batch)?;
+            batch.clear();
+            bytes = 0;
+        }
+        Ok(())
+    })?;
+    if !batch.is_empty() {
+        send(&batch)?;
+    }
+    Ok((read, loaded))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn staging(name: &str) -> Staging {
+        let path = env::temp_dir().join(format!("catalog-test-{}-{}.db", name, std::process::id()));
+        let staging = Staging::create(&path).unwrap();
+        staging
+            .connection
+            .execute_batch(
+                "INSERT INTO works VALUES ('/works/OL1W', 'Dune',
+                    '[\"Science fiction\", \"Deserts\"]', 'A desert planet.',
+                    '[\"/authors/OL1A\"]');
+                INSERT INTO authors VALUES ('/authors/OL1A', 'Frank Herbert');",
+            )
+            .unwrap();
+        staging
+    }
+
+    fn no_filter() -> Filter {
+        Filter {
+            languages: vec![],
+            subjects: vec![],
+        }
+    }
+
+    #[test]
+    fn reads_the_year_of_free_text_dates() {
+        assert_eq!(year("March 1999"), Some(1999));
+        assert_eq!(year("1965-08-01"), Some(1965));
+        assert_eq!(year("c. 19th century"), None);
+    }
+
+    #[test]
+    fn maps_editions_to_books_with_details_from_their_work() {
+        let staging = staging("editions");
+        let edition = json!({
+            "key": "/books/OL1M",
+            "works": [{ "key": "/works/OL1W" }],
+            "isbn_10": ["0441172717"],
+            "publishers": ["Ace Books"],
+            "publish_date": "June 1990",
+            "number_of_pages": 535,
+            "languages": [{ "key": "/languages/eng" }],
+            "physical_format": "Mass Market Paperback",
+            "covers": [-1, 12345],
+        });
+        let fields = edition_fields(&edition, &staging, &no_filter())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            fields,
+            json!({
+                "source_key": "openlibrary:/books/OL1M",
+                "title": "Dune",
+                "author": "Frank Herbert",
+                "subjects": ["Science fiction", "Deserts"],
+                "isbn": "0441172717",
+                "publisher": "Ace Books",
+                "year": 1990,
+                "pages": 535,
+                "language": "eng",
+                "format": "paperback",
+                "cover": "https://covers.openlibrary.org/b/id/12345-L.jpg",
+                "description": "A desert planet.",
+            })
+        );
+    }
+
+    #[test]
+    fn skips_editions_outside_the_filter_or_without_an_author() {
+        let staging = staging("filters");
+        let edition = json!({
+            "key": "/books/OL1M",
+            "works": [{ "key": "/works/OL1W" }],
+            "languages": [{ "key": "/languages/eng" }],
+        });
+        let french = Filter {
+            languages: vec!["fre".to_string()],
+            subjects: vec![],
+        };
+        let fantasy = Filter {
+            languages: vec![],
+            subjects: vec!["fantasy".to_string()],
+        };
+        assert!(edition_fields(&edition, &staging, &french)
+            .unwrap()
+            .is_none());
+        assert!(edition_fields(&edition, &staging, &fantasy)
+            .unwrap()
+            .is_none());
+        let anonymous = json!({ "key": "/books/OL2M", "title": "Anonymous" });
+        assert!(edition_fields(&anonymous, &staging, &no_filter())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,68 +1,566 @@
+mod authors;
+mod challenges;
+mod clubs;
+mod duplicates;
+mod erasure;
+mod genres;
+mod idempotency;
+mod import;
+mod library;
+mod lists;
+mod metadata;
+mod patch;
+mod revisions;
+mod seed;
+mod series;
+mod shelves;
+mod takeout;
+mod trash;
+mod works;
+
+use std::time::Duration;
+
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use neo4rs::*;
-use rocket::FromForm;
+use rocket::{FromForm, FromFormField};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub use authors::{Author, AuthorUpdate, AuthoredBook, Contributor};
+pub use challenges::{Challenge, NewChallenge};
+pub use clubs::{Club, NewClub, NewPost, NewSection, Post, Section};
+pub use duplicates::DuplicateCandidate;
+pub use genres::{Genre, NewGenre};
+pub use import::ImportError;
+pub use library::{BookIndex, LibraryEntry, Resolution};
+pub use lists::{NewReadingList, ReadingList};
+pub use metadata::FieldError;
+pub use patch::PatchError;
+pub use revisions::Revision;
+pub use seed::SeedReport;
+pub use series::{Series, SeriesDetail, SeriesEntry};
+pub use takeout::PersonalData;
+pub use trash::Trashed;
+pub use works::{EditionFormat, Work};
+
+use genres::{genre_filter, with_genre_filter};
+use revisions::RevisionAction;
+use series::reading_order_filter;
+use works::has_read_work;
+
+use crate::normalize::author_key;
+
+/// An edition of a [`Work`]. Editions of the same work count as one book for
+/// recommendations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Book {
+    #[serde(default)]
     id: String,
     title: String,
     author: String,
     genre: String,
     cover: String,
+    #[serde(default)]
+    work_id: Option<String>,
+    #[serde(default)]
+    isbn: Option<String>,
+    #[serde(default)]
+    format: Option<EditionFormat>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    publisher: Option<String>,
+    #[serde(default)]
+    year: Option<i64>,
+    #[serde(default)]
+    pages: Option<i64>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    subjects: Vec<String>,
+    #[serde(default)]
+    contributors: Vec<Contributor>,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    series: Vec<SeriesEntry>,
+    /// Incremented on every edit and sent as the `ETag` of the book.
+    #[serde(skip_deserializing)]
+    version: i64,
 }
 
 impl Book {
-    pub fn new(id: String, title: String, author: String, genre: String, cover: String) -> Self {
+    pub fn new(
+        id: String,
+        title: String,
+        author: String,
+        genre: String,
+        cover: String,
+        contributors: Vec<Contributor>,
+        genres: Vec<String>,
+    ) -> Self {
         Self {
             id,
             title,
             author,
             genre,
             cover,
+            contributors,
+            genres,
+            series: vec![],
+            work_id: None,
+            isbn: None,
+            format: None,
+            language: None,
+            publisher: None,
+            year: None,
+            pages: None,
+            description: None,
+            subjects: vec![],
+            version: 0,
         }
     }
 
-    pub fn id(&self) -> &String {
-        &self.id
+    pub fn isbn(&self) -> Option<&str> {
+        self.isbn.as_deref()
     }
 
-    pub fn title(&self) -> &String {
-        &self.title
+    pub fn format(&self) -> Option<EditionFormat> {
+        self.format
     }
 
-    pub fn author(&self) -> &String {
-        &self.author
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
     }
 
-    pub fn genre(&self) -> &String {
-        &self.genre
+    pub fn version(&self) -> i64 {
+        self.version
     }
+}
 
-    pub fn cover(&self) -> &String {
-        &self.cover
+/// Query parameters narrowing down `GET /books`. `genre` is a genre id or
+/// name and also matches its subgenres; `author` is an author id or name;
+/// `q` searches titles and authors.
+#[derive(Debug, Default, FromForm)]
+pub struct BookFilter {
+    genre: Option<String>,
+    author: Option<String>,
+    q: Option<String>,
+}
+
+/// A book with the time it was added to the catalog.
+#[derive(Debug, Serialize)]
+pub struct Arrival {
+    #[serde(flatten)]
+    book: Book,
+    added_at: String,
+}
+
+/// Books read one at a time as the database returns them, for responses too
+/// large to collect first.
+pub struct BookStream(BoxStream<'static, Result<Row>>);
+
+impl BookStream {
+    /// The next book, `None` after the last one, or an error if the
+    /// database failed before the last book was read.
+    pub async fn next(&mut self) -> Option<Result<Book>> {
+        match self.0.next().await? {
+            Ok(row) => Some(Ok(book_from_row(&row, "b"))),
+            Err(e) => {
+                println!("Could not stream books: {}", e);
+                Some(Err(e))
+            }
+        }
     }
 }
 
+/// The query behind [`DatabaseService::get_all_books`],
+/// [`DatabaseService::get_books_window`] and [`DatabaseService::stream_books`],
+/// optionally skipping `$skip` books and returning at most `$limit`.
+fn books_query(filter: &BookFilter, windowed: bool) -> Query {
+    with_genre_filter(
+        query(&format!(
+            "MATCH (b:Book)
+            WHERE {}
+                AND ($q IS NULL
+                    OR toLower(b.title) CONTAINS toLower($q)
+                    OR toLower(b.author) CONTAINS toLower($q))
+                AND ($author IS NULL OR EXISTS {{
+                    MATCH (b)<-[:WROTE]-(a:Author)
+                    WHERE a.id = $author OR a.key = $author_key OR $author_key IN a.aliases
+                }})
+            RETURN {}
+            ORDER BY b.title, b.id
+            {}",
+            genre_filter("b"),
+            book_fields("b"),
+            match windowed {
+                true => "SKIP $skip LIMIT $limit",
+                false => "",
+            }
+        )),
+        filter.genre.as_deref(),
+    )
+    .param("q", filter.q.as_deref())
+    .param("author", filter.author.as_deref())
+    .param("author_key", filter.author.as_deref().map(author_key))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     id: i32,
     name: String,
+    #[serde(default)]
+    followers: i64,
+    #[serde(default)]
+    following: i64,
+    #[serde(default)]
+    requires_follow_approval: bool,
+    /// Incremented on every edit and sent as the `ETag` of the user.
+    #[serde(skip_deserializing)]
+    version: i64,
 }
 
 impl User {
-    pub fn new(id: i32, name: String) -> Self {
-        Self { id, name }
-    }
-
-    pub fn id(&self) -> i32 {
-        self.id
+    pub fn new(
+        id: i32,
+        name: String,
+        followers: i64,
+        following: i64,
+        requires_follow_approval: bool,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            followers,
+            following,
+            requires_follow_approval,
+            version: 0,
+        }
     }
 
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    requires_follow_approval: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FollowStatus {
+    Following,
+    Requested,
+}
+
+/// Which signal `GET /users/<id>/recommendations` ranks books by.
+#[derive(Debug, Default, FromFormField)]
+pub enum RecommendationMode {
+    /// Books read by anyone who shares a book with the user.
+    #[default]
+    #[field(value = "co-readers")]
+    CoReaders,
+    /// Books read or rated by people the user follows.
+    #[field(value = "following")]
+    Following,
 }
 
+#[derive(Debug, Serialize)]
+pub struct Recommendation {
+    #[serde(flatten)]
+    book: Book,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl Recommendation {
+    pub fn new(book: Book, reason: Option<String>) -> Self {
+        Self { book, reason }
+    }
+}
+
+/// Builds the attribution shown next to a social recommendation, e.g.
+/// "read by Alex and 2 others you follow". `readers` is ordered by how
+/// strongly each of them engaged with the book.
+fn attribution(readers: &[String]) -> String {
+    match readers {
+        [] => String::new(),
+        [first] => format!("read by {}, who you follow", first),
+        [first, _] => format!("read by {} and 1 other you follow", first),
+        [first, rest @ ..] => format!("read by {} and {} others you follow", first, rest.len()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum EventKind {
+    #[serde(rename = "finished_book")]
+    Finished,
+    #[serde(rename = "rated_book")]
+    Rated,
+    #[serde(rename = "reviewed_book")]
+    Reviewed,
+    #[serde(rename = "created_list")]
+    CreatedList,
+    #[serde(rename = "started_challenge")]
+    StartedChallenge,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Finished => "finished_book",
+            EventKind::Rated => "rated_book",
+            EventKind::Reviewed => "reviewed_book",
+            EventKind::CreatedList => "created_list",
+            EventKind::StartedChallenge => "started_challenge",
+        }
+    }
+}
+
+/// The reading list a `created_list` event is about.
+#[derive(Debug, Serialize)]
+pub struct EventList {
+    id: String,
+    name: String,
+}
+
+/// The reading challenge a `started_challenge` event is about.
+#[derive(Debug, Serialize)]
+pub struct EventChallenge {
+    id: String,
+    year: i64,
+    goal: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedEvent {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    created_at: String,
+    actor_id: i32,
+    actor_name: String,
+    book: Option<Book>,
+    rating: Option<i64>,
+    review: Option<String>,
+    list: Option<EventList>,
+    challenge: Option<EventChallenge>,
+    #[serde(skip)]
+    cursor: String,
+}
+
+impl FeedEvent {
+    fn from_row(row: &Row) -> Self {
+        let id: String = row.get("e.id").unwrap_or_default();
+        let created_millis: i64 = row.get("created_millis").unwrap_or_default();
+        let book = row
+            .get::<String>("b.id")
+            .ok()
+            .map(|_| book_from_row(row, "b"));
+        Self {
+            cursor: format!("{}_{}", created_millis, id),
+            id,
+            kind: row.get("e.type").unwrap_or_default(),
+            created_at: row.get("created_at").unwrap_or_default(),
+            actor_id: row.get("actor.id").unwrap_or_default(),
+            actor_name: row.get("actor.name").unwrap_or_default(),
+            book,
+            rating: row.get("e.rating").ok(),
+            review: row.get("e.review").ok(),
+            list: row.get("l.id").ok().map(|id| EventList {
+                id,
+                name: row.get("l.name").unwrap_or_default(),
+            }),
+            challenge: row.get("c.id").ok().map(|id| EventChallenge {
+                id,
+                year: row.get("c.year").unwrap_or_default(),
+                goal: row.get("c.goal").unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+/// Where a page of an activity feed starts: just after the event with this
+/// creation time and id. Written as `<millis>_<event id>`.
+#[derive(Debug)]
+pub struct FeedCursor {
+    before: i64,
+    before_id: String,
+}
+
+impl FeedCursor {
+    /// Reads a cursor handed out as `next_cursor`, or `None` if it is not one.
+    pub fn parse(cursor: &str) -> Option<Self> {
+        let (millis, event_id) = cursor.split_once('_')?;
+        if event_id.is_empty() {
+            return None;
+        }
+        Some(FeedCursor {
+            before: millis.parse().ok()?,
+            before_id: event_id.to_string(),
+        })
+    }
+}
+
+/// One page of an activity feed. Pass `next_cursor` back as `cursor` to
+/// fetch the following page; it is absent on the last page.
+#[derive(Debug, Serialize)]
+pub struct FeedPage {
+    items: Vec<FeedEvent>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeriodCount {
+    period: String,
+    books: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Share {
+    name: String,
+    books: i64,
+}
+
+/// How often the statistics comparing readers with the community are
+/// recomputed.
+const COMMUNITY_STATS_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Reading statistics derived from a user's `HAS_READ` edges.
+///
+/// `diversity` is the Shannon entropy of the user's genre distribution,
+/// `community_diversity` the same measure averaged over all readers, as of
+/// the last [`DatabaseService::refresh_community_stats`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadingStats {
+    books_read: i64,
+    books_per_month: Vec<PeriodCount>,
+    books_per_year: Vec<PeriodCount>,
+    genres: Vec<Share>,
+    authors: Vec<Share>,
+    average_rating: Option<f64>,
+    pages_read: i64,
+    longest_streak: i64,
+    diversity: f64,
+    community_diversity: f64,
+}
+
+/// Appended to a write that has the acting user `u`, the book `b` and the
+/// `HAS_READ` edge `r` in scope. Records the event once and fans it out to
+/// every current follower, so reading a feed never has to walk the follow
+/// graph. Expects an `$event_type` parameter.
+const PUBLISH_EVENT: &str = "CREATE (u)-[:PERFORMED]->(e:Event {
+        id: randomUUID(),
+        type: $event_type,
+        created_at: datetime(),
+        rating: r.rating,
+        review: r.review
+    })-[:ABOUT]->(b)
+    WITH u, e
+    CALL {
+        WITH u, e
+        MATCH (f:User)-[:FOLLOWS]->(u)
+        CREATE (f)-[:FEED_ITEM]->(e)
+    }";
+
+/// Like [`PUBLISH_EVENT`], for an event about the reading list or challenge
+/// bound to `about` rather than a read book.
+const PUBLISH_EVENT_ABOUT: &str = "CREATE (u)-[:PERFORMED]->(e:Event {
+        id: randomUUID(),
+        type: $event_type,
+        created_at: datetime()
+    })-[:ABOUT]->(about)
+    WITH u, e, about
+    CALL {
+        WITH u, e
+        MATCH (f:User)-[:FOLLOWS]->(u)
+        CREATE (f)-[:FEED_ITEM]->(e)
+    }";
+
+/// The `RETURN` columns for the book bound to `var`, read back by
+/// [`book_from_row`].
+fn book_fields(var: &str) -> String {
+    format!(
+        "{v}.id, {v}.title, {v}.author, {v}.genre, {v}.cover,
+        {v}.isbn, {v}.format, {v}.language, {v}.publisher, {v}.year,
+        {v}.pages, {v}.description, coalesce({v}.subjects, []) AS {v}_subjects,
+        coalesce({v}.version, 1) AS {v}_version,
+        head([({v})-[:EDITION_OF]->(edition_of:Work) | edition_of.id]) AS {v}_work_id,
+        [({v})<-[credit:WROTE]-(contributor:Author) | {{
+            author_id: contributor.id,
+            name: contributor.name,
+            role: credit.role,
+            position: credit.position
+        }}]
+            AS {v}_contributors,
+        [({v})-[:IN_GENRE]->(tagged_genre:Genre) | tagged_genre.name] AS {v}_genres,
+        [({v})-[part:PART_OF]->(in_series:Series) | {{
+            series_id: in_series.id,
+            name: in_series.name,
+            volume: part.volume
+        }}]
+            AS {v}_series",
+        v = var
+    )
+}
+
+/// Reads the columns returned by [`book_fields`] for the book bound to
+/// `var`, e.g. `rec.title` for `var = "rec"`.
+fn book_from_row(row: &Row, var: &str) -> Book {
+    let mut contributors: Vec<Contributor> = row
+        .get(&format!("{}_contributors", var))
+        .unwrap_or_default();
+    contributors.sort_by_key(|c| c.position);
+    Book {
+        series: row.get(&format!("{}_series", var)).unwrap_or_default(),
+        work_id: row.get(&format!("{}_work_id", var)).ok(),
+        isbn: row.get(&format!("{}.isbn", var)).ok(),
+        format: row.get(&format!("{}.format", var)).ok(),
+        language: row.get(&format!("{}.language", var)).ok(),
+        publisher: row.get(&format!("{}.publisher", var)).ok(),
+        year: row.get(&format!("{}.year", var)).ok(),
+        pages: row.get(&format!("{}.pages", var)).ok(),
+        description: row.get(&format!("{}.description", var)).ok(),
+        subjects: row.get(&format!("{}_subjects", var)).unwrap_or_default(),
+        version: row.get(&format!("{}_version", var)).unwrap_or_default(),
+        ..Book::new(
+            row.get(&format!("{}.id", var)).unwrap_or_default(),
+            row.get(&format!("{}.title", var)).unwrap_or_default(),
+            row.get(&format!("{}.author", var)).unwrap_or_default(),
+            row.get(&format!("{}.genre", var)).unwrap_or_default(),
+            row.get(&format!("{}.cover", var)).unwrap_or_default(),
+            contributors,
+            row.get(&format!("{}_genres", var)).unwrap_or_default(),
+        )
+    }
+}
+
+const USER_FIELDS: &str = "u.id, u.name,
+    COUNT { (u)<-[:FOLLOWS]-(:User) } AS followers,
+    COUNT { (u)-[:FOLLOWS]->(:User) } AS following,
+    coalesce(u.requires_follow_approval, false) AS requires_follow_approval,
+    coalesce(u.version, 1) AS version";
+
+fn user_from_row(row: &Row) -> User {
+    User {
+        version: row.get("version").unwrap_or_default(),
+        ..User::new(
+            row.get("u.id").unwrap_or_default(),
+            row.get("u.name").unwrap_or_default(),
+            row.get("followers").unwrap_or_default(),
+            row.get("following").unwrap_or_default(),
+            row.get("requires_follow_approval").unwrap_or_default(),
+        )
+    }
+}
+
+#[derive(Clone)]
 pub struct DatabaseService {
     graph: Graph,
 }
@@ -82,68 +580,233 @@ impl DatabaseService {
         Ok(Self { graph })
     }
 
-    pub async fn get_all_books(&self) -> Vec<Book> {
+    /// Creates constraints and migrates data written by earlier versions.
+    /// Safe to run on every start.
+    pub async fn migrate(&self) -> Result<()> {
+        self.migrate_book_ids().await?;
+        for constraint in [
+            "CREATE CONSTRAINT book_id IF NOT EXISTS FOR (b:Book) REQUIRE b.id IS UNIQUE",
+            "CREATE CONSTRAINT book_redirect_id IF NOT EXISTS FOR (r:BookRedirect) REQUIRE r.id IS UNIQUE",
+            "CREATE CONSTRAINT idempotency_key IF NOT EXISTS FOR (k:IdempotencyKey) REQUIRE k.key IS UNIQUE",
+            "CREATE CONSTRAINT author_id IF NOT EXISTS FOR (a:Author) REQUIRE a.id IS UNIQUE",
+            "CREATE CONSTRAINT author_key IF NOT EXISTS FOR (a:Author) REQUIRE a.key IS UNIQUE",
+            "CREATE CONSTRAINT genre_id IF NOT EXISTS FOR (g:Genre) REQUIRE g.id IS UNIQUE",
+            "CREATE CONSTRAINT genre_key IF NOT EXISTS FOR (g:Genre) REQUIRE g.key IS UNIQUE",
+            "CREATE CONSTRAINT series_id IF NOT EXISTS FOR (s:Series) REQUIRE s.id IS UNIQUE",
+            "CREATE CONSTRAINT series_key IF NOT EXISTS FOR (s:Series) REQUIRE s.key IS UNIQUE",
+            "CREATE CONSTRAINT work_id IF NOT EXISTS FOR (w:Work) REQUIRE w.id IS UNIQUE",
+            "CREATE CONSTRAINT work_key IF NOT EXISTS FOR (w:Work) REQUIRE w.key IS UNIQUE",
+            "CREATE CONSTRAINT source_record_key IF NOT EXISTS FOR (s:SourceRecord) REQUIRE s.key IS UNIQUE",
+        ] {
+            self.graph.run(query(constraint)).await.map_err(|e| {
+                println!("Could not create constraint: {}", e);
+                e
+            })?;
+        }
+        self.migrate_authors().await?;
+        self.migrate_genres().await?;
+        self.migrate_works().await
+    }
+
+    /// Gives books without an id, or sharing their id with another book, a
+    /// fresh one so the uniqueness constraint on `Book.id` can be created.
+    async fn migrate_book_ids(&self) -> Result<()> {
+        for statement in [
+            "MATCH (b:Book) WHERE coalesce(b.id, '') = '' SET b.id = randomUUID()",
+            "MATCH (b:Book)
+            WITH b.id AS id, collect(b) AS books
+            WHERE size(books) > 1
+            UNWIND tail(books) AS duplicate
+            SET duplicate.id = randomUUID()",
+        ] {
+            self.graph.run(query(statement)).await.map_err(|e| {
+                println!("Could not migrate book ids: {}", e);
+                e
+            })?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_all_books(&self, filter: &BookFilter) -> Vec<Book> {
         let mut result = self
             .graph
-            .execute(query(
-                "MATCH (b:Book) RETURN b.id, b.title, b.author, b.genre, b.cover",
-            ))
+            .execute(books_query(filter, false))
             .await
             .map_err(|e| -> Vec<Book> {
                 println!("Could not fetch books: {}", e);
-                return vec![];
+                vec![]
             })
             .unwrap();
 
         let mut books: Vec<Book> = vec![];
         while let Ok(Some(row)) = result.next().await {
-            books.push(Book::new(
-                row.get("b.id").unwrap_or_default(),
-                row.get("b.title").unwrap_or_default(),
-                row.get("b.author").unwrap_or_default(),
-                row.get("b.genre").unwrap_or_default(),
-                row.get("b.cover").unwrap_or_default(),
-            ));
+            books.push(book_from_row(&row, "b"));
         }
         books
     }
 
+    /// Returns the books `get_all_books` would, skipping the first `skip`
+    /// and returning at most `limit`.
+    pub async fn get_books_window(
+        &self,
+        filter: &BookFilter,
+        skip: i64,
+        limit: i64,
+    ) -> Result<Vec<Book>> {
+        let mut result = self
+            .graph
+            .execute(
+                books_query(filter, true)
+                    .param("skip", skip)
+                    .param("limit", limit),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch books: {}", e);
+                e
+            })?;
+
+        let mut books: Vec<Book> = vec![];
+        while let Some(row) = result.next().await? {
+            books.push(book_from_row(&row, "b"));
+        }
+        Ok(books)
+    }
+
+    /// Returns the books added to the catalog most recently, newest first.
+    pub async fn get_new_books(&self, limit: i64) -> Result<Vec<Arrival>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (b:Book)
+                    WHERE b.created_at IS NOT NULL
+                    RETURN {}, toString(b.created_at) AS added_at
+                    ORDER BY b.created_at DESC
+                    LIMIT $limit",
+                    book_fields("b")
+                ))
+                .param("limit", limit),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch new books: {}", e);
+                e
+            })?;
+
+        let mut books: Vec<Arrival> = vec![];
+        while let Some(row) = result.next().await? {
+            books.push(Arrival {
+                book: book_from_row(&row, "b"),
+                added_at: row.get("added_at").unwrap_or_default(),
+            });
+        }
+        Ok(books)
+    }
+
+    pub async fn stream_books(&self, filter: &BookFilter) -> Result<BookStream> {
+        self.graph
+            .execute(books_query(filter, false))
+            .await
+            .map(|rows| BookStream(rows.into_stream().into_stream().boxed()))
+            .map_err(|e| {
+                println!("Could not fetch books: {}", e);
+                e
+            })
+    }
+
+    /// Returns the book with the given id, following the redirect left behind
+    /// when a book was merged into another.
     pub async fn get_book(&self, id: &str) -> Option<Book> {
         let mut result = self
             .graph
             .execute(
-                query("MATCH (b:Book {id: $id}) RETURN b.id, b.title, b.author, b.genre, b.cover")
-                    .param("id", id),
+                query(&format!(
+                    "CALL {{
+                        MATCH (b:Book {{id: $id}}) RETURN b
+                        UNION
+                        MATCH (:BookRedirect {{id: $id}})-[:REDIRECTS_TO]->(b:Book) RETURN b
+                    }}
+                    RETURN {}",
+                    book_fields("b")
+                ))
+                .param("id", id),
             )
             .await
             .map_err(|e| -> Option<Book> {
                 println!("Could not fetch book with id '{}': {}", id, e);
-                return None;
+                None
             })
             .unwrap();
 
-        while let Ok(Some(row)) = result.next().await {
-            return Some(Book::new(
-                row.get("b.id").unwrap_or_default(),
-                row.get("b.title").unwrap_or_default(),
-                row.get("b.author").unwrap_or_default(),
-                row.get("b.genre").unwrap_or_default(),
-                row.get("b.cover").unwrap_or_default(),
-            ));
+        if let Ok(Some(row)) = result.next().await {
+            return Some(book_from_row(&row, "b"));
         }
         None
     }
 
-    pub async fn add_book(&self, book: &Book) -> Option<String> {
+    /// The current version of a book, or `None` if it does not exist.
+    pub async fn book_version(&self, id: &str) -> Result<Option<i64>> {
+        let mut result = self
+            .graph
+            .execute(
+                query("MATCH (b:Book {id: $id}) RETURN coalesce(b.version, 1) AS version")
+                    .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch version of book with id '{}': {}", id, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("version").ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates a book under a new server-generated id and returns that id. The
+    /// client-supplied `id` is ignored. If `idempotency_key` was already used
+    /// for a book, that book's id is returned and nothing is created.
+    pub async fn add_book(
+        &self,
+        book: &Book,
+        idempotency_key: Option<&str>,
+        editor: Option<i32>,
+    ) -> Option<String> {
+        if let Some(key) = idempotency_key {
+            match self.find_idempotent_book(key).await {
+                Ok(Some(id)) => return Some(id),
+                Ok(None) => {}
+                Err(_) => return None,
+            }
+        }
+
         let mut result = self
             .graph
             .execute(
-                query("CREATE (b:Book {id: $id, title: $title, author: $author, genre: $genre, cover: $cover}) RETURN elementId(b) AS id")
-                    .param("id", book.id.as_str())
-                    .param("title", book.title.as_str())
-                    .param("author", book.author.as_str())
-                    .param("genre", book.genre.as_str())
-                    .param("cover", book.cover.as_str())
+                query(
+                    "CREATE (b:Book {id: randomUUID(), title: $title, author: $author, genre: $genre,
+                        cover: $cover, isbn: $isbn, format: $format, language: $language,
+                        publisher: $publisher, year: $year, pages: $pages, description: $description,
+                        subjects: $subjects, created_at: datetime(), version: 1})
+                    FOREACH (key IN CASE WHEN $idempotency_key IS NULL THEN [] ELSE [$idempotency_key] END |
+                        CREATE (:IdempotencyKey {key: key, created_at: datetime()})-[:CREATED]->(b))
+                    RETURN b.id AS id",
+                )
+                .param("idempotency_key", idempotency_key)
+                .param("title", book.title.as_str())
+                .param("author", book.credited_author())
+                .param("genre", book.primary_genre())
+                .param("cover", book.cover.as_str())
+                .param("isbn", book.isbn.as_deref())
+                .param("format", book.format.map(|f| f.as_str()))
+                .param("language", book.language.as_deref())
+                .param("publisher", book.publisher.as_deref())
+                .param("year", book.year)
+                .param("pages", book.pages)
+                .param("description", book.description.as_deref())
+                .param("subjects", book.subjects.clone()),
             )
             .await
             .map_err(|e| -> Option<String> {
@@ -152,63 +815,107 @@ impl DatabaseService {
             })
             .unwrap();
 
-        while let Ok(Some(row)) = result.next().await {
-            return row.get("id").ok();
+        if let Ok(Some(row)) = result.next().await {
+            let id: String = row.get("id").ok()?;
+            if self.link_contributors(&id, book).await.is_err()
+                || self.link_genres(&id, book).await.is_err()
+                || self.link_series(&id, book).await.is_err()
+                || self.link_work(&id, book).await.is_err()
+                || self
+                    .record_revision(&id, RevisionAction::Created, None, editor, None, None)
+                    .await
+                    .is_err()
+            {
+                return None;
+            }
+            return Some(id);
         }
         None
     }
 
-    pub async fn edit_book(&self, id: &str, book: &Book) -> Result<()> {
-        let _ = self
+    /// Updates a book and records the change as a revision made by `editor`.
+    /// With `expected`, the update only goes through if the book is still at
+    /// that version; returns `false` otherwise.
+    pub async fn edit_book(
+        &self,
+        id: &str,
+        book: &Book,
+        editor: Option<i32>,
+        expected: Option<i64>,
+    ) -> Result<bool> {
+        let before = self.get_book(id).await;
+        if !self.write_book(id, book, expected).await? {
+            return Ok(false);
+        }
+        self.record_revision(
+            id,
+            RevisionAction::Updated,
+            before.as_ref(),
+            editor,
+            None,
+            None,
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn write_book(&self, id: &str, book: &Book, expected: Option<i64>) -> Result<bool> {
+        let mut result = self
             .graph
-            .run(
+            .execute(
                 query(
-                    "MATCH (b:Book {id: $id}) 
-                    SET b.title = $title, b.author = $author, b.genre = $genre, b.cover = $cover",
+                    "MATCH (b:Book {id: $id})
+                    WHERE $expected IS NULL OR coalesce(b.version, 1) = $expected
+                    SET b.version = coalesce(b.version, 1) + 1, b.title = $title, b.author = $author, b.genre = $genre, b.cover = $cover,
+                        b.isbn = $isbn, b.format = $format, b.language = $language,
+                        b.publisher = $publisher, b.year = $year, b.pages = $pages,
+                        b.description = $description, b.subjects = $subjects
+                    RETURN b.id",
                 )
                 .param("id", id)
+                .param("expected", expected)
                 .param("title", book.title.as_str())
-                .param("author", book.author.as_str())
-                .param("genre", book.genre.as_str())
-                .param("cover", book.cover.as_str()),
+                .param("author", book.credited_author())
+                .param("genre", book.primary_genre())
+                .param("cover", book.cover.as_str())
+                .param("isbn", book.isbn.as_deref())
+                .param("format", book.format.map(|f| f.as_str()))
+                .param("language", book.language.as_deref())
+                .param("publisher", book.publisher.as_deref())
+                .param("year", book.year)
+                .param("pages", book.pages)
+                .param("description", book.description.as_deref())
+                .param("subjects", book.subjects.clone()),
             )
             .await
             .map_err(|e| {
                 println!("Could not update book with id '{}': {}", id, e);
                 e
             })?;
-        Ok(())
-    }
-
-    pub async fn delete_book(&self, id: &str) -> Result<()> {
-        let _ = self
-            .graph
-            .run(query("MATCH (b:Book {id: $id}) DETACH DELETE b").param("id", id))
-            .await
-            .map_err(|e| {
-                println!("Could not delete book with id '{}': {}", id, e);
-                e
-            })?;
-        Ok(())
+        if result.next().await?.is_none() {
+            return Ok(false);
+        }
+        self.link_contributors(id, book).await?;
+        self.link_genres(id, book).await?;
+        self.link_series(id, book).await?;
+        self.link_work(id, book).await?;
+        Ok(true)
     }
 
     pub async fn get_all_users(&self) -> Vec<User> {
         let mut result = self
             .graph
-            .execute(query("MATCH (u:User) RETURN u.id, u.name"))
+            .execute(query(&format!("MATCH (u:User) RETURN {}", USER_FIELDS)))
             .await
             .map_err(|e| -> Vec<User> {
                 println!("Could not fetch users: {}", e);
-                return vec![];
+                vec![]
             })
             .unwrap();
 
         let mut users: Vec<User> = vec![];
         while let Ok(Some(row)) = result.next().await {
-            users.push(User::new(
-                row.get("u.id").unwrap_or_default(),
-                row.get("u.name").unwrap_or_default(),
-            ));
+            users.push(user_from_row(&row));
         }
         users
     }
@@ -216,30 +923,55 @@ impl DatabaseService {
     pub async fn get_user(&self, id: i32) -> Option<User> {
         let mut result = self
             .graph
-            .execute(query("MATCH (u:User {id: $id}) RETURN u.id, u.name").param("id", id))
+            .execute(
+                query(&format!(
+                    "MATCH (u:User {{id: $id}}) RETURN {}",
+                    USER_FIELDS
+                ))
+                .param("id", id),
+            )
             .await
             .map_err(|e| -> Option<User> {
                 println!("Could not fetch user with id '{}': {}", id, e);
-                return None;
+                None
             })
             .unwrap();
 
-        while let Ok(Some(row)) = result.next().await {
-            return Some(User::new(
-                row.get("u.id").unwrap_or_default(),
-                row.get("u.name").unwrap_or_default(),
-            ));
+        if let Ok(Some(row)) = result.next().await {
+            return Some(user_from_row(&row));
         }
         None
     }
 
+    /// The current version of a user, or `None` if it does not exist.
+    pub async fn user_version(&self, id: i32) -> Result<Option<i64>> {
+        let mut result = self
+            .graph
+            .execute(
+                query("MATCH (u:User {id: $id}) RETURN coalesce(u.version, 1) AS version")
+                    .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch version of user with id '{}': {}", id, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("version").ok()),
+            None => Ok(None),
+        }
+    }
+
     pub async fn add_user(&self, user: &User) -> Option<String> {
         let mut result = self
             .graph
             .execute(
-                query("CREATE (u:User {id: $id, name: $name}) RETURN elementId(u) AS id")
-                    .param("id", user.id)
-                    .param("name", user.name.as_str()),
+                query(
+                    "CREATE (u:User {id: $id, name: $name, version: 1}) RETURN elementId(u) AS id",
+                )
+                .param("id", user.id)
+                .param("name", user.name.as_str()),
             )
             .await
             .map_err(|e| -> Option<String> {
@@ -248,35 +980,224 @@ impl DatabaseService {
             })
             .unwrap();
 
-        while let Ok(Some(row)) = result.next().await {
+        if let Ok(Some(row)) = result.next().await {
             return row.get("id").ok();
         }
         None
     }
 
-    pub async fn edit_user(&self, id: i32, user: &User) -> Result<()> {
-        let _ = self
+    /// Renames a user if they are still at version `expected`; returns
+    /// `false` otherwise.
+    pub async fn edit_user(&self, id: i32, user: &User, expected: i64) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id})
+                    WHERE coalesce(u.version, 1) = $expected
+                    SET u.name = $name, u.version = coalesce(u.version, 1) + 1
+                    RETURN u.id",
+                )
+                .param("id", id)
+                .param("expected", expected)
+                .param("name", user.name.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not update user with id '{}': {}", id, e);
+                e
+            })?;
+        Ok(result.next().await?.is_some())
+    }
+
+    pub async fn follow_user(&self, id: i32, target_id: i32) -> Result<Option<FollowStatus>> {
+        let mut result = self
             .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id}), (t:User {id: $target_id})
+                    WHERE u <> t
+                    RETURN coalesce(t.requires_follow_approval, false)
+                        AND NOT (u)-[:FOLLOWS]->(t) AS needs_approval",
+                )
+                .param("id", id)
+                .param("target_id", target_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not look up user with id '{}' to follow: {}",
+                    target_id, e
+                );
+                e
+            })?;
+        let needs_approval: bool = match result.next().await? {
+            Some(row) => row.get("needs_approval").unwrap_or_default(),
+            None => return Ok(None),
+        };
+
+        let (statement, status) = if needs_approval {
+            (
+                "MATCH (u:User {id: $id}), (t:User {id: $target_id})
+                MERGE (u)-[r:REQUESTED_FOLLOW]->(t) ON CREATE SET r.requested_at = datetime()",
+                FollowStatus::Requested,
+            )
+        } else {
+            (
+                "MATCH (u:User {id: $id}), (t:User {id: $target_id})
+                MERGE (u)-[r:FOLLOWS]->(t) ON CREATE SET r.since = datetime()",
+                FollowStatus::Following,
+            )
+        };
+        self.graph
             .run(
-                query("MATCH (u:User {id: $id}) SET u.name = $name")
+                query(statement)
                     .param("id", id)
-                    .param("name", user.name.as_str()),
+                    .param("target_id", target_id),
             )
             .await
             .map_err(|e| {
-                println!("Could not update user with id '{}': {}", id, e);
+                println!(
+                    "Could not follow user with id '{}' as user with id '{}': {}",
+                    target_id, id, e
+                );
+                e
+            })?;
+        Ok(Some(status))
+    }
+
+    pub async fn unfollow_user(&self, id: i32, target_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (u:User {id: $id})-[r:FOLLOWS|REQUESTED_FOLLOW]->(t:User {id: $target_id})
+                    DELETE r",
+                )
+                .param("id", id)
+                .param("target_id", target_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not unfollow user with id '{}' as user with id '{}': {}",
+                    target_id, id, e
+                );
                 e
             })?;
         Ok(())
     }
 
-    pub async fn delete_user(&self, id: i32) -> Result<()> {
-        let _ = self
+    pub async fn get_followers(&self, id: i32) -> Result<Vec<User>> {
+        self.get_related_users(id, "MATCH (:User {id: $id})<-[:FOLLOWS]-(u:User)")
+            .await
+    }
+
+    pub async fn get_following(&self, id: i32) -> Result<Vec<User>> {
+        self.get_related_users(id, "MATCH (:User {id: $id})-[:FOLLOWS]->(u:User)")
+            .await
+    }
+
+    pub async fn get_follow_requests(&self, id: i32) -> Result<Vec<User>> {
+        self.get_related_users(id, "MATCH (:User {id: $id})<-[:REQUESTED_FOLLOW]-(u:User)")
+            .await
+    }
+
+    async fn get_related_users(&self, id: i32, pattern: &str) -> Result<Vec<User>> {
+        let mut result = self
             .graph
-            .run(query("MATCH (u:User {id: $id}) DETACH DELETE u").param("id", id))
+            .execute(
+                query(&format!(
+                    "{} RETURN {} ORDER BY u.name",
+                    pattern, USER_FIELDS
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch related users for user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        let mut users: Vec<User> = vec![];
+        while let Some(row) = result.next().await? {
+            users.push(user_from_row(&row));
+        }
+        Ok(users)
+    }
+
+    pub async fn approve_follow_request(&self, id: i32, requester_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (r:User {id: $requester_id})-[req:REQUESTED_FOLLOW]->(u:User {id: $id})
+                    DELETE req
+                    MERGE (r)-[f:FOLLOWS]->(u) ON CREATE SET f.since = datetime()",
+                )
+                .param("id", id)
+                .param("requester_id", requester_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not approve follow request from user with id '{}' for user with id '{}': {}",
+                    requester_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn reject_follow_request(&self, id: i32, requester_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (:User {id: $requester_id})-[req:REQUESTED_FOLLOW]->(:User {id: $id})
+                    DELETE req",
+                )
+                .param("id", id)
+                .param("requester_id", requester_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not reject follow request from user with id '{}' for user with id '{}': {}",
+                    requester_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    /// Updates the user's privacy settings. Turning follow approval off accepts
+    /// every pending follow request.
+    pub async fn update_privacy(&self, id: i32, settings: &PrivacySettings) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (u:User {id: $id})
+                    SET u.requires_follow_approval = $requires_follow_approval,
+                        u.version = coalesce(u.version, 1) + 1
+                    WITH u
+                    WHERE NOT $requires_follow_approval
+                    MATCH (r:User)-[req:REQUESTED_FOLLOW]->(u)
+                    DELETE req
+                    MERGE (r)-[f:FOLLOWS]->(u) ON CREATE SET f.since = datetime()",
+                )
+                .param("id", id)
+                .param(
+                    "requires_follow_approval",
+                    settings.requires_follow_approval,
+                ),
+            )
             .await
             .map_err(|e| {
-                println!("Could not delete user with id '{}': {}", id, e);
+                println!(
+                    "Could not update privacy settings for user with id '{}': {}",
+                    id, e
+                );
                 e
             })?;
         Ok(())
@@ -285,48 +1206,56 @@ impl DatabaseService {
     pub async fn get_user_books(&self, id: i32) -> Vec<Book> {
         let mut result = self
             .graph
-            .execute(query(
-                "MATCH (u:User {id: $id})-[:HAS_READ]->(b:Book) RETURN b.id, b.title, b.author, b.genre, b.cover",
+            .execute(
+                query(&format!(
+                    "MATCH (u:User {{id: $id}})-[:HAS_READ]->(b:Book) RETURN {}",
+                    book_fields("b")
+                ))
+                .param("id", id),
             )
-            .param("id", id))
             .await
             .map_err(|e| -> Vec<Book> {
                 println!("Could not fetch books for user with id '{}': {}", id, e);
-                return vec![];
+                vec![]
             })
             .unwrap();
 
         let mut books: Vec<Book> = vec![];
         while let Ok(Some(row)) = result.next().await {
-            books.push(Book::new(
-                row.get("b.id").unwrap_or_default(),
-                row.get("b.title").unwrap_or_default(),
-                row.get("b.author").unwrap_or_default(),
-                row.get("b.genre").unwrap_or_default(),
-                row.get("b.cover").unwrap_or_default(),
-            ));
+            books.push(book_from_row(&row, "b"));
         }
         books
     }
 
     pub async fn add_book_to_user(&self, user_id: i32, book_id: &str) -> Result<()> {
-        let _ = self
-            .graph
-            .run(query("MATCH (u:User {id: $user_id}) MATCH (b:Book {id: $book_id}) MERGE (u)-[r:HAS_READ]->(b)")
+        self.graph
+            .run(
+                query(&format!(
+                    "MATCH (u:User {{id: $user_id}}) MATCH (b:Book {{id: $book_id}})
+                    OPTIONAL MATCH (u)-[existing:HAS_READ]->(b)
+                    WITH u, b, existing IS NULL AS is_new
+                    MERGE (u)-[r:HAS_READ]->(b) ON CREATE SET r.read_at = datetime()
+                    WITH u, b, r WHERE is_new
+                    {}",
+                    PUBLISH_EVENT
+                ))
                 .param("user_id", user_id)
                 .param("book_id", book_id)
+                .param("event_type", EventKind::Finished.as_str()),
             )
             .await
             .map_err(|e| {
-                println!("Could not add book with id '{}' to user with id '{}': {}", book_id, user_id, e);
+                println!(
+                    "Could not add book with id '{}' to user with id '{}': {}",
+                    book_id, user_id, e
+                );
                 e
             })?;
         Ok(())
     }
 
     pub async fn remove_book_from_user(&self, user_id: i32, book_id: &str) -> Result<()> {
-        let _ = self
-            .graph
+        self.graph
             .run(
                 query(
                     "MATCH (u:User {id: $user_id})-[r:HAS_READ]->(b:Book {id: $book_id}) DELETE r",
@@ -345,37 +1274,466 @@ impl DatabaseService {
         Ok(())
     }
 
-    pub async fn recommend_books(&self, id: i32) -> Vec<Book> {
+    pub async fn rate_book(&self, user_id: i32, book_id: &str, rating: u8) -> Result<()> {
+        self.graph
+            .run(
+                query(&format!(
+                    "MATCH (u:User {{id: $user_id}})-[r:HAS_READ]->(b:Book {{id: $book_id}})
+                    SET r.rating = $rating, r.rated_at = datetime()
+                    WITH u, b, r
+                    {}",
+                    PUBLISH_EVENT
+                ))
+                .param("user_id", user_id)
+                .param("book_id", book_id)
+                .param("rating", rating as i64)
+                .param("event_type", EventKind::Rated.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not rate book with id '{}' for user with id '{}': {}",
+                    book_id, user_id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn review_book(&self, user_id: i32, book_id: &str, review: &str) -> Result<()> {
+        self.graph
+            .run(
+                query(&format!(
+                    "MATCH (u:User {{id: $user_id}})-[r:HAS_READ]->(b:Book {{id: $book_id}})
+                    SET r.review = $review, r.reviewed_at = datetime()
+                    WITH u, b, r
+                    {}",
+                    PUBLISH_EVENT
+                ))
+                .param("user_id", user_id)
+                .param("book_id", book_id)
+                .param("review", review)
+                .param("event_type", EventKind::Reviewed.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not review book with id '{}' for user with id '{}': {}",
+                    book_id, user_id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    /// Reads a page of the user's activity feed, newest first. Items from
+    /// people the user no longer follows or has muted, and muted event types,
+    /// are skipped.
+    pub async fn get_feed(
+        &self,
+        id: i32,
+        cursor: Option<&FeedCursor>,
+        limit: i64,
+    ) -> Result<FeedPage> {
         let mut result = self
             .graph
-            .execute(query(
-                "MATCH (u:User {id: $id})-[:HAS_READ]->(b:Book)
-                    MATCH (similarUser:User)-[:HAS_READ]->(b)
-                    WHERE similarUser <> u
-                    MATCH (similarUser)-[:HAS_READ]->(rec:Book)
-                    WHERE NOT (u)-[:HAS_READ]->(rec)
-                    RETURN rec.id, rec.title, rec.author, rec.genre, rec.cover, COUNT(similarUser) AS score
-                    ORDER BY score DESC
-                    LIMIT 5",
+            .execute(
+                query(&format!(
+                    "MATCH (me:User {{id: $id}})-[:FEED_ITEM]->(e:Event)<-[:PERFORMED]-(actor:User)
+                    WHERE (me)-[:FOLLOWS]->(actor)
+                        AND NOT (me)-[:MUTES]->(actor)
+                        AND NOT e.type IN coalesce(me.muted_event_types, [])
+                    WITH me, e, actor, e.created_at.epochMillis AS created_millis
+                    WHERE $before IS NULL
+                        OR created_millis < $before
+                        OR (created_millis = $before AND e.id < $before_id)
+                    OPTIONAL MATCH (e)-[:ABOUT]->(b:Book)
+                    OPTIONAL MATCH (e)-[:ABOUT]->(l:ReadingList)
+                    OPTIONAL MATCH (e)-[:ABOUT]->(c:Challenge)
+                    RETURN e.id, e.type, toString(e.created_at) AS created_at, created_millis,
+                        e.rating, e.review, actor.id, actor.name, l.id, l.name,
+                        c.id, c.year, c.goal, {}
+                    ORDER BY created_millis DESC, e.id DESC
+                    LIMIT $limit",
+                    book_fields("b")
+                ))
+                .param("id", id)
+                .param("before", cursor.map(|cursor| cursor.before))
+                .param(
+                    "before_id",
+                    cursor
+                        .map(|cursor| cursor.before_id.as_str())
+                        .unwrap_or_default(),
+                )
+                .param("limit", limit + 1),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch feed for user with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut items: Vec<FeedEvent> = vec![];
+        let mut next_cursor = None;
+        while let Some(row) = result.next().await? {
+            if items.len() as i64 == limit {
+                next_cursor = items.last().map(|item| item.cursor.clone());
+                break;
+            }
+            items.push(FeedEvent::from_row(&row));
+        }
+        Ok(FeedPage { items, next_cursor })
+    }
+
+    pub async fn mute_user(&self, id: i32, target_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (u:User {id: $id}), (t:User {id: $target_id})
+                    WHERE u <> t
+                    MERGE (u)-[:MUTES]->(t)",
+                )
+                .param("id", id)
+                .param("target_id", target_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not mute user with id '{}' for user with id '{}': {}",
+                    target_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn unmute_user(&self, id: i32, target_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (:User {id: $id})-[m:MUTES]->(:User {id: $target_id}) DELETE m")
+                    .param("id", id)
+                    .param("target_id", target_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not unmute user with id '{}' for user with id '{}': {}",
+                    target_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn get_muted_users(&self, id: i32) -> Result<Vec<User>> {
+        self.get_related_users(id, "MATCH (:User {id: $id})-[:MUTES]->(u:User)")
+            .await
+    }
+
+    pub async fn get_muted_event_types(&self, id: i32) -> Result<Vec<String>> {
+        let mut result = self
+            .graph
+            .execute(
+                query("MATCH (u:User {id: $id}) RETURN coalesce(u.muted_event_types, []) AS types")
+                    .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch muted event types for user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("types").unwrap_or_default()),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub async fn set_muted_event_types(&self, id: i32, kinds: &[EventKind]) -> Result<()> {
+        let kinds: Vec<&str> = kinds.iter().map(EventKind::as_str).collect();
+        self.graph
+            .run(
+                query("MATCH (u:User {id: $id}) SET u.muted_event_types = $kinds")
+                    .param("id", id)
+                    .param("kinds", kinds),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not update muted event types for user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    /// Recomputes the statistics that compare a reader with the community,
+    /// which cover every reader and are too costly to derive per request.
+    pub async fn refresh_community_stats(&self) -> Result<()> {
+        self.graph
+            .run(query(
+                "CALL {
+                    MATCH (reader:User)-[:HAS_READ]->(b:Book)
+                    WITH reader, b.genre AS genre, count(*) AS books
+                    WITH reader, collect(books) AS counts, sum(books) AS total
+                    WITH reduce(h = 0.0, c IN counts |
+                        h - (toFloat(c) / total) * log(toFloat(c) / total)) AS entropy
+                    RETURN coalesce(avg(entropy), 0.0) AS diversity
+                }
+                MERGE (s:CommunityStats)
+                SET s.diversity = diversity, s.computed_at = datetime()",
+            ))
+            .await
+            .map_err(|e| {
+                println!("Could not refresh community stats: {}", e);
+                e
+            })
+    }
+
+    /// Refreshes the community statistics every [`COMMUNITY_STATS_INTERVAL`]
+    /// for as long as the server runs.
+    pub async fn refresh_community_stats_periodically(self) {
+        let mut interval = rocket::tokio::time::interval(COMMUNITY_STATS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = self.refresh_community_stats().await;
+        }
+    }
+
+    pub async fn reading_stats(&self, id: i32) -> Result<Option<ReadingStats>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id})
+                    CALL {
+                        WITH u
+                        OPTIONAL MATCH (u)-[r:HAS_READ]->(b:Book)
+                        RETURN count(b) AS books_read,
+                            avg(r.rating) AS average_rating,
+                            sum(coalesce(b.pages, 0)) AS pages_read
+                    }
+                    CALL {
+                        WITH u
+                        MATCH (u)-[r:HAS_READ]->(:Book)
+                        WHERE r.read_at IS NOT NULL
+                        WITH substring(toString(date(r.read_at)), 0, 7) AS period, count(*) AS books
+                        ORDER BY period
+                        RETURN collect({period: period, books: books}) AS books_per_month
+                    }
+                    CALL {
+                        WITH u
+                        MATCH (u)-[r:HAS_READ]->(:Book)
+                        WHERE r.read_at IS NOT NULL
+                        WITH toString(r.read_at.year) AS period, count(*) AS books
+                        ORDER BY period
+                        RETURN collect({period: period, books: books}) AS books_per_year
+                    }
+                    CALL {
+                        WITH u
+                        MATCH (u)-[:HAS_READ]->(b:Book)
+                        WITH b.genre AS name, count(*) AS books
+                        ORDER BY books DESC, name
+                        RETURN collect({name: name, books: books}) AS genres,
+                            collect(books) AS genre_counts,
+                            sum(books) AS total
+                    }
+                    CALL {
+                        WITH u
+                        MATCH (u)-[:HAS_READ]->(b:Book)
+                        WITH b.author AS name, count(*) AS books
+                        ORDER BY books DESC, name
+                        RETURN collect({name: name, books: books}) AS authors
+                    }
+                    CALL {
+                        WITH u
+                        MATCH (u)-[r:HAS_READ]->(:Book)
+                        WHERE r.read_at IS NOT NULL
+                        WITH DISTINCT date(r.read_at) AS day
+                        ORDER BY day
+                        WITH collect(day) AS days
+                        RETURN reduce(s = {best: 0, current: 0, previous: null}, day IN days |
+                            CASE
+                                WHEN s.previous IS NOT NULL AND day = s.previous + duration({days: 1})
+                                THEN {best: CASE WHEN s.current + 1 > s.best THEN s.current + 1 ELSE s.best END,
+                                    current: s.current + 1, previous: day}
+                                ELSE {best: CASE WHEN s.best > 1 THEN s.best ELSE 1 END,
+                                    current: 1, previous: day}
+                            END).best AS longest_streak
+                    }
+                    CALL {
+                        OPTIONAL MATCH (s:CommunityStats)
+                        RETURN coalesce(s.diversity, 0.0) AS community_diversity
+                    }
+                    RETURN books_read, books_per_month, books_per_year, genres, authors,
+                        average_rating, pages_read, longest_streak, community_diversity,
+                        reduce(h = 0.0, c IN genre_counts |
+                            h - (toFloat(c) / total) * log(toFloat(c) / total)) AS diversity",
+                )
+                .param("id", id),
             )
-            .param("id", id))
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch reading stats for user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(row.to().map_err(Error::DeserializationError)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Recommends books read by users who share books with the user, including
+    /// the anonymized reads of deleted accounts. Reads count per work, so
+    /// editions of a work the user has read are never suggested. The next
+    /// volume of a series the user has started comes first, and a volume is
+    /// never recommended while an earlier one is still unread.
+    pub async fn recommend_books(&self, id: i32, genre: Option<&str>) -> Vec<Book> {
+        let mut result = self
+            .graph
+            .execute(with_genre_filter(
+                query(&format!(
+                    "MATCH (u:User {{id: $id}})
+                    CALL {{
+                        WITH u
+                        MATCH (u)-[:HAS_READ]->(:Book)-[:EDITION_OF]->(shared:Work)
+                            <-[:EDITION_OF]-(:Book)<-[:HAS_READ]-(similarUser:User|AnonymousReader)
+                        WHERE similarUser <> u
+                        WITH DISTINCT shared, similarUser
+                        MATCH (similarUser)-[:HAS_READ]->(:Book)-[:EDITION_OF]->(work:Work)
+                        WITH DISTINCT shared, similarUser, work
+                        RETURN work, COUNT(similarUser) AS score, false AS continues
+                        UNION ALL
+                        WITH u
+                        MATCH (u)-[:HAS_READ]->(:Book)-[:PART_OF]->(:Series)
+                            <-[:PART_OF]-(:Book)-[:EDITION_OF]->(work:Work)
+                        RETURN DISTINCT work, 0 AS score, true AS continues
+                    }}
+                    WITH u, work, sum(score) AS score, any(c IN collect(continues) WHERE c) AS continues
+                    MATCH (rec:Book)-[:EDITION_OF]->(work)
+                    WHERE NOT {} AND {} AND {}
+                    WITH work, score, continues, rec
+                    ORDER BY COUNT {{ (rec)<-[:HAS_READ]-(:User|AnonymousReader) }} DESC, rec.title
+                    WITH work, score, continues, head(collect(rec)) AS rec
+                    ORDER BY continues DESC, score DESC
+                    LIMIT 5
+                    RETURN {}, score",
+                    has_read_work("u", "rec"),
+                    genre_filter("rec"),
+                    reading_order_filter("u", "rec"),
+                    book_fields("rec")
+                ))
+                .param("id", id),
+                genre,
+            ))
             .await
             .map_err(|e| -> Vec<Book> {
-                println!("Could not fetch recommended books for user with id '{}': {}", id, e);
-                return vec![];
+                println!(
+                    "Could not fetch recommended books for user with id '{}': {}",
+                    id, e
+                );
+                vec![]
             })
             .unwrap();
 
         let mut books: Vec<Book> = vec![];
         while let Ok(Some(row)) = result.next().await {
-            books.push(Book::new(
-                row.get("rec.id").unwrap_or_default(),
-                row.get("rec.title").unwrap_or_default(),
-                row.get("rec.author").unwrap_or_default(),
-                row.get("rec.genre").unwrap_or_default(),
-                row.get("rec.cover").unwrap_or_default(),
-            ));
+            books.push(book_from_row(&row, "rec"));
         }
         books
     }
+
+    /// Recommends books that people the user follows have read, weighting each
+    /// read by its rating (unrated reads count as 3/5) and by how recently it
+    /// happened, with a half-life of roughly four months. Reads count per work,
+    /// and later volumes of a series are held back until the earlier ones are
+    /// read.
+    pub async fn recommend_books_from_following(
+        &self,
+        id: i32,
+        genre: Option<&str>,
+    ) -> Result<Vec<Recommendation>> {
+        let mut result = self
+            .graph
+            .execute(with_genre_filter(
+                query(&format!(
+                    "MATCH (u:User {{id: $id}})-[:FOLLOWS]->(f:User)-[r:HAS_READ]->(:Book)
+                        -[:EDITION_OF]->(work:Work)
+                    WITH u, work, f,
+                        coalesce(toFloat(r.rating) / 5.0, 0.6) AS strength,
+                        CASE
+                            WHEN coalesce(r.rated_at, r.read_at) IS NULL THEN 0.25
+                            ELSE exp(-duration.inDays(coalesce(r.rated_at, r.read_at), datetime()).days / 180.0)
+                        END AS recency
+                    WITH u, work, f, max(strength * recency) AS weight
+                    ORDER BY weight DESC
+                    WITH u, work, sum(weight) AS score, collect(f.name) AS readers
+                    MATCH (rec:Book)-[:EDITION_OF]->(work)
+                    WHERE NOT {} AND {} AND {}
+                    WITH work, score, readers, rec
+                    ORDER BY COUNT {{ (rec)<-[:HAS_READ]-(:User|AnonymousReader) }} DESC, rec.title
+                    WITH work, score, readers, head(collect(rec)) AS rec
+                    RETURN {}, score, readers
+                    ORDER BY score DESC
+                    LIMIT 5",
+                    has_read_work("u", "rec"),
+                    genre_filter("rec"),
+                    reading_order_filter("u", "rec"),
+                    book_fields("rec")
+                ))
+                .param("id", id),
+                genre,
+            ))
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch social recommendations for user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        let mut recommendations: Vec<Recommendation> = vec![];
+        while let Some(row) = result.next().await? {
+            let readers: Vec<String> = row.get("readers").unwrap_or_default();
+            recommendations.push(Recommendation::new(
+                book_from_row(&row, "rec"),
+                Some(attribution(&readers)),
+            ));
+        }
+        Ok(recommendations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feed_cursors() {
+        let cursor = FeedCursor::parse("1700000000000_0b1c-event").unwrap();
+        assert_eq!(cursor.before, 1_700_000_000_000);
+        assert_eq!(cursor.before_id, "0b1c-event");
+    }
+
+    #[test]
+    fn rejects_malformed_feed_cursors() {
+        for cursor in [
+            "",
+            "1700000000000",
+            "1700000000000_",
+            "yesterday_abc",
+            "_abc",
+        ] {
+            assert!(FeedCursor::parse(cursor).is_none(), "{:?}", cursor);
+        }
+    }
 }
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::{book_fields, book_from_row, user_from_row, Book, DatabaseService, User, USER_FIELDS};
+use crate::normalize::{author_key, split_authors};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContributorRole {
+    #[default]
+    Author,
+    Translator,
+    Illustrator,
+    Editor,
+    Narrator,
+}
+
+impl ContributorRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContributorRole::Author => "author",
+            ContributorRole::Translator => "translator",
+            ContributorRole::Illustrator => "illustrator",
+            ContributorRole::Editor => "editor",
+            ContributorRole::Narrator => "narrator",
+        }
+    }
+}
+
+/// A person credited on a book through a `WROTE` relationship.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contributor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author_id: Option<String>,
+    name: String,
+    #[serde(default)]
+    role: ContributorRole,
+    #[serde(default, skip_serializing)]
+    pub(super) position: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Author {
+    id: String,
+    name: String,
+    bio: String,
+    works: i64,
+    followers: i64,
+}
+
+impl Author {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn works(&self) -> i64 {
+        self.works
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorUpdate {
+    name: String,
+    #[serde(default)]
+    bio: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthoredBook {
+    #[serde(flatten)]
+    book: Book,
+    role: String,
+}
+
+const AUTHOR_FIELDS: &str = "a.id, a.name, coalesce(a.bio, '') AS bio,
+    COUNT { (a)-[:WROTE]->(:Book) } AS works,
+    COUNT { (a)<-[:FOLLOWS]-(:User) } AS followers";
+
+fn author_from_row(row: &Row) -> Author {
+    Author {
+        id: row.get("a.id").unwrap_or_default(),
+        name: row.get("a.name").unwrap_or_default(),
+        bio: row.get("bio").unwrap_or_default(),
+        works: row.get("works").unwrap_or_default(),
+        followers: row.get("followers").unwrap_or_default(),
+    }
+}
+
+impl Book {
+    /// The free-text author shown for the book, derived from its credited
+    /// authors when the `author` field was left empty.
+    pub(super) fn credited_author(&self) -> String {
+        if !self.author.trim().is_empty() || self.contributors.is_empty() {
+            return self.author.trim().to_string();
+        }
+        let authors: Vec<&str> = self
+            .contributors
+            .iter()
+            .filter(|c| matches!(c.role, ContributorRole::Author))
+            .map(|c| c.name.trim())
+            .collect();
+        authors.join(", ")
+    }
+}
+
+/// Builds the `WROTE` links for a book. Explicit contributors win; otherwise
+/// the free-text `author` field is split into one author per name.
+pub(super) fn contributor_links(
+    book_id: &str,
+    book: &Book,
+) -> Vec<HashMap<&'static str, BoltType>> {
+    let contributors: Vec<(String, ContributorRole)> = if book.contributors.is_empty() {
+        split_authors(&book.author)
+            .into_iter()
+            .map(|name| (name, ContributorRole::Author))
+            .collect()
+    } else {
+        book.contributors
+            .iter()
+            .map(|c| (c.name.trim().to_string(), c.role))
+            .collect()
+    };
+
+    contributors
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (name, _))| !author_key(name).is_empty())
+        .map(|(position, (name, role))| {
+            HashMap::from([
+                ("book_id", BoltType::from(book_id)),
+                ("key", BoltType::from(author_key(&name))),
+                ("name", BoltType::from(name)),
+                ("role", BoltType::from(role.as_str())),
+                ("position", BoltType::from(position as i64)),
+            ])
+        })
+        .collect()
+}
+
+impl DatabaseService {
+    /// Replaces the `WROTE` relationships of a book from its contributors or
+    /// its `author` field.
+    pub(super) async fn link_contributors(&self, book_id: &str, book: &Book) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (:Book {id: $book_id})<-[w:WROTE]-(:Author) DELETE w")
+                    .param("book_id", book_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not unlink authors of book with id '{}': {}",
+                    book_id, e
+                );
+                e
+            })?;
+        self.write_contributor_links(contributor_links(book_id, book))
+            .await
+    }
+
+    /// Creates `WROTE` relationships, reusing `Author` nodes whose key or
+    /// aliases match and creating the rest.
+    pub(super) async fn write_contributor_links(
+        &self,
+        links: Vec<HashMap<&str, BoltType>>,
+    ) -> Result<()> {
+        if links.is_empty() {
+            return Ok(());
+        }
+        self.graph
+            .run(
+                query(
+                    "UNWIND $links AS link
+                    MATCH (b:Book {id: link.book_id})
+                    OPTIONAL MATCH (known:Author) WHERE link.key IN known.aliases
+                    WITH b, link, coalesce(known.key, link.key) AS key
+                    MERGE (a:Author {key: key})
+                    ON CREATE SET a.id = randomUUID(), a.name = link.name, a.aliases = []
+                    MERGE (a)-[w:WROTE {role: link.role}]->(b)
+                    SET w.position = link.position",
+                )
+                .param("links", links),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not link authors to books: {}", e);
+                e
+            })
+    }
+
+    /// Turns the `author` strings of books that are not linked to any
+    /// `Author` yet into `Author` nodes and `WROTE` relationships.
+    pub(super) async fn migrate_authors(&self) -> Result<()> {
+        let mut result = self
+            .graph
+            .execute(query(
+                "MATCH (b:Book)
+                WHERE coalesce(b.author, '') <> '' AND NOT (b)<-[:WROTE]-(:Author)
+                RETURN b.id, b.author",
+            ))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch books to migrate authors: {}", e);
+                e
+            })?;
+
+        let mut links: Vec<HashMap<&str, BoltType>> = vec![];
+        while let Some(row) = result.next().await? {
+            let id: String = row.get("b.id").unwrap_or_default();
+            let author: String = row.get("b.author").unwrap_or_default();
+            let book = Book::new(
+                id.clone(),
+                String::new(),
+                author,
+                String::new(),
+                String::new(),
+                vec![],
+                vec![],
+            );
+            links.extend(contributor_links(&id, &book));
+        }
+
+        while !links.is_empty() {
+            let batch: Vec<_> = links.drain(..links.len().min(1000)).collect();
+            self.write_contributor_links(batch).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_all_authors(&self) -> Result<Vec<Author>> {
+        let mut result = self
+            .graph
+            .execute(query(&format!(
+                "MATCH (a:Author) RETURN {} ORDER BY a.name",
+                AUTHOR_FIELDS
+            )))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch authors: {}", e);
+                e
+            })?;
+
+        let mut authors: Vec<Author> = vec![];
+        while let Some(row) = result.next().await? {
+            authors.push(author_from_row(&row));
+        }
+        Ok(authors)
+    }
+
+    /// Returns the authors `get_all_authors` would, skipping the first
+    /// `skip` and returning at most `limit`.
+    pub async fn get_authors_window(&self, skip: i64, limit: i64) -> Result<Vec<Author>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (a:Author) RETURN {} ORDER BY a.name, a.id SKIP $skip LIMIT $limit",
+                    AUTHOR_FIELDS
+                ))
+                .param("skip", skip)
+                .param("limit", limit),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch authors: {}", e);
+                e
+            })?;
+
+        let mut authors: Vec<Author> = vec![];
+        while let Some(row) = result.next().await? {
+            authors.push(author_from_row(&row));
+        }
+        Ok(authors)
+    }
+
+    pub async fn get_author(&self, id: &str) -> Result<Option<Author>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (a:Author {{id: $id}}) RETURN {}",
+                    AUTHOR_FIELDS
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch author with id '{}': {}", id, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(author_from_row(&row))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn edit_author(&self, id: &str, author: &AuthorUpdate) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (a:Author {id: $id}) SET a.name = $name, a.key = $key, a.bio = $bio")
+                    .param("id", id)
+                    .param("name", author.name.trim())
+                    .param("key", author_key(&author.name))
+                    .param("bio", author.bio.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not update author with id '{}': {}", id, e);
+                e
+            })
+    }
+
+    pub async fn get_author_works(&self, id: &str) -> Result<Vec<AuthoredBook>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:Author {{id: $id}})-[r:WROTE]->(b:Book)
+                    RETURN {}, r.role AS role
+                    ORDER BY b.title",
+                    book_fields("b")
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch works of author with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut works: Vec<AuthoredBook> = vec![];
+        while let Some(row) = result.next().await? {
+            works.push(AuthoredBook {
+                book: book_from_row(&row, "b"),
+                role: row.get("role").unwrap_or_default(),
+            });
+        }
+        Ok(works)
+    }
+
+    pub async fn get_author_followers(&self, id: &str) -> Result<Vec<User>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:Author {{id: $id}})<-[:FOLLOWS]-(u:User) RETURN {} ORDER BY u.name",
+                    USER_FIELDS
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch followers of author with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        let mut users: Vec<User> = vec![];
+        while let Some(row) = result.next().await? {
+            users.push(user_from_row(&row));
+        }
+        Ok(users)
+    }
+
+    pub async fn follow_author(&self, id: &str, user_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (a:Author {id: $id}), (u:User {id: $user_id})
+                    MERGE (u)-[f:FOLLOWS]->(a) ON CREATE SET f.since = datetime()",
+                )
+                .param("id", id)
+                .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not follow author with id '{}' as user with id '{}': {}",
+                    id, user_id, e
+                );
+                e
+            })
+    }
+
+    pub async fn unfollow_author(&self, id: &str, user_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (:User {id: $user_id})-[f:FOLLOWS]->(:Author {id: $id}) DELETE f")
+                    .param("id", id)
+                    .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not unfollow author with id '{}' as user with id '{}': {}",
+                    id, user_id, e
+                );
+                e
+            })
+    }
+
+    /// Merges a duplicate author such as "Tolkien" into "J.R.R. Tolkien".
+    /// Works, including those in the trash, and followers move to the
+    /// surviving author, and the duplicate's key is kept as an alias so later
+    /// imports link to the survivor.
+    pub async fn merge_author(&self, id: &str, into_id: &str) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (duplicate:Author {id: $id}), (survivor:Author {id: $into_id})
+                    WHERE duplicate <> survivor
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (duplicate)-[w:WROTE]->(b:Book|DeletedBook)
+                        MERGE (survivor)-[merged:WROTE {role: w.role}]->(b)
+                        SET merged.position = w.position
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (u:User|DeletedUser)-[f:FOLLOWS]->(duplicate)
+                        MERGE (u)-[merged:FOLLOWS]->(survivor)
+                        ON CREATE SET merged.since = f.since
+                    }
+                    SET survivor.aliases = coalesce(survivor.aliases, [])
+                        + duplicate.key + coalesce(duplicate.aliases, [])
+                    DETACH DELETE duplicate",
+                )
+                .param("id", id)
+                .param("into_id", into_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not merge author with id '{}' into author with id '{}': {}",
+                    id, into_id, e
+                );
+                e
+            })
+    }
+}
@@ -0,0 +1,134 @@
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::{DatabaseService, EventKind, PUBLISH_EVENT_ABOUT};
+
+/// A user's goal to read `goal` books in `year`, with the books they have
+/// read that year so far.
+#[derive(Debug, Serialize)]
+pub struct Challenge {
+    id: String,
+    year: i64,
+    goal: i64,
+    books_read: i64,
+    created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewChallenge {
+    year: i64,
+    goal: i64,
+}
+
+impl NewChallenge {
+    pub fn is_valid(&self) -> bool {
+        self.goal > 0 && (1000..=9999).contains(&self.year)
+    }
+}
+
+impl DatabaseService {
+    /// Starts a reading challenge for a year and tells the user's followers
+    /// about it. A user has one challenge per year; starting another one for
+    /// the same year only changes its goal.
+    pub async fn start_challenge(
+        &self,
+        user_id: i32,
+        challenge: &NewChallenge,
+    ) -> Result<Option<String>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $user_id})-[:STARTED]->(c:Challenge {year: $year})
+                    SET c.goal = $goal
+                    RETURN c.id AS id",
+                )
+                .param("user_id", user_id)
+                .param("year", challenge.year)
+                .param("goal", challenge.goal),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not update challenge of user with id '{}': {}",
+                    user_id, e
+                );
+                e
+            })?;
+        if let Some(row) = result.next().await? {
+            return Ok(row.get("id").ok());
+        }
+
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (u:User {{id: $user_id}})
+                    CREATE (u)-[:STARTED]->(about:Challenge {{
+                        id: randomUUID(),
+                        year: $year,
+                        goal: $goal,
+                        created_at: datetime()
+                    }})
+                    WITH u, about
+                    {}
+                    RETURN about.id AS id",
+                    PUBLISH_EVENT_ABOUT
+                ))
+                .param("user_id", user_id)
+                .param("year", challenge.year)
+                .param("goal", challenge.goal)
+                .param("event_type", EventKind::StartedChallenge.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not start challenge for user with id '{}': {}",
+                    user_id, e
+                );
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("id").ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the reading challenges of a user, latest year first, with
+    /// the books read in each year counted from the `HAS_READ` edges.
+    pub async fn get_challenges(&self, user_id: i32) -> Result<Vec<Challenge>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $user_id})-[:STARTED]->(c:Challenge)
+                    RETURN c.id, c.year, c.goal, toString(c.created_at) AS created_at,
+                        COUNT { (u)-[r:HAS_READ]->(:Book) WHERE r.read_at.year = c.year }
+                            AS books_read
+                    ORDER BY c.year DESC",
+                )
+                .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch challenges of user with id '{}': {}",
+                    user_id, e
+                );
+                e
+            })?;
+
+        let mut challenges = vec![];
+        while let Some(row) = result.next().await? {
+            challenges.push(Challenge {
+                id: row.get("c.id").unwrap_or_default(),
+                year: row.get("c.year").unwrap_or_default(),
+                goal: row.get("c.goal").unwrap_or_default(),
+                books_read: row.get("books_read").unwrap_or_default(),
+                created_at: row.get("created_at").unwrap_or_default(),
+            });
+        }
+        Ok(challenges)
+    }
+}
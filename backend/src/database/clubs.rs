@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::works::has_read_work;
+use super::{
+    book_fields, book_from_row, user_from_row, Book, DatabaseService, Recommendation, User,
+    USER_FIELDS,
+};
+
+#[derive(Debug, Serialize)]
+pub struct Club {
+    id: String,
+    name: String,
+    description: String,
+    members: i64,
+    current_book: Option<Book>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewClub {
+    name: String,
+    #[serde(default)]
+    description: String,
+    owner_id: i32,
+}
+
+/// A section of the club's current book, e.g. "Chapters 1-5", to be read
+/// between `starts_on` and `ends_on`.
+#[derive(Debug, Serialize)]
+pub struct Section {
+    id: String,
+    position: i64,
+    title: String,
+    starts_on: String,
+    ends_on: String,
+    posts: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewSection {
+    title: String,
+    starts_on: NaiveDate,
+    ends_on: NaiveDate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Post {
+    id: String,
+    user_id: i32,
+    user_name: String,
+    body: String,
+    created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPost {
+    user_id: i32,
+    body: String,
+}
+
+fn club_fields() -> String {
+    format!(
+        "c.id, c.name, c.description, COUNT {{ (c)<-[:MEMBER_OF]-(:User) }} AS members, {}",
+        book_fields("b")
+    )
+}
+
+fn club_from_row(row: &Row) -> Club {
+    Club {
+        id: row.get("c.id").unwrap_or_default(),
+        name: row.get("c.name").unwrap_or_default(),
+        description: row.get("c.description").unwrap_or_default(),
+        members: row.get("members").unwrap_or_default(),
+        current_book: row
+            .get::<String>("b.id")
+            .ok()
+            .map(|_| book_from_row(row, "b")),
+    }
+}
+
+impl DatabaseService {
+    pub async fn create_club(&self, club: &NewClub) -> Result<Option<String>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $owner_id})
+                    CREATE (u)-[:MEMBER_OF {role: 'owner', joined_at: datetime()}]->(c:Club {
+                        id: randomUUID(),
+                        name: $name,
+                        description: $description,
+                        created_at: datetime()
+                    })
+                    RETURN c.id AS id",
+                )
+                .param("owner_id", club.owner_id)
+                .param("name", club.name.as_str())
+                .param("description", club.description.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not create club: {}", e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("id").ok()),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_all_clubs(&self) -> Result<Vec<Club>> {
+        let mut result = self
+            .graph
+            .execute(query(&format!(
+                "MATCH (c:Club)
+                OPTIONAL MATCH (c)-[:CURRENTLY_READING]->(b:Book)
+                RETURN {}
+                ORDER BY c.name",
+                club_fields()
+            )))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch clubs: {}", e);
+                e
+            })?;
+
+        let mut clubs: Vec<Club> = vec![];
+        while let Some(row) = result.next().await? {
+            clubs.push(club_from_row(&row));
+        }
+        Ok(clubs)
+    }
+
+    pub async fn get_club(&self, id: &str) -> Result<Option<Club>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (c:Club {{id: $id}})
+                    OPTIONAL MATCH (c)-[:CURRENTLY_READING]->(b:Book)
+                    RETURN {}",
+                    club_fields()
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch club with id '{}': {}", id, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(club_from_row(&row))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_club_members(&self, id: &str) -> Result<Vec<User>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:Club {{id: $id}})<-[:MEMBER_OF]-(u:User) RETURN {} ORDER BY u.name",
+                    USER_FIELDS
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch members of club with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut users: Vec<User> = vec![];
+        while let Some(row) = result.next().await? {
+            users.push(user_from_row(&row));
+        }
+        Ok(users)
+    }
+
+    pub async fn join_club(&self, id: &str, user_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (c:Club {id: $id}), (u:User {id: $user_id})
+                    MERGE (u)-[m:MEMBER_OF]->(c)
+                    ON CREATE SET m.role = 'member', m.joined_at = datetime()",
+                )
+                .param("id", id)
+                .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not add user with id '{}' to club with id '{}': {}",
+                    user_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn leave_club(&self, id: &str, user_id: i32) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (:User {id: $user_id})-[m:MEMBER_OF]->(:Club {id: $id}) DELETE m")
+                    .param("id", id)
+                    .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not remove user with id '{}' from club with id '{}': {}",
+                    user_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn set_club_book(&self, id: &str, book_id: &str) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (c:Club {id: $id}), (b:Book {id: $book_id})
+                    OPTIONAL MATCH (c)-[old:CURRENTLY_READING]->(:Book)
+                    DELETE old
+                    WITH DISTINCT c, b
+                    MERGE (c)-[r:CURRENTLY_READING]->(b) ON CREATE SET r.since = datetime()",
+                )
+                .param("id", id)
+                .param("book_id", book_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not set book with id '{}' for club with id '{}': {}",
+                    book_id, id, e
+                );
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn get_club_schedule(&self, id: &str) -> Result<Vec<Section>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (c:Club {id: $id})-[:CURRENTLY_READING]->(b:Book)
+                    MATCH (c)-[:HAS_SECTION]->(s:Section {book_id: b.id})
+                    RETURN s.id, s.position, s.title,
+                        toString(s.starts_on) AS starts_on, toString(s.ends_on) AS ends_on,
+                        COUNT { (s)<-[:IN_SECTION]-(:Post) } AS posts
+                    ORDER BY s.position",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch schedule of club with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut sections: Vec<Section> = vec![];
+        while let Some(row) = result.next().await? {
+            sections.push(Section {
+                id: row.get("s.id").unwrap_or_default(),
+                position: row.get("s.position").unwrap_or_default(),
+                title: row.get("s.title").unwrap_or_default(),
+                starts_on: row.get("starts_on").unwrap_or_default(),
+                ends_on: row.get("ends_on").unwrap_or_default(),
+                posts: row.get("posts").unwrap_or_default(),
+            });
+        }
+        Ok(sections)
+    }
+
+    /// Replaces the schedule of the club's current book. Sections are matched
+    /// by position, so discussions survive renaming or moving dates; sections
+    /// beyond the new length are deleted along with their posts. Returns
+    /// `false` if the schedule is invalid or the club has no current book.
+    pub async fn set_club_schedule(&self, id: &str, sections: &[NewSection]) -> Result<bool> {
+        if sections.is_empty()
+            || sections
+                .iter()
+                .any(|s| s.title.trim().is_empty() || s.ends_on < s.starts_on)
+        {
+            return Ok(false);
+        }
+        let sections: Vec<HashMap<&str, BoltType>> = sections
+            .iter()
+            .map(|s| {
+                HashMap::from([
+                    ("title", BoltType::from(s.title.trim())),
+                    ("starts_on", BoltType::from(s.starts_on)),
+                    ("ends_on", BoltType::from(s.ends_on)),
+                ])
+            })
+            .collect();
+
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (c:Club {id: $id})-[:CURRENTLY_READING]->(b:Book)
+                    OPTIONAL MATCH (c)-[:HAS_SECTION]->(old:Section {book_id: b.id})
+                    WHERE old.position >= size($sections)
+                    OPTIONAL MATCH (old)<-[:IN_SECTION]-(p:Post)
+                    DETACH DELETE p, old
+                    WITH DISTINCT c, b
+                    UNWIND range(0, size($sections) - 1) AS position
+                    WITH c, b, position, $sections[position] AS section
+                    MERGE (c)-[:HAS_SECTION]->(s:Section {book_id: b.id, position: position})
+                    ON CREATE SET s.id = randomUUID()
+                    SET s.title = section.title,
+                        s.starts_on = section.starts_on,
+                        s.ends_on = section.ends_on
+                    RETURN count(s) AS sections",
+                )
+                .param("id", id)
+                .param("sections", sections),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not update schedule of club with id '{}': {}", id, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get::<i64>("sections").unwrap_or_default() > 0),
+            None => Ok(false),
+        }
+    }
+
+    pub async fn get_section_posts(&self, id: &str, section_id: &str) -> Result<Vec<Post>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:Club {id: $id})-[:HAS_SECTION]->(s:Section {id: $section_id})
+                    MATCH (s)<-[:IN_SECTION]-(p:Post)<-[:POSTED]-(u:User)
+                    RETURN p.id, p.body, toString(p.created_at) AS created_at, u.id, u.name
+                    ORDER BY p.created_at",
+                )
+                .param("id", id)
+                .param("section_id", section_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch posts of section with id '{}': {}",
+                    section_id, e
+                );
+                e
+            })?;
+
+        let mut posts: Vec<Post> = vec![];
+        while let Some(row) = result.next().await? {
+            posts.push(Post {
+                id: row.get("p.id").unwrap_or_default(),
+                user_id: row.get("u.id").unwrap_or_default(),
+                user_name: row.get("u.name").unwrap_or_default(),
+                body: row.get("p.body").unwrap_or_default(),
+                created_at: row.get("created_at").unwrap_or_default(),
+            });
+        }
+        Ok(posts)
+    }
+
+    /// Adds a post to a section's discussion thread. Only club members can
+    /// post; returns `None` otherwise.
+    pub async fn add_section_post(
+        &self,
+        id: &str,
+        section_id: &str,
+        post: &NewPost,
+    ) -> Result<Option<String>> {
+        if post.body.trim().is_empty() {
+            return Ok(None);
+        }
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (c:Club {id: $id})-[:HAS_SECTION]->(s:Section {id: $section_id})
+                    MATCH (u:User {id: $user_id})-[:MEMBER_OF]->(c)
+                    CREATE (u)-[:POSTED]->(p:Post {
+                        id: randomUUID(),
+                        body: $body,
+                        created_at: datetime()
+                    })-[:IN_SECTION]->(s)
+                    RETURN p.id AS id",
+                )
+                .param("id", id)
+                .param("section_id", section_id)
+                .param("user_id", post.user_id)
+                .param("body", post.body.trim()),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not add post to section with id '{}': {}",
+                    section_id, e
+                );
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("id").ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Recommends books for the whole club: candidates come from readers who
+    /// share books with any member, are ranked by how often they co-occur and
+    /// how many members they reach, and must be unread by most members.
+    pub async fn recommend_books_for_club(&self, id: &str) -> Result<Vec<Recommendation>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (c:Club {{id: $id}})<-[:MEMBER_OF]-(m:User)
+                    WITH c, collect(m) AS members
+                    UNWIND members AS m
+                    MATCH (m)-[:HAS_READ]->(:Book)-[:EDITION_OF]->(:Work)
+                        <-[:EDITION_OF]-(:Book)<-[:HAS_READ]-(peer:User|AnonymousReader)-[:HAS_READ]->(rec:Book)
+                    WHERE NOT peer IN members AND NOT (c)-[:CURRENTLY_READING]->(rec)
+                    WITH rec, members, count(*) AS affinity, count(DISTINCT m) AS reached
+                    WITH rec, affinity, reached, size(members) AS club_size,
+                        size([member IN members WHERE {}]) AS readers
+                    WHERE readers * 2 < club_size
+                    RETURN {}, club_size - readers AS unread, club_size, affinity * reached AS score
+                    ORDER BY score DESC
+                    LIMIT 5",
+                    has_read_work("member", "rec"),
+                    book_fields("rec")
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch recommendations for club with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        let mut recommendations: Vec<Recommendation> = vec![];
+        while let Some(row) = result.next().await? {
+            let unread: i64 = row.get("unread").unwrap_or_default();
+            let club_size: i64 = row.get("club_size").unwrap_or_default();
+            recommendations.push(Recommendation::new(
+                book_from_row(&row, "rec"),
+                Some(format!("unread by {} of {} members", unread, club_size)),
+            ));
+        }
+        Ok(recommendations)
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::{BTreeSet, HashMap};
+
+use neo4rs::*;
+use serde::Serialize;
+use strsim::jaro_winkler;
+
+use super::{revisions::RevisionAction, Book, BookFilter, DatabaseService};
+use crate::normalize::{first_author_key, title_key};
+
+/// Titles must be at least this similar (Jaro-Winkler) to be suggested as
+/// duplicates when they are not identical after normalization.
+pub(super) const MIN_TITLE_SIMILARITY: f64 = 0.92;
+pub(super) const MIN_AUTHOR_SIMILARITY: f64 = 0.85;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateReason {
+    SameIsbn,
+    SameTitleAndAuthor,
+    SimilarTitleAndAuthor,
+}
+
+/// Two books that are likely the same edition, with a similarity between 0
+/// and 1.
+#[derive(Debug, Serialize)]
+pub struct DuplicateCandidate {
+    books: [Book; 2],
+    reason: DuplicateReason,
+    similarity: f64,
+}
+
+struct Fingerprint<'a> {
+    book: &'a Book,
+    title: String,
+    author: String,
+}
+
+impl Fingerprint<'_> {
+    /// Whether the two books are distinguishable editions, e.g. a hardcover and
+    /// an audiobook of the same work.
+    fn differs_in_edition(&self, other: &Fingerprint) -> bool {
+        fn differs<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+        differs(self.book.isbn(), other.book.isbn())
+            || differs(
+                self.book.format().map(|f| f.as_str()),
+                other.book.format().map(|f| f.as_str()),
+            )
+            || differs(self.book.language(), other.book.language())
+    }
+
+    fn compare(&self, other: &Fingerprint) -> Option<(DuplicateReason, f64)> {
+        if self.book.isbn().is_some() && self.book.isbn() == other.book.isbn() {
+            return Some((DuplicateReason::SameIsbn, 1.0));
+        }
+        if self.title.is_empty() || self.differs_in_edition(other) {
+            return None;
+        }
+        if self.title == other.title && self.author == other.author {
+            return Some((DuplicateReason::SameTitleAndAuthor, 1.0));
+        }
+        let title = jaro_winkler(&self.title, &other.title);
+        let author = jaro_winkler(&self.author, &other.author);
+        (title >= MIN_TITLE_SIMILARITY && author >= MIN_AUTHOR_SIMILARITY).then_some((
+            DuplicateReason::SimilarTitleAndAuthor,
+            (2.0 * title + author) / 3.0,
+        ))
+    }
+}
+
+/// Words too common to block titles on; "The …" alone would put a large part
+/// of the catalog into one block.
+const TITLE_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "at", "by", "das", "de", "der", "die", "el", "for", "from", "in", "is", "l",
+    "la", "le", "les", "los", "of", "on", "the", "to", "un", "une", "with",
+];
+
+/// The first word of a normalized title that is not a stop word.
+fn title_block(title: &str) -> Option<&str> {
+    title
+        .split(' ')
+        .find(|word| !word.is_empty() && !TITLE_STOP_WORDS.contains(word))
+}
+
+/// Pairs of books worth comparing: those sharing an ISBN, a first author or
+/// the first significant word of their title. Comparing every pair would not
+/// scale with the catalog.
+fn candidate_pairs(fingerprints: &[Fingerprint]) -> BTreeSet<(usize, usize)> {
+    let mut blocks: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        if let Some(isbn) = fingerprint.book.isbn() {
+            blocks.entry(format!("isbn:{}", isbn)).or_default().push(i);
+        }
+        if !fingerprint.author.is_empty() {
+            blocks
+                .entry(format!("author:{}", fingerprint.author))
+                .or_default()
+                .push(i);
+        }
+        if let Some(word) = title_block(&fingerprint.title) {
+            blocks.entry(format!("title:{}", word)).or_default().push(i);
+        }
+    }
+
+    let mut pairs = BTreeSet::new();
+    for block in blocks.values() {
+        for (n, &i) in block.iter().enumerate() {
+            for &j in &block[n + 1..] {
+                pairs.insert((i.min(j), i.max(j)));
+            }
+        }
+    }
+    pairs
+}
+
+impl DatabaseService {
+    /// Finds pairs of books that are likely duplicates, most similar first.
+    pub async fn find_duplicate_books(&self, limit: usize) -> Vec<DuplicateCandidate> {
+        let books = self.get_all_books(&BookFilter::default()).await;
+        let fingerprints: Vec<Fingerprint> = books
+            .iter()
+            .map(|book| Fingerprint {
+                book,
+                title: title_key(&book.title),
+                author: first_author_key(&book.author),
+            })
+            .collect();
+
+        let mut candidates: Vec<DuplicateCandidate> = candidate_pairs(&fingerprints)
+            .into_iter()
+            .filter_map(|(i, j)| {
+                let (a, b) = (&fingerprints[i], &fingerprints[j]);
+                a.compare(b).map(|(reason, similarity)| DuplicateCandidate {
+                    books: [a.book.clone(), b.book.clone()],
+                    reason,
+                    similarity,
+                })
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        candidates.truncate(limit);
+        candidates
+    }
+
+    /// Merges a duplicate book into another. Reads, credits, genres, series,
+    /// club usage, reading lists, feed events and idempotency keys move to
+    /// the survivor; if
+    /// a user read both, the survivor's read keeps its own values and gains
+    /// the missing ones. The duplicate becomes a `BookRedirect` recording when
+    /// it was merged, so its id still resolves through `get_book`. The merge
+    /// is recorded as a revision of the survivor, and the duplicate's own
+    /// revisions are deleted with it.
+    pub async fn merge_book(&self, id: &str, into_id: &str, editor: Option<i32>) -> Result<bool> {
+        let before = self.get_book(into_id).await;
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (duplicate:Book {id: $id}), (survivor:Book {id: $into_id})
+                    WHERE duplicate <> survivor
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (u:User|DeletedUser|AnonymousReader)-[r:HAS_READ]->(duplicate)
+                        MERGE (u)-[merged:HAS_READ]->(survivor)
+                        ON CREATE SET merged += properties(r)
+                        ON MATCH SET merged.read_at = coalesce(merged.read_at, r.read_at),
+                            merged.rating = coalesce(merged.rating, r.rating),
+                            merged.rated_at = coalesce(merged.rated_at, r.rated_at),
+                            merged.review = coalesce(merged.review, r.review),
+                            merged.reviewed_at = coalesce(merged.reviewed_at, r.reviewed_at)
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (a:Author)-[w:WROTE]->(duplicate)
+                        MERGE (a)-[merged:WROTE {role: w.role}]->(survivor)
+                        ON CREATE SET merged.position = w.position
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (duplicate)-[:IN_GENRE]->(g:Genre)
+                        MERGE (survivor)-[:IN_GENRE]->(g)
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (duplicate)-[p:PART_OF]->(s:Series)
+                        MERGE (survivor)-[merged:PART_OF]->(s)
+                        ON CREATE SET merged.volume = p.volume
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (c:Club)-[r:CURRENTLY_READING]->(duplicate)
+                        MERGE (c)-[merged:CURRENTLY_READING]->(survivor)
+                        ON CREATE SET merged.since = r.since
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (:Club)-[:HAS_SECTION]->(s:Section {book_id: duplicate.id})
+                        SET s.book_id = survivor.id
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (l:ReadingList)-[i:INCLUDES]->(duplicate)
+                        MERGE (l)-[merged:INCLUDES]->(survivor)
+                        ON CREATE SET merged.added_at = i.added_at
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (e:Event)-[:ABOUT]->(duplicate)
+                        MERGE (e)-[:ABOUT]->(survivor)
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (k:IdempotencyKey)-[:CREATED]->(duplicate)
+                        MERGE (k)-[:CREATED]->(survivor)
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (s:SourceRecord)-[:DESCRIBES]->(duplicate)
+                        MERGE (s)-[:DESCRIBES]->(survivor)
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (r:BookRedirect)-[old:REDIRECTS_TO]->(duplicate)
+                        CREATE (r)-[:REDIRECTS_TO]->(survivor)
+                        DELETE old
+                    }
+                    CALL {
+                        WITH duplicate
+                        MATCH (duplicate)-[:HAS_REVISION]->(r:Revision)
+                        DETACH DELETE r
+                    }
+                    OPTIONAL MATCH (duplicate)-[:EDITION_OF]->(work:Work)
+                    CALL {
+                        WITH duplicate
+                        MATCH (duplicate)-[rel]-()
+                        DELETE rel
+                    }
+                    CALL {
+                        WITH work
+                        WITH work
+                        WHERE work IS NOT NULL AND NOT (work)<-[:EDITION_OF]-(:Book|DeletedBook)
+                        DELETE work
+                    }
+                    REMOVE duplicate:Book
+                    SET duplicate:BookRedirect, duplicate.merged_at = datetime(),
+                        survivor.version = coalesce(survivor.version, 1) + 1
+                    CREATE (duplicate)-[:REDIRECTS_TO]->(survivor)
+                    RETURN survivor.id AS id",
+                )
+                .param("id", id)
+                .param("into_id", into_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not merge book with id '{}' into book with id '{}': {}",
+                    id, into_id, e
+                );
+                e
+            })?;
+
+        if result.next().await?.is_none() {
+            return Ok(false);
+        }
+        self.record_revision(
+            into_id,
+            RevisionAction::Merged,
+            before.as_ref(),
+            editor,
+            None,
+            Some(id),
+        )
+        .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str, author: &str) -> Book {
+        Book::new(
+            String::new(),
+            title.to_string(),
+            author.to_string(),
+            String::new(),
+            String::new(),
+            vec![],
+            vec![],
+        )
+    }
+
+    fn fingerprint(book: &Book) -> Fingerprint<'_> {
+        Fingerprint {
+            book,
+            title: title_key(&book.title),
+            author: first_author_key(&book.author),
+        }
+    }
+
+    #[test]
+    fn blocks_titles_on_their_first_significant_word() {
+        assert_eq!(title_block("the lord of the rings"), Some("lord"));
+        assert_eq!(title_block("a tale of two cities"), Some("tale"));
+        assert_eq!(title_block("the"), None);
+    }
+
+    #[test]
+    fn does_not_pair_titles_sharing_only_an_article() {
+        let books = [
+            book("The Hobbit", "J.R.R. Tolkien"),
+            book("The Road", "Cormac McCarthy"),
+            book("The Hobit", "Tolkien, J. R. R."),
+        ];
+        let fingerprints: Vec<Fingerprint> = books.iter().map(fingerprint).collect();
+        assert_eq!(candidate_pairs(&fingerprints), BTreeSet::from([(0, 2)]));
+    }
+}
@@ -0,0 +1,77 @@
+use neo4rs::*;
+
+use super::DatabaseService;
+
+impl DatabaseService {
+    /// Permanently removes a user from the graph, whether active or in the
+    /// trash: their posts, feed events, reading lists and challenges are
+    /// deleted, the revisions they made no longer name them, and the user
+    /// node goes with every relationship it has. With `keep_signals`, the user's reads first move
+    /// to a new `AnonymousReader` with a random id, keeping only the ratings,
+    /// so the books still count towards other users' recommendations. Both
+    /// steps run in one transaction, so a failed erasure leaves no anonymous
+    /// reads behind to be counted twice when it is retried.
+    pub async fn erase_user(&self, id: i32, keep_signals: bool) -> Result<()> {
+        let mut queries = vec![];
+        if keep_signals {
+            queries.push(
+                query(
+                    "MATCH (:User|DeletedUser {id: $id})-[r:HAS_READ]->(b)
+                    WITH collect({book: b, rating: r.rating}) AS reads
+                    WHERE size(reads) > 0
+                    CREATE (a:AnonymousReader {id: randomUUID()})
+                    WITH a, reads
+                    UNWIND reads AS read
+                    WITH a, read.book AS b, read.rating AS rating
+                    CREATE (a)-[:HAS_READ {rating: rating}]->(b)",
+                )
+                .param("id", id),
+            );
+        }
+        queries.push(
+            query(
+                "MATCH (u:User|DeletedUser {id: $id})
+                CALL {
+                    WITH u
+                    MATCH (u)-[:POSTED]->(p:Post)
+                    DETACH DELETE p
+                }
+                CALL {
+                    WITH u
+                    MATCH (u)-[:PERFORMED]->(e:Event)
+                    DETACH DELETE e
+                }
+                CALL {
+                    WITH u
+                    MATCH (u)-[:CREATED]->(l:ReadingList)
+                    DETACH DELETE l
+                }
+                CALL {
+                    WITH u
+                    MATCH (u)-[:STARTED]->(c:Challenge)
+                    DETACH DELETE c
+                }
+                CALL {
+                    MATCH (r:Revision {user_id: $id})
+                    SET r.user_id = NULL, r.user_name = NULL
+                }
+                DETACH DELETE u",
+            )
+            .param("id", id),
+        );
+
+        let mut txn = self.graph.start_txn().await.map_err(|e| {
+            println!("Could not erase user with id '{}': {}", id, e);
+            e
+        })?;
+        if let Err(e) = txn.run_queries(queries).await {
+            println!("Could not erase user with id '{}': {}", id, e);
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+        txn.commit().await.map_err(|e| {
+            println!("Could not erase user with id '{}': {}", id, e);
+            e
+        })
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::{Book, DatabaseService};
+use crate::normalize::genre_key;
+
+/// A node of the genre taxonomy with its subgenres. `books` counts books
+/// tagged with this genre itself, not with its subgenres.
+#[derive(Debug, Serialize)]
+pub struct Genre {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+    books: i64,
+    children: Vec<Genre>,
+}
+
+impl Genre {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn books(&self) -> i64 {
+        self.books
+    }
+
+    pub(crate) fn children(&self) -> &[Genre] {
+        &self.children
+    }
+
+    /// Finds the genre with `id` among these genres and their subgenres.
+    pub(crate) fn find<'a>(genres: &'a [Genre], id: &str) -> Option<&'a Genre> {
+        genres.iter().find_map(|genre| match genre.id == id {
+            true => Some(genre),
+            false => Genre::find(&genre.children, id),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewGenre {
+    name: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+}
+
+/// A `WHERE` predicate that keeps the book bound to `var` if it is tagged
+/// with the `$genre` filter or any of its subgenres. Passes when no filter
+/// is set; the parameters are added by [`with_genre_filter`].
+pub(super) fn genre_filter(var: &str) -> String {
+    format!(
+        "($genre IS NULL OR EXISTS {{
+            MATCH ({v})-[:IN_GENRE]->(:Genre)-[:SUBGENRE_OF*0..]->(filter_genre:Genre)
+            WHERE filter_genre.id = $genre
+                OR filter_genre.key = $genre_key
+                OR $genre_key IN filter_genre.aliases
+        }})",
+        v = var
+    )
+}
+
+/// Adds the parameters used by [`genre_filter`]. `genre` may be a genre id or
+/// name.
+pub(super) fn with_genre_filter(query: Query, genre: Option<&str>) -> Query {
+    query
+        .param("genre", genre)
+        .param("genre_key", genre.map(genre_key))
+}
+
+impl Book {
+    /// The genre names the book is tagged with, falling back to the single
+    /// free-text `genre` field.
+    fn genre_names(&self) -> Vec<&str> {
+        let names: Vec<&str> = if self.genres.is_empty() {
+            vec![self.genre.as_str()]
+        } else {
+            self.genres.iter().map(String::as_str).collect()
+        };
+        names
+            .into_iter()
+            .map(str::trim)
+            .filter(|name| !genre_key(name).is_empty())
+            .collect()
+    }
+
+    /// The genre stored in the `genre` property, which is the first tagged
+    /// genre when the field was left empty.
+    pub(super) fn primary_genre(&self) -> String {
+        if !self.genre.trim().is_empty() {
+            return self.genre.trim().to_string();
+        }
+        self.genre_names()
+            .first()
+            .map(|name| name.to_string())
+            .unwrap_or_default()
+    }
+}
+
+pub(super) fn genre_links(book_id: &str, book: &Book) -> Vec<HashMap<&'static str, BoltType>> {
+    book.genre_names()
+        .into_iter()
+        .map(|name| {
+            HashMap::from([
+                ("book_id", BoltType::from(book_id)),
+                ("key", BoltType::from(genre_key(name))),
+                ("name", BoltType::from(name)),
+            ])
+        })
+        .collect()
+}
+
+fn build_tree(parent_id: Option<&str>, genres: &mut Vec<Genre>) -> Vec<Genre> {
+    let (mut children, rest): (Vec<Genre>, Vec<Genre>) = std::mem::take(genres)
+        .into_iter()
+        .partition(|genre| genre.parent_id.as_deref() == parent_id);
+    *genres = rest;
+    for child in children.iter_mut() {
+        child.children = build_tree(Some(&child.id), genres);
+    }
+    children
+}
+
+impl DatabaseService {
+    /// Replaces the `IN_GENRE` relationships of a book. Unknown genres are
+    /// created at the top level of the taxonomy.
+    pub(super) async fn link_genres(&self, book_id: &str, book: &Book) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (:Book {id: $book_id})-[t:IN_GENRE]->(:Genre) DELETE t")
+                    .param("book_id", book_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not unlink genres of book with id '{}': {}",
+                    book_id, e
+                );
+                e
+            })?;
+        self.write_genre_links(genre_links(book_id, book)).await
+    }
+
+    pub(super) async fn write_genre_links(
+        &self,
+        links: Vec<HashMap<&str, BoltType>>,
+    ) -> Result<()> {
+        if links.is_empty() {
+            return Ok(());
+        }
+        self.graph
+            .run(
+                query(
+                    "UNWIND $links AS link
+                    MATCH (b:Book {id: link.book_id})
+                    OPTIONAL MATCH (known:Genre) WHERE link.key IN known.aliases
+                    WITH b, link, coalesce(known.key, link.key) AS key
+                    MERGE (g:Genre {key: key})
+                    ON CREATE SET g.id = randomUUID(), g.name = link.name, g.aliases = []
+                    MERGE (b)-[:IN_GENRE]->(g)",
+                )
+                .param("links", links),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not link genres to books: {}", e);
+                e
+            })
+    }
+
+    /// Turns the `genre` strings of books that are not tagged with any
+    /// `Genre` yet into top-level `Genre` nodes.
+    pub(super) async fn migrate_genres(&self) -> Result<()> {
+        let mut result = self
+            .graph
+            .execute(query(
+                "MATCH (b:Book)
+                WHERE coalesce(b.genre, '') <> '' AND NOT (b)-[:IN_GENRE]->(:Genre)
+                RETURN b.id, b.genre",
+            ))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch books to migrate genres: {}", e);
+                e
+            })?;
+
+        let mut links: Vec<HashMap<&str, BoltType>> = vec![];
+        while let Some(row) = result.next().await? {
+            let id: String = row.get("b.id").unwrap_or_default();
+            let genre: String = row.get("b.genre").unwrap_or_default();
+            links.push(HashMap::from([
+                ("book_id", BoltType::from(id)),
+                ("key", BoltType::from(genre_key(&genre))),
+                ("name", BoltType::from(genre.trim())),
+            ]));
+        }
+
+        while !links.is_empty() {
+            let batch: Vec<_> = links.drain(..links.len().min(1000)).collect();
+            self.write_genre_links(batch).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the whole taxonomy as a forest of top-level genres.
+    pub async fn get_genre_tree(&self) -> Result<Vec<Genre>> {
+        let mut result = self
+            .graph
+            .execute(query(
+                "MATCH (g:Genre)
+                OPTIONAL MATCH (g)-[:SUBGENRE_OF]->(parent:Genre)
+                RETURN g.id, g.name, parent.id, COUNT { (g)<-[:IN_GENRE]-(:Book) } AS books
+                ORDER BY g.name",
+            ))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch genres: {}", e);
+                e
+            })?;
+
+        let mut genres: Vec<Genre> = vec![];
+        while let Some(row) = result.next().await? {
+            genres.push(Genre {
+                id: row.get("g.id").unwrap_or_default(),
+                name: row.get("g.name").unwrap_or_default(),
+                parent_id: row.get("parent.id").ok(),
+                books: row.get("books").unwrap_or_default(),
+                children: vec![],
+            });
+        }
+        Ok(build_tree(None, &mut genres))
+    }
+
+    pub async fn add_genre(&self, genre: &NewGenre) -> Result<Option<String>> {
+        if genre_key(&genre.name).is_empty() {
+            return Ok(None);
+        }
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "OPTIONAL MATCH (parent:Genre {id: $parent_id})
+                    WITH parent
+                    WHERE $parent_id IS NULL OR parent IS NOT NULL
+                    CREATE (g:Genre {id: randomUUID(), key: $key, name: $name, aliases: []})
+                    FOREACH (p IN CASE WHEN parent IS NULL THEN [] ELSE [parent] END |
+                        CREATE (g)-[:SUBGENRE_OF]->(p))
+                    RETURN g.id AS id",
+                )
+                .param("parent_id", genre.parent_id.as_deref())
+                .param("key", genre_key(&genre.name))
+                .param("name", genre.name.trim()),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not add genre: {}", e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("id").ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Renames a genre and moves it under a new parent, or to the top level
+    /// when `parent_id` is absent. Returns `false` if the genre does not
+    /// exist or the move would create a cycle.
+    pub async fn edit_genre(&self, id: &str, genre: &NewGenre) -> Result<bool> {
+        if genre_key(&genre.name).is_empty() {
+            return Ok(false);
+        }
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (g:Genre {id: $id})
+                    OPTIONAL MATCH (parent:Genre {id: $parent_id})
+                    WITH g, parent
+                    WHERE ($parent_id IS NULL OR parent IS NOT NULL)
+                        AND (parent IS NULL OR NOT (parent)-[:SUBGENRE_OF*0..]->(g))
+                    SET g.name = $name, g.key = $key
+                    WITH g, parent
+                    OPTIONAL MATCH (g)-[old:SUBGENRE_OF]->(:Genre)
+                    DELETE old
+                    WITH DISTINCT g, parent
+                    FOREACH (p IN CASE WHEN parent IS NULL THEN [] ELSE [parent] END |
+                        CREATE (g)-[:SUBGENRE_OF]->(p))
+                    RETURN g.id AS id",
+                )
+                .param("id", id)
+                .param("parent_id", genre.parent_id.as_deref())
+                .param("key", genre_key(&genre.name))
+                .param("name", genre.name.trim()),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not update genre with id '{}': {}", id, e);
+                e
+            })?;
+
+        Ok(result.next().await?.is_some())
+    }
+
+    /// Deletes a genre. Its subgenres and books move up to its parent; at the
+    /// top level, subgenres become top-level genres and books lose the tag.
+    pub async fn delete_genre(&self, id: &str) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (g:Genre {id: $id})
+                    OPTIONAL MATCH (g)-[:SUBGENRE_OF]->(parent:Genre)
+                    CALL {
+                        WITH g, parent
+                        MATCH (child:Genre)-[:SUBGENRE_OF]->(g)
+                        WHERE parent IS NOT NULL
+                        MERGE (child)-[:SUBGENRE_OF]->(parent)
+                    }
+                    CALL {
+                        WITH g, parent
+                        MATCH (b:Book)-[:IN_GENRE]->(g)
+                        WHERE parent IS NOT NULL
+                        MERGE (b)-[:IN_GENRE]->(parent)
+                    }
+                    DETACH DELETE g",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not delete genre with id '{}': {}", id, e);
+                e
+            })
+    }
+
+    /// Merges a duplicate genre into another. Books and subgenres move to the
+    /// survivor, and the duplicate's key is kept as an alias so filters and
+    /// later tagging by the old name still resolve. Merging a genre into one
+    /// of its own subgenres is refused.
+    pub async fn merge_genre(&self, id: &str, into_id: &str) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (duplicate:Genre {id: $id}), (survivor:Genre {id: $into_id})
+                    WHERE duplicate <> survivor AND NOT (survivor)-[:SUBGENRE_OF*]->(duplicate)
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (b:Book|DeletedBook)-[:IN_GENRE]->(duplicate)
+                        MERGE (b)-[:IN_GENRE]->(survivor)
+                    }
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (child:Genre)-[:SUBGENRE_OF]->(duplicate)
+                        MERGE (child)-[:SUBGENRE_OF]->(survivor)
+                    }
+                    SET survivor.aliases = coalesce(survivor.aliases, [])
+                        + duplicate.key + coalesce(duplicate.aliases, [])
+                    DETACH DELETE duplicate
+                    RETURN survivor.id AS id",
+                )
+                .param("id", id)
+                .param("into_id", into_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not merge genre with id '{}' into genre with id '{}': {}",
+                    id, into_id, e
+                );
+                e
+            })?;
+
+        Ok(result.next().await?.is_some())
+    }
+}
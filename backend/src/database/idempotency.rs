@@ -0,0 +1,45 @@
+use neo4rs::*;
+
+use super::DatabaseService;
+
+/// How long an `Idempotency-Key` is remembered. A retry after this window
+/// creates a new book.
+pub(super) const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+impl DatabaseService {
+    /// Returns the id of the book created earlier with the same idempotency
+    /// key, forgetting keys older than [`IDEMPOTENCY_KEY_TTL_HOURS`] first.
+    pub(super) async fn find_idempotent_book(&self, key: &str) -> Result<Option<String>> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (k:IdempotencyKey)
+                    WHERE k.created_at < datetime() - duration({hours: $ttl})
+                    DETACH DELETE k",
+                )
+                .param("ttl", IDEMPOTENCY_KEY_TTL_HOURS),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not expire idempotency keys: {}", e);
+                e
+            })?;
+
+        let mut result = self
+            .graph
+            .execute(
+                query("MATCH (:IdempotencyKey {key: $key})-[:CREATED]->(b:Book) RETURN b.id")
+                    .param("key", key),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not look up idempotency key '{}': {}", key, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("b.id").ok()),
+            None => Ok(None),
+        }
+    }
+}
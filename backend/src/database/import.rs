@@ -0,0 +1,261 @@
+use neo4rs::*;
+use rocket::serde::json::{serde_json, Value};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    book_fields, book_from_row,
+    patch::patched_book,
+    revisions::{diff, FieldChange},
+    Book, DatabaseService, FieldError, PatchError,
+};
+use crate::normalize::normalize_isbn;
+
+/// Fields a new book needs before it can be read from an imported row.
+const REQUIRED_FIELDS: [&str; 4] = ["title", "author", "genre", "cover"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportOutcome {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl ImportOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportOutcome::Created => "created",
+            ImportOutcome::Updated => "updated",
+            ImportOutcome::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// What importing one row did, or would do in a dry run. `book_id` is unset
+/// for books a dry run would create.
+#[derive(Debug, Serialize)]
+pub struct ImportedBook {
+    pub outcome: ImportOutcome,
+    pub book_id: Option<String>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Why a row was not imported. Database errors are logged where they occur.
+#[derive(Debug)]
+pub enum ImportError {
+    Invalid(Vec<FieldError>),
+    Database,
+}
+
+impl From<neo4rs::Error> for ImportError {
+    fn from(_: neo4rs::Error) -> Self {
+        ImportError::Database
+    }
+}
+
+impl From<PatchError> for ImportError {
+    fn from(error: PatchError) -> Self {
+        match error {
+            PatchError::Invalid(errors) => ImportError::Invalid(errors),
+            PatchError::NotFound | PatchError::Stale | PatchError::Database => {
+                ImportError::Database
+            }
+        }
+    }
+}
+
+/// Reads a new book from the JSON fields of an imported row and validates
+/// it. Fields the source does not provide, like a cover, are left empty.
+pub(super) fn new_book(fields: &Value) -> Result<Book, ImportError> {
+    let mut fields = fields.clone();
+    if let Some(fields) = fields.as_object_mut() {
+        for field in REQUIRED_FIELDS {
+            fields.entry(field).or_insert_with(|| Value::from(""));
+        }
+    }
+    let mut book: Book = serde_json::from_value(fields)
+        .map_err(|e| ImportError::Invalid(vec![FieldError::new("row", e.to_string())]))?;
+    book.validate().map_err(ImportError::Invalid)?;
+    Ok(book)
+}
+
+/// The changes writing `after` would make. Credits and genre tags left empty
+/// are derived from `author` and `genre` when the book is written, so they
+/// are not reported as cleared.
+fn planned_changes(before: Option<&Book>, after: &Book) -> Vec<FieldChange> {
+    diff(before, after)
+        .into_iter()
+        .filter(|change| {
+            let derived =
+                before.is_none() || matches!(change.field.as_str(), "contributors" | "genres");
+            !(derived && change.new.as_array().is_some_and(Vec::is_empty))
+        })
+        .collect()
+}
+
+impl DatabaseService {
+    async fn find_book_by_source_key(&self, key: &str) -> Result<Option<Book>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:SourceRecord {{key: $key}})-[:DESCRIBES]->(b:Book)
+                    RETURN {}
+                    LIMIT 1",
+                    book_fields("b")
+                ))
+                .param("key", key),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch book with source key '{}': {}", key, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(book_from_row(&row, "b"))),
+            None => Ok(None),
+        }
+    }
+
+    /// Remembers that the record `key` of an external catalog describes a
+    /// book, so importing the record again updates that book.
+    async fn link_source_record(&self, book_id: &str, key: &str) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (b:Book {id: $book_id})
+                    MERGE (s:SourceRecord {key: $key})
+                    WITH b, s
+                    OPTIONAL MATCH (s)-[old:DESCRIBES]->()
+                    DELETE old
+                    MERGE (s)-[:DESCRIBES]->(b)",
+                )
+                .param("book_id", book_id)
+                .param("key", key),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not link source key '{}' to book with id '{}': {}",
+                    key, book_id, e
+                );
+                e
+            })
+    }
+
+    async fn find_book_by_isbn(&self, isbn: &str) -> Result<Option<Book>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (b:Book {{isbn: $isbn}})
+                    RETURN {}
+                    ORDER BY b.created_at
+                    LIMIT 1",
+                    book_fields("b")
+                ))
+                .param("isbn", isbn),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch book with ISBN '{}': {}", isbn, e);
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(book_from_row(&row, "b"))),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates or updates a book from one imported row, given as the JSON
+    /// fields of a book. A row updates the book with its `id`, or else the
+    /// book its `source_key` was imported as before, or else the book with
+    /// its ISBN; fields missing from the row keep their current values. A dry
+    /// run writes nothing and reports the changes it would make. A book
+    /// created under `idempotency_key` is not created again when the row is
+    /// imported a second time, e.g. after a restart.
+    pub async fn import_book(
+        &self,
+        row: &Value,
+        dry_run: bool,
+        idempotency_key: Option<&str>,
+    ) -> Result<ImportedBook, ImportError> {
+        let id = row
+            .get("id")
+            .and_then(Value::as_str)
+            .filter(|id| !id.is_empty());
+        let source_key = row
+            .get("source_key")
+            .and_then(Value::as_str)
+            .filter(|key| !key.is_empty());
+        let existing = match id {
+            Some(id) => Some(self.get_book(id).await.ok_or_else(|| {
+                ImportError::Invalid(vec![FieldError::new(
+                    "id",
+                    format!("no book has the id '{}'", id),
+                )])
+            })?),
+            None => match source_key {
+                Some(key) => self.find_book_by_source_key(key).await?,
+                None => None,
+            },
+        };
+        let existing = match existing {
+            Some(book) => Some(book),
+            None => match row
+                .get("isbn")
+                .and_then(Value::as_str)
+                .and_then(normalize_isbn)
+            {
+                Some(isbn) => self.find_book_by_isbn(&isbn).await?,
+                None => None,
+            },
+        };
+
+        let Some(existing) = existing else {
+            let book = new_book(row)?;
+            let changes = planned_changes(None, &book);
+            let book_id = match dry_run {
+                true => None,
+                false => {
+                    let id = self
+                        .add_book(&book, idempotency_key, None)
+                        .await
+                        .ok_or(ImportError::Database)?;
+                    if let Some(key) = source_key {
+                        self.link_source_record(&id, key).await?;
+                    }
+                    Some(id)
+                }
+            };
+            return Ok(ImportedBook {
+                outcome: ImportOutcome::Created,
+                book_id,
+                changes,
+            });
+        };
+
+        let book = patched_book(&existing, row)?;
+        let changes = planned_changes(Some(&existing), &book);
+        if let (Some(key), false) = (source_key, dry_run) {
+            self.link_source_record(&existing.id, key).await?;
+        }
+        if changes.is_empty() {
+            return Ok(ImportedBook {
+                outcome: ImportOutcome::Unchanged,
+                book_id: Some(existing.id),
+                changes,
+            });
+        }
+        if !dry_run {
+            self.edit_book(&existing.id, &book, None, None).await?;
+        }
+        Ok(ImportedBook {
+            outcome: ImportOutcome::Updated,
+            book_id: Some(existing.id),
+            changes,
+        })
+    }
+}
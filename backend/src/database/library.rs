@@ -0,0 +1,432 @@
+use neo4rs::*;
+use rocket::serde::json::Value;
+use serde::{Deserialize, Serialize};
+use strsim::jaro_winkler;
+
+use super::{
+    duplicates::{MIN_AUTHOR_SIMILARITY, MIN_TITLE_SIMILARITY},
+    import::new_book,
+    Book, BookFilter, DatabaseService, FieldError, ImportError,
+};
+use crate::normalize::{first_author_key, title_key};
+
+/// Looser similarities below which a catalog book is not offered as a
+/// candidate for an imported read. Matches between these and the duplicate
+/// thresholds are left for the user to review.
+const MIN_CANDIDATE_TITLE_SIMILARITY: f64 = 0.8;
+const MIN_CANDIDATE_AUTHOR_SIMILARITY: f64 = 0.7;
+
+/// Only rows on this shelf become reads; the other exclusive shelves
+/// ("to-read", "currently-reading", "did-not-finish") are skipped.
+const READ_STATUS: &str = "read";
+
+/// A book from another service's library export and how the user read it.
+/// `book` holds the JSON fields of the book; `read_at` is a `YYYY-MM-DD`
+/// date.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub source: String,
+    pub book: Value,
+    pub status: String,
+    pub rating: Option<i64>,
+    pub read_at: Option<String>,
+    pub review: Option<String>,
+    pub shelves: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadOutcome {
+    Matched,
+    Created,
+    Unmatched,
+    Skipped,
+}
+
+impl ReadOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReadOutcome::Matched => "matched",
+            ReadOutcome::Created => "created",
+            ReadOutcome::Unmatched => "unmatched",
+            ReadOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// A catalog book that may be the one an imported row refers to.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchCandidate {
+    book_id: String,
+    title: String,
+    author: String,
+    similarity: f64,
+}
+
+/// What importing one read did, or would do in a dry run. Unmatched rows
+/// carry the candidates the user can choose from.
+#[derive(Debug, Serialize)]
+pub struct ImportedRead {
+    pub outcome: ReadOutcome,
+    pub book_id: Option<String>,
+    pub candidates: Vec<MatchCandidate>,
+}
+
+/// How the user settles an unmatched row: read an existing book, add the
+/// book to the catalog, or leave the row out.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Resolution {
+    Match { book_id: String },
+    Create,
+    Skip,
+}
+
+struct IndexedBook {
+    id: String,
+    isbn: Option<String>,
+    title: String,
+    author: String,
+    title_key: String,
+    author_key: String,
+}
+
+/// The catalog held in memory while a library import runs, so each row is
+/// matched without a query. Books the import creates are added as it goes.
+pub struct BookIndex {
+    books: Vec<IndexedBook>,
+}
+
+/// How the book of an imported row is found: by the catalog id the row
+/// names, as tools that import ratings for known books send, or else by its
+/// fields.
+enum EntryBook {
+    Id(String),
+    Fields(Box<Book>),
+}
+
+fn entry_book(book: &Value) -> Result<EntryBook, ImportError> {
+    match book
+        .get("id")
+        .and_then(Value::as_str)
+        .filter(|id| !id.is_empty())
+    {
+        Some(id) => Ok(EntryBook::Id(id.to_string())),
+        None => Ok(EntryBook::Fields(Box::new(new_book(book)?))),
+    }
+}
+
+enum BookMatch {
+    Found(String),
+    Ambiguous(Vec<MatchCandidate>),
+    Missing,
+}
+
+impl BookIndex {
+    fn insert(&mut self, id: &str, book: &Book) {
+        self.books.push(IndexedBook {
+            id: id.to_string(),
+            isbn: book.isbn.clone(),
+            title: book.title.clone(),
+            author: book.author.clone(),
+            title_key: title_key(&book.title),
+            author_key: first_author_key(&book.author),
+        });
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.books.iter().any(|indexed| indexed.id == id)
+    }
+
+    /// Finds the catalog book for an imported one: by ISBN, then by
+    /// identical title and first author, then by a single close fuzzy match.
+    /// Several close matches, or only looser ones, are returned for review.
+    fn find(&self, book: &Book) -> BookMatch {
+        if let Some(found) = self
+            .books
+            .iter()
+            .find(|indexed| book.isbn.is_some() && indexed.isbn == book.isbn)
+        {
+            return BookMatch::Found(found.id.clone());
+        }
+        let title = title_key(&book.title);
+        let author = first_author_key(&book.author);
+        if let Some(found) = self
+            .books
+            .iter()
+            .find(|indexed| indexed.title_key == title && indexed.author_key == author)
+        {
+            return BookMatch::Found(found.id.clone());
+        }
+
+        let mut close: Vec<MatchCandidate> = vec![];
+        let mut candidates: Vec<MatchCandidate> = vec![];
+        for indexed in &self.books {
+            let title_similarity = jaro_winkler(&title, &indexed.title_key);
+            let author_similarity = jaro_winkler(&author, &indexed.author_key);
+            if title_similarity < MIN_CANDIDATE_TITLE_SIMILARITY
+                || author_similarity < MIN_CANDIDATE_AUTHOR_SIMILARITY
+            {
+                continue;
+            }
+            let candidate = MatchCandidate {
+                book_id: indexed.id.clone(),
+                title: indexed.title.clone(),
+                author: indexed.author.clone(),
+                similarity: (2.0 * title_similarity + author_similarity) / 3.0,
+            };
+            if title_similarity >= MIN_TITLE_SIMILARITY
+                && author_similarity >= MIN_AUTHOR_SIMILARITY
+            {
+                close.push(candidate.clone());
+            }
+            candidates.push(candidate);
+        }
+        match close.as_slice() {
+            [found] => return BookMatch::Found(found.book_id.clone()),
+            [] if candidates.is_empty() => return BookMatch::Missing,
+            _ => {}
+        }
+        candidates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        candidates.truncate(5);
+        BookMatch::Ambiguous(candidates)
+    }
+}
+
+impl DatabaseService {
+    pub async fn book_index(&self) -> BookIndex {
+        let mut index = BookIndex { books: vec![] };
+        for book in self.get_all_books(&BookFilter::default()).await {
+            index.insert(&book.id, &book);
+        }
+        index
+    }
+
+    /// Records that a user read a book, keeping any rating, review or date
+    /// the user already set here and adding the imported shelves.
+    async fn link_read(&self, user_id: i32, book_id: &str, entry: &LibraryEntry) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (u:User {id: $user_id}), (b:Book {id: $book_id})
+                    WITH u, b, CASE WHEN $read_at IS NULL THEN datetime()
+                        ELSE datetime({date: date($read_at)}) END AS read_at
+                    MERGE (u)-[r:HAS_READ]->(b)
+                    ON CREATE SET r.read_at = read_at, r.imported_from = $source
+                    SET r.rating = coalesce(r.rating, $rating),
+                        r.rated_at = CASE WHEN r.rated_at IS NULL AND $rating IS NOT NULL
+                            THEN read_at ELSE r.rated_at END,
+                        r.review = coalesce(r.review, $review),
+                        r.reviewed_at = CASE WHEN r.reviewed_at IS NULL AND $review IS NOT NULL
+                            THEN read_at ELSE r.reviewed_at END,
+                        r.shelves = coalesce(r.shelves, [])
+                            + [shelf IN $shelves WHERE NOT shelf IN coalesce(r.shelves, [])]",
+                )
+                .param("user_id", user_id)
+                .param("book_id", book_id)
+                .param("source", entry.source.as_str())
+                .param("read_at", entry.read_at.as_deref())
+                .param("rating", entry.rating)
+                .param("review", entry.review.as_deref())
+                .param("shelves", entry.shelves.clone()),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not import read of book with id '{}' for user with id '{}': {}",
+                    book_id, user_id, e
+                );
+                e
+            })
+    }
+
+    /// Imports one read from another service. A book named by its catalog
+    /// id is read as is; any other book is matched against `index` and
+    /// created when the catalog has nothing like it, and rows with uncertain
+    /// matches are left unmatched for the user to resolve. A book
+    /// created under `idempotency_key` is not created again when the row is
+    /// imported a second time.
+    pub async fn import_read(
+        &self,
+        user_id: i32,
+        entry: &LibraryEntry,
+        index: &mut BookIndex,
+        dry_run: bool,
+        idempotency_key: Option<&str>,
+    ) -> Result<ImportedRead, ImportError> {
+        if entry.status != READ_STATUS {
+            return Ok(ImportedRead {
+                outcome: ReadOutcome::Skipped,
+                book_id: None,
+                candidates: vec![],
+            });
+        }
+        let book = match entry_book(&entry.book)? {
+            EntryBook::Fields(book) => *book,
+            EntryBook::Id(id) => {
+                let book_id = match index.contains(&id) {
+                    true => id,
+                    false => self
+                        .get_book(&id)
+                        .await
+                        .map(|book| book.id)
+                        .ok_or_else(|| {
+                            ImportError::Invalid(vec![FieldError::new(
+                                "id",
+                                format!("no book has the id '{}'", id),
+                            )])
+                        })?,
+                };
+                if !dry_run {
+                    self.link_read(user_id, &book_id, entry).await?;
+                }
+                return Ok(ImportedRead {
+                    outcome: ReadOutcome::Matched,
+                    book_id: Some(book_id),
+                    candidates: vec![],
+                });
+            }
+        };
+        let (outcome, book_id) = match index.find(&book) {
+            BookMatch::Found(id) => (ReadOutcome::Matched, id),
+            BookMatch::Ambiguous(candidates) => {
+                return Ok(ImportedRead {
+                    outcome: ReadOutcome::Unmatched,
+                    book_id: None,
+                    candidates,
+                })
+            }
+            BookMatch::Missing if dry_run => {
+                index.insert("", &book);
+                (ReadOutcome::Created, String::new())
+            }
+            BookMatch::Missing => {
+                let id = self
+                    .add_book(&book, idempotency_key, None)
+                    .await
+                    .ok_or(ImportError::Database)?;
+                index.insert(&id, &book);
+                (ReadOutcome::Created, id)
+            }
+        };
+        if !dry_run {
+            self.link_read(user_id, &book_id, entry).await?;
+        }
+        Ok(ImportedRead {
+            outcome,
+            book_id: Some(book_id).filter(|id| !id.is_empty()),
+            candidates: vec![],
+        })
+    }
+
+    /// Applies the user's choice for an unmatched row. Returns the outcome
+    /// and the book that was read, if any. A book created under
+    /// `idempotency_key` is not created again if the choice is applied twice.
+    pub async fn resolve_read(
+        &self,
+        user_id: i32,
+        entry: &LibraryEntry,
+        resolution: &Resolution,
+        idempotency_key: Option<&str>,
+    ) -> Result<(ReadOutcome, Option<String>), ImportError> {
+        let (outcome, book_id) = match resolution {
+            Resolution::Skip => return Ok((ReadOutcome::Skipped, None)),
+            Resolution::Match { book_id } => {
+                let book = self.get_book(book_id).await.ok_or_else(|| {
+                    ImportError::Invalid(vec![FieldError::new(
+                        "book_id",
+                        format!("no book has the id '{}'", book_id),
+                    )])
+                })?;
+                (ReadOutcome::Matched, book.id)
+            }
+            Resolution::Create => {
+                let book = new_book(&entry.book)?;
+                let id = self
+                    .add_book(&book, idempotency_key, None)
+                    .await
+                    .ok_or(ImportError::Database)?;
+                (ReadOutcome::Created, id)
+            }
+        };
+        self.link_read(user_id, &book_id, entry).await?;
+        Ok((outcome, Some(book_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json::{json, serde_json};
+
+    use super::*;
+
+    fn index(books: &[(&str, &str, &str)]) -> BookIndex {
+        let mut index = BookIndex { books: vec![] };
+        for (id, title, author) in books {
+            let book: Book = serde_json::from_value(json!({
+                "title": title,
+                "author": author,
+                "genre": "",
+                "cover": "",
+            }))
+            .unwrap();
+            index.insert(id, &book);
+        }
+        index
+    }
+
+    fn find(index: &BookIndex, fields: Value) -> BookMatch {
+        match entry_book(&fields) {
+            Ok(EntryBook::Fields(book)) => index.find(&book),
+            _ => panic!("expected book fields"),
+        }
+    }
+
+    #[test]
+    fn reads_books_named_by_id_without_their_fields() {
+        match entry_book(&json!({ "id": "b1" })) {
+            Ok(EntryBook::Id(id)) => assert_eq!(id, "b1"),
+            _ => panic!("expected the book id"),
+        }
+        assert!(index(&[("b1", "Dune", "Frank Herbert")]).contains("b1"));
+    }
+
+    #[test]
+    fn requires_a_title_for_books_not_named_by_id() {
+        assert!(matches!(
+            entry_book(&json!({ "author": "Frank Herbert" })),
+            Err(ImportError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn matches_books_by_title_and_first_author() {
+        let index = index(&[
+            ("b1", "Dune", "Frank Herbert"),
+            ("b2", "The Hobbit", "J.R.R. Tolkien"),
+        ]);
+        let found = find(
+            &index,
+            json!({ "title": "the hobbit", "author": "Tolkien, J. R. R." }),
+        );
+        assert!(matches!(found, BookMatch::Found(id) if id == "b2"));
+        let missing = find(
+            &index,
+            json!({ "title": "Piranesi", "author": "Susanna Clarke" }),
+        );
+        assert!(matches!(missing, BookMatch::Missing));
+    }
+
+    #[test]
+    fn leaves_loose_matches_for_review() {
+        let index = index(&[
+            ("b1", "The Colour of Magic", "Terry Pratchett"),
+            ("b2", "The Color of Magic", "Terry Pratchet"),
+        ]);
+        let found = find(
+            &index,
+            json!({ "title": "The Colour of Magick", "author": "Terry Pratchett" }),
+        );
+        assert!(matches!(found, BookMatch::Ambiguous(candidates) if candidates.len() == 2));
+    }
+}
@@ -0,0 +1,156 @@
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::{book_fields, book_from_row, Book, DatabaseService, EventKind, PUBLISH_EVENT_ABOUT};
+
+/// A list of books a user put together, e.g. "Best of 2024", in the order
+/// the books were added.
+#[derive(Debug, Serialize)]
+pub struct ReadingList {
+    id: String,
+    name: String,
+    description: String,
+    created_at: String,
+    books: Vec<Book>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewReadingList {
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+impl NewReadingList {
+    pub fn is_valid(&self) -> bool {
+        !self.name.trim().is_empty()
+    }
+}
+
+impl DatabaseService {
+    /// Creates a reading list and tells the user's followers about it.
+    pub async fn create_list(&self, user_id: i32, list: &NewReadingList) -> Result<Option<String>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (u:User {{id: $user_id}})
+                    CREATE (u)-[:CREATED]->(about:ReadingList {{
+                        id: randomUUID(),
+                        name: $name,
+                        description: $description,
+                        created_at: datetime()
+                    }})
+                    WITH u, about
+                    {}
+                    RETURN about.id AS id",
+                    PUBLISH_EVENT_ABOUT
+                ))
+                .param("user_id", user_id)
+                .param("name", list.name.trim())
+                .param("description", list.description.as_str())
+                .param("event_type", EventKind::CreatedList.as_str()),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not create list for user with id '{}': {}",
+                    user_id, e
+                );
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(row.get("id").ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the reading lists of a user, oldest first.
+    pub async fn get_lists(&self, user_id: i32) -> Result<Vec<ReadingList>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:User {{id: $user_id}})-[:CREATED]->(l:ReadingList)
+                    OPTIONAL MATCH (l)-[i:INCLUDES]->(b:Book)
+                    RETURN l.id, l.name, l.description, toString(l.created_at) AS created_at,
+                        {}
+                    ORDER BY l.created_at, l.id, i.added_at",
+                    book_fields("b")
+                ))
+                .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch lists of user with id '{}': {}", user_id, e);
+                e
+            })?;
+
+        let mut lists: Vec<ReadingList> = vec![];
+        while let Some(row) = result.next().await? {
+            let id: String = row.get("l.id").unwrap_or_default();
+            if lists.last().is_none_or(|list| list.id != id) {
+                lists.push(ReadingList {
+                    id,
+                    name: row.get("l.name").unwrap_or_default(),
+                    description: row.get("l.description").unwrap_or_default(),
+                    created_at: row.get("created_at").unwrap_or_default(),
+                    books: vec![],
+                });
+            }
+            if row.get::<String>("b.id").is_ok() {
+                if let Some(list) = lists.last_mut() {
+                    list.books.push(book_from_row(&row, "b"));
+                }
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Adds a book to the end of a list. Returns `false` if the list or the
+    /// book does not exist.
+    pub async fn add_book_to_list(&self, list_id: &str, book_id: &str) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (l:ReadingList {id: $list_id}), (b:Book {id: $book_id})
+                    MERGE (l)-[i:INCLUDES]->(b)
+                    ON CREATE SET i.added_at = datetime()
+                    RETURN l.id",
+                )
+                .param("list_id", list_id)
+                .param("book_id", book_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not add book with id '{}' to list with id '{}': {}",
+                    book_id, list_id, e
+                );
+                e
+            })?;
+        Ok(result.next().await?.is_some())
+    }
+
+    pub async fn remove_book_from_list(&self, list_id: &str, book_id: &str) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (:ReadingList {id: $list_id})-[i:INCLUDES]->(:Book {id: $book_id})
+                    DELETE i",
+                )
+                .param("list_id", list_id)
+                .param("book_id", book_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not remove book with id '{}' from list with id '{}': {}",
+                    book_id, list_id, e
+                );
+                e
+            })
+    }
+}
@@ -0,0 +1,301 @@
+use chrono::{Datelike, Utc};
+use isolang::Language;
+use serde::Serialize;
+
+use super::{Book, User};
+use crate::{export::MARC_LANGUAGE_CODES, normalize::normalize_isbn};
+
+const MAX_TITLE_LENGTH: usize = 500;
+const MAX_PUBLISHER_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 10_000;
+const MAX_SUBJECTS: usize = 50;
+const MAX_SUBJECT_LENGTH: usize = 100;
+const MAX_PAGES: i64 = 100_000;
+const MAX_USER_NAME_LENGTH: usize = 100;
+
+/// A validation error for a single field of a submitted book.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    field: &'static str,
+    message: String,
+}
+
+impl FieldError {
+    pub(crate) fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+
+    pub(super) fn field(&self) -> &str {
+        self.field
+    }
+}
+
+/// Normalizes an ISO 639-1, ISO 639-2 or ISO 639-3 code to the two-letter
+/// code where one exists, e.g. "ENG" to "en" and the bibliographic "ger" to
+/// "de".
+fn normalize_language(value: &str) -> Option<String> {
+    let code = value.trim().to_lowercase();
+    let language = match code.len() {
+        2 => Language::from_639_1(&code),
+        3 => Language::from_639_3(&code).or_else(|| {
+            MARC_LANGUAGE_CODES
+                .iter()
+                .find(|(_, marc)| *marc == code)
+                .and_then(|(iso, _)| Language::from_639_3(iso))
+        }),
+        _ => None,
+    }?;
+    Some(
+        language
+            .to_639_1()
+            .unwrap_or_else(|| language.to_639_3())
+            .to_string(),
+    )
+}
+
+/// Trims an optional text field, treating blank values as absent.
+fn trimmed(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+impl Book {
+    /// Checks the submitted fields and normalizes them in place: text is
+    /// trimmed, the ISBN is stored as ISBN-13 and the language as an ISO 639
+    /// code. Returns every invalid field rather than stopping at the first.
+    pub fn validate(&mut self) -> Result<(), Vec<FieldError>> {
+        let mut errors: Vec<FieldError> = vec![];
+
+        self.title = self.title.trim().to_string();
+        if self.title.is_empty() {
+            errors.push(FieldError::new("title", "title is required"));
+        } else if self.title.chars().count() > MAX_TITLE_LENGTH {
+            errors.push(FieldError::new(
+                "title",
+                format!("title must be at most {} characters", MAX_TITLE_LENGTH),
+            ));
+        }
+
+        if let Some(isbn) = trimmed(&self.isbn) {
+            match normalize_isbn(&isbn) {
+                Some(normalized) => self.isbn = Some(normalized),
+                None => errors.push(FieldError::new(
+                    "isbn",
+                    format!("'{}' is not a valid ISBN-10 or ISBN-13", isbn),
+                )),
+            }
+        } else {
+            self.isbn = None;
+        }
+
+        if let Some(year) = self.year {
+            let latest = Utc::now().year() as i64 + 1;
+            if !(-3000..=latest).contains(&year) {
+                errors.push(FieldError::new(
+                    "year",
+                    format!("year must be between -3000 and {}", latest),
+                ));
+            }
+        }
+
+        self.publisher = trimmed(&self.publisher);
+        if let Some(publisher) = &self.publisher {
+            if publisher.chars().count() > MAX_PUBLISHER_LENGTH {
+                errors.push(FieldError::new(
+                    "publisher",
+                    format!(
+                        "publisher must be at most {} characters",
+                        MAX_PUBLISHER_LENGTH
+                    ),
+                ));
+            }
+        }
+
+        if let Some(language) = trimmed(&self.language) {
+            match normalize_language(&language) {
+                Some(code) => self.language = Some(code),
+                None => errors.push(FieldError::new(
+                    "language",
+                    format!("'{}' is not an ISO 639 language code", language),
+                )),
+            }
+        } else {
+            self.language = None;
+        }
+
+        if let Some(pages) = self.pages {
+            if !(1..=MAX_PAGES).contains(&pages) {
+                errors.push(FieldError::new(
+                    "pages",
+                    format!("pages must be between 1 and {}", MAX_PAGES),
+                ));
+            }
+        }
+
+        self.description = trimmed(&self.description);
+        if let Some(description) = &self.description {
+            if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+                errors.push(FieldError::new(
+                    "description",
+                    format!(
+                        "description must be at most {} characters",
+                        MAX_DESCRIPTION_LENGTH
+                    ),
+                ));
+            }
+        }
+
+        let mut subjects: Vec<String> = vec![];
+        for subject in self.subjects.iter().map(|s| s.trim()) {
+            if subject.is_empty() || subjects.iter().any(|s| s.eq_ignore_ascii_case(subject)) {
+                continue;
+            }
+            if subject.chars().count() > MAX_SUBJECT_LENGTH {
+                errors.push(FieldError::new(
+                    "subjects",
+                    format!(
+                        "subject '{}' must be at most {} characters",
+                        subject, MAX_SUBJECT_LENGTH
+                    ),
+                ));
+            }
+            subjects.push(subject.to_string());
+        }
+        if subjects.len() > MAX_SUBJECTS {
+            errors.push(FieldError::new(
+                "subjects",
+                format!("at most {} subjects are allowed", MAX_SUBJECTS),
+            ));
+        }
+        self.subjects = subjects;
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl User {
+    /// Checks the editable fields of a user, trimming the name in place.
+    pub fn validate(&mut self) -> Result<(), Vec<FieldError>> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(vec![FieldError::new("name", "name is required")]);
+        }
+        if self.name.chars().count() > MAX_USER_NAME_LENGTH {
+            return Err(vec![FieldError::new(
+                "name",
+                format!("name must be at most {} characters", MAX_USER_NAME_LENGTH),
+            )]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str) -> Book {
+        Book::new(
+            String::new(),
+            title.to_string(),
+            "Frank Herbert".to_string(),
+            "Science Fiction".to_string(),
+            String::new(),
+            vec![],
+            vec![],
+        )
+    }
+
+    fn invalid_fields(book: &mut Book) -> Vec<String> {
+        match book.validate() {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(|e| e.field().to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn normalizes_language_codes() {
+        for (value, expected) in [
+            ("en", "en"),
+            ("EN", "en"),
+            ("eng", "en"),
+            (" deu ", "de"),
+            ("ger", "de"),
+            ("fre", "fr"),
+            ("chi", "zh"),
+            ("dut", "nl"),
+            ("haw", "haw"),
+        ] {
+            assert_eq!(
+                normalize_language(value).as_deref(),
+                Some(expected),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_language_codes() {
+        for value in ["", "e", "xx", "xyz", "english", "en-US"] {
+            assert_eq!(normalize_language(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn validation_normalizes_fields_in_place() {
+        let mut book = book("  Dune ");
+        book.isbn = Some(" 0-441-17271-7 ".to_string());
+        book.language = Some("ENG".to_string());
+        book.publisher = Some("  ".to_string());
+        book.subjects = vec![
+            " Desert ".to_string(),
+            "desert".to_string(),
+            String::new(),
+            "Politics".to_string(),
+        ];
+
+        assert!(book.validate().is_ok());
+        assert_eq!(book.title, "Dune");
+        assert_eq!(book.isbn.as_deref(), Some("9780441172719"));
+        assert_eq!(book.language.as_deref(), Some("en"));
+        assert_eq!(book.publisher, None);
+        assert_eq!(book.subjects, vec!["Desert", "Politics"]);
+    }
+
+    #[test]
+    fn validation_reports_every_invalid_field() {
+        let mut book = book(" ");
+        book.isbn = Some("0441172718".to_string());
+        book.language = Some("klingon".to_string());
+        book.year = Some(99_999);
+        book.pages = Some(0);
+
+        assert_eq!(
+            invalid_fields(&mut book),
+            vec!["title", "isbn", "year", "language", "pages"]
+        );
+    }
+
+    #[test]
+    fn validation_limits_lengths() {
+        let mut book = book(&"a".repeat(MAX_TITLE_LENGTH + 1));
+        book.description = Some("a".repeat(MAX_DESCRIPTION_LENGTH + 1));
+        book.subjects = (0..=MAX_SUBJECTS).map(|i| i.to_string()).collect();
+
+        assert_eq!(
+            invalid_fields(&mut book),
+            vec!["title", "description", "subjects"]
+        );
+    }
+}
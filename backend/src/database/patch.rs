@@ -0,0 +1,131 @@
+use neo4rs::query;
+use rocket::serde::json::{serde_json, Value};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Book, DatabaseService, FieldError, User};
+use crate::merge_patch;
+
+/// Why a patch was not applied. Database errors are logged where they occur.
+#[derive(Debug)]
+pub enum PatchError {
+    NotFound,
+    /// The entity is no longer at the version the patch was made against.
+    Stale,
+    Invalid(Vec<FieldError>),
+    Database,
+}
+
+impl From<neo4rs::Error> for PatchError {
+    fn from(_: neo4rs::Error) -> Self {
+        PatchError::Database
+    }
+}
+
+/// Applies a merge patch to the JSON form of `current` and reads the result
+/// back, reporting fields of the wrong type as validation errors.
+fn patched<T: Serialize + DeserializeOwned>(current: &T, patch: &Value) -> Result<T, PatchError> {
+    let mut value = serde_json::to_value(current)
+        .map_err(|e| PatchError::Invalid(vec![FieldError::new("body", e.to_string())]))?;
+    merge_patch::apply(&mut value, patch);
+    serde_json::from_value(value)
+        .map_err(|e| PatchError::Invalid(vec![FieldError::new("body", e.to_string())]))
+}
+
+fn patches(patch: &Value, field: &str) -> bool {
+    patch.get(field).is_some()
+}
+
+/// Applies a merge patch to a book and validates the result. Patching
+/// `author` or `genre` alone replaces the book's credits or genre tags with
+/// the new value, as it would when creating the book.
+pub(super) fn patched_book(current: &Book, patch: &Value) -> Result<Book, PatchError> {
+    let mut book: Book = patched(current, patch)?;
+    book.id = current.id.clone();
+    if patches(patch, "author") && !patches(patch, "contributors") {
+        book.contributors.clear();
+    }
+    if patches(patch, "genre") && !patches(patch, "genres") {
+        book.genres.clear();
+    }
+    book.validate().map_err(PatchError::Invalid)?;
+    Ok(book)
+}
+
+impl DatabaseService {
+    /// Changes only the fields of a book named in a JSON Merge Patch. The
+    /// patched book is validated like a full update and only written if the
+    /// book is still at version `expected`.
+    pub async fn patch_book(
+        &self,
+        id: &str,
+        patch: &Value,
+        editor: Option<i32>,
+        expected: i64,
+    ) -> Result<(), PatchError> {
+        let current = self.get_book(id).await.ok_or(PatchError::NotFound)?;
+        let book = patched_book(&current, patch)?;
+        if self
+            .edit_book(&book.id, &book, editor, Some(expected))
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(PatchError::Stale)
+        }
+    }
+
+    /// Changes only the fields of a user named in a JSON Merge Patch. `name`
+    /// and `requires_follow_approval` can be changed; follower counts are
+    /// ignored. All changes are written at once under a single new version,
+    /// and only if the user is still at version `expected`.
+    pub async fn patch_user(
+        &self,
+        id: i32,
+        patch: &Value,
+        expected: i64,
+    ) -> Result<(), PatchError> {
+        let current = self.get_user(id).await.ok_or(PatchError::NotFound)?;
+        let mut user: User = patched(&current, patch)?;
+        user.validate().map_err(PatchError::Invalid)?;
+        if user.name == current.name
+            && user.requires_follow_approval == current.requires_follow_approval
+        {
+            return Ok(());
+        }
+
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id})
+                    WHERE coalesce(u.version, 1) = $expected
+                    SET u.name = $name,
+                        u.requires_follow_approval = $requires_follow_approval,
+                        u.version = coalesce(u.version, 1) + 1
+                    WITH u
+                    CALL {
+                        WITH u
+                        WITH u
+                        WHERE NOT $requires_follow_approval
+                        MATCH (r:User)-[req:REQUESTED_FOLLOW]->(u)
+                        DELETE req
+                        MERGE (r)-[f:FOLLOWS]->(u) ON CREATE SET f.since = datetime()
+                    }
+                    RETURN u.id",
+                )
+                .param("id", id)
+                .param("expected", expected)
+                .param("name", user.name.as_str())
+                .param("requires_follow_approval", user.requires_follow_approval),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not patch user with id '{}': {}", id, e);
+                e
+            })?;
+        match result.next().await? {
+            Some(_) => Ok(()),
+            None => Err(PatchError::Stale),
+        }
+    }
+}
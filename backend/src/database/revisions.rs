@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use neo4rs::*;
+use rocket::serde::json::{serde_json, Value};
+use serde::{Deserialize, Serialize};
+
+use super::{Book, DatabaseService};
+
+/// The fields of a book whose changes are recorded in revisions.
+const TRACKED_FIELDS: [&str; 16] = [
+    "title",
+    "author",
+    "genre",
+    "cover",
+    "isbn",
+    "format",
+    "language",
+    "publisher",
+    "year",
+    "pages",
+    "description",
+    "subjects",
+    "contributors",
+    "genres",
+    "series",
+    "work_id",
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionAction {
+    Created,
+    Updated,
+    Reverted,
+    Merged,
+}
+
+impl RevisionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RevisionAction::Created => "created",
+            RevisionAction::Updated => "updated",
+            RevisionAction::Reverted => "reverted",
+            RevisionAction::Merged => "merged",
+        }
+    }
+}
+
+/// The value of one field before and after a revision. `null` stands for an
+/// unset field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub(super) field: String,
+    pub(super) old: Value,
+    pub(super) new: Value,
+}
+
+/// A recorded change to a book. Revisions are numbered from 1 per book;
+/// `user_id` and `user_name` identify who made the change, if known.
+#[derive(Debug, Serialize)]
+pub struct Revision {
+    number: i64,
+    action: RevisionAction,
+    created_at: String,
+    user_id: Option<i32>,
+    user_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverted_to: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merged_from: Option<String>,
+    changes: Vec<FieldChange>,
+}
+
+fn revision_from_row(row: &Row) -> Result<Revision> {
+    let changes: String = row.get("r.changes").unwrap_or_default();
+    Ok(Revision {
+        number: row.get("r.number").unwrap_or_default(),
+        action: row.get("r.action").map_err(Error::DeserializationError)?,
+        created_at: row.get("created_at").unwrap_or_default(),
+        user_id: row.get("r.user_id").ok(),
+        user_name: row.get("r.user_name").ok(),
+        reverted_to: row.get("r.reverted_to").ok(),
+        merged_from: row.get("r.merged_from").ok(),
+        changes: serde_json::from_str(&changes).unwrap_or_default(),
+    })
+}
+
+/// The tracked fields that differ between two versions of a book.
+pub(super) fn diff(before: Option<&Book>, after: &Book) -> Vec<FieldChange> {
+    let before = before
+        .and_then(|book| serde_json::to_value(book).ok())
+        .unwrap_or(Value::Null);
+    let after = serde_json::to_value(after).unwrap_or(Value::Null);
+    TRACKED_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let old = before.get(field).cloned().unwrap_or(Value::Null);
+            let new = after.get(field).cloned().unwrap_or(Value::Null);
+            (old != new).then(|| FieldChange {
+                field: field.to_string(),
+                old,
+                new,
+            })
+        })
+        .collect()
+}
+
+const REVISION_FIELDS: &str = "r.number, r.action, toString(r.created_at) AS created_at,
+    r.user_id, r.user_name, r.reverted_to, r.merged_from, r.changes";
+
+impl DatabaseService {
+    /// Records a revision of a book by comparing `before` with the book as it
+    /// is stored now. Nothing is recorded if no tracked field changed, except
+    /// for merges, which always leave a record.
+    pub(super) async fn record_revision(
+        &self,
+        book_id: &str,
+        action: RevisionAction,
+        before: Option<&Book>,
+        editor: Option<i32>,
+        reverted_to: Option<i64>,
+        merged_from: Option<&str>,
+    ) -> Result<()> {
+        let Some(after) = self.get_book(book_id).await else {
+            return Ok(());
+        };
+        let changes = diff(before, &after);
+        if changes.is_empty() && !matches!(action, RevisionAction::Merged) {
+            return Ok(());
+        }
+        self.graph
+            .run(
+                query(
+                    "MATCH (b:Book {id: $book_id})
+                    OPTIONAL MATCH (u:User {id: $user_id})
+                    WITH b, u, COUNT { (b)-[:HAS_REVISION]->(:Revision) } AS previous
+                    CREATE (b)-[:HAS_REVISION]->(:Revision {
+                        id: randomUUID(),
+                        number: previous + 1,
+                        action: $action,
+                        created_at: datetime(),
+                        user_id: $user_id,
+                        user_name: u.name,
+                        reverted_to: $reverted_to,
+                        merged_from: $merged_from,
+                        changes: $changes
+                    })",
+                )
+                .param("book_id", book_id)
+                .param("user_id", editor)
+                .param("action", action.as_str())
+                .param("reverted_to", reverted_to)
+                .param("merged_from", merged_from)
+                .param(
+                    "changes",
+                    serde_json::to_string(&changes).unwrap_or_default(),
+                ),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not record revision of book with id '{}': {}",
+                    book_id, e
+                );
+                e
+            })
+    }
+
+    /// Records a revision with changes worked out beforehand for each of
+    /// many books, e.g. books filled in by a bulk load, in a single query.
+    pub(super) async fn record_revisions(
+        &self,
+        action: RevisionAction,
+        revisions: Vec<(String, Vec<FieldChange>)>,
+    ) -> Result<()> {
+        if revisions.is_empty() {
+            return Ok(());
+        }
+        let revisions: Vec<HashMap<&str, BoltType>> = revisions
+            .into_iter()
+            .map(|(book_id, changes)| {
+                HashMap::from([
+                    ("book_id", BoltType::from(book_id)),
+                    (
+                        "changes",
+                        BoltType::from(serde_json::to_string(&changes).unwrap_or_default()),
+                    ),
+                ])
+            })
+            .collect();
+        self.graph
+            .run(
+                query(
+                    "UNWIND $revisions AS revision
+                    MATCH (b:Book {id: revision.book_id})
+                    WITH b, revision, COUNT { (b)-[:HAS_REVISION]->(:Revision) } AS previous
+                    CREATE (b)-[:HAS_REVISION]->(:Revision {
+                        id: randomUUID(),
+                        number: previous + 1,
+                        action: $action,
+                        created_at: datetime(),
+                        changes: revision.changes
+                    })",
+                )
+                .param("action", action.as_str())
+                .param("revisions", revisions),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not record revisions: {}", e);
+                e
+            })
+    }
+
+    /// Returns the revisions of a book, newest first.
+    pub async fn get_book_revisions(&self, id: &str) -> Result<Vec<Revision>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:Book {{id: $id}})-[:HAS_REVISION]->(r:Revision)
+                    RETURN {}
+                    ORDER BY r.number DESC",
+                    REVISION_FIELDS
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch revisions of book with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut revisions: Vec<Revision> = vec![];
+        while let Some(row) = result.next().await? {
+            revisions.push(revision_from_row(&row)?);
+        }
+        Ok(revisions)
+    }
+
+    pub async fn get_book_revision(&self, id: &str, number: i64) -> Result<Option<Revision>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:Book {{id: $id}})-[:HAS_REVISION]->(r:Revision {{number: $number}})
+                    RETURN {}",
+                    REVISION_FIELDS
+                ))
+                .param("id", id)
+                .param("number", number),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch revision {} of book with id '{}': {}",
+                    number, id, e
+                );
+                e
+            })?;
+
+        match result.next().await? {
+            Some(row) => Ok(Some(revision_from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Restores a book to its state right after revision `number` by undoing
+    /// the changes of every later revision. The revert is itself recorded as
+    /// a revision. Returns `false` if the book or revision does not exist and
+    /// `None` if the book is no longer at version `expected`.
+    pub async fn revert_book(
+        &self,
+        id: &str,
+        number: i64,
+        editor: Option<i32>,
+        expected: i64,
+    ) -> Result<Option<bool>> {
+        let Some(current) = self.get_book(id).await else {
+            return Ok(Some(false));
+        };
+        let revisions = self.get_book_revisions(id).await?;
+        if !revisions.iter().any(|revision| revision.number == number) {
+            return Ok(Some(false));
+        }
+
+        let Ok(mut value) = serde_json::to_value(&current) else {
+            return Ok(Some(false));
+        };
+        for revision in revisions.iter().filter(|revision| revision.number > number) {
+            for change in &revision.changes {
+                value[change.field.as_str()] = change.old.clone();
+            }
+        }
+        let Ok(target) = serde_json::from_value::<Book>(value) else {
+            return Ok(Some(false));
+        };
+
+        if !self.write_book(id, &target, Some(expected)).await? {
+            return Ok(None);
+        }
+        self.record_revision(
+            id,
+            RevisionAction::Reverted,
+            Some(&current),
+            editor,
+            Some(number),
+            None,
+        )
+        .await?;
+        Ok(Some(true))
+    }
+}
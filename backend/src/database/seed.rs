@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use neo4rs::*;
+use rocket::serde::json::{serde_json, Value};
+use serde::Serialize;
+
+use super::{
+    authors::contributor_links,
+    genres::genre_links,
+    import::new_book,
+    revisions::{FieldChange, RevisionAction},
+    series::series_links,
+    works::work_link,
+    Book, DatabaseService, FieldError, ImportError,
+};
+
+/// The fields a seed fills in on a book that already exists but lacks them.
+const ENRICHED_FIELDS: [&str; 9] = [
+    "cover",
+    "isbn",
+    "format",
+    "language",
+    "publisher",
+    "year",
+    "pages",
+    "description",
+    "subjects",
+];
+
+/// What seeding one batch did. Books already in the catalog, found by source
+/// key or ISBN, only have their missing fields filled in.
+#[derive(Debug, Default, Serialize)]
+pub struct SeedReport {
+    created: usize,
+    enriched: usize,
+    unchanged: usize,
+    invalid: Vec<InvalidSeed>,
+}
+
+/// A book of a batch that was left out, by its position in the batch.
+#[derive(Debug, Serialize)]
+pub struct InvalidSeed {
+    index: usize,
+    errors: Vec<FieldError>,
+}
+
+fn seed_row(key: &str, book: &Book) -> HashMap<&'static str, BoltType> {
+    HashMap::from([
+        ("key", BoltType::from(key)),
+        ("title", BoltType::from(book.title.as_str())),
+        ("author", BoltType::from(book.credited_author())),
+        ("genre", BoltType::from(book.primary_genre())),
+        ("cover", BoltType::from(book.cover.as_str())),
+        ("isbn", BoltType::from(book.isbn.clone())),
+        ("format", BoltType::from(book.format.map(|f| f.as_str()))),
+        ("language", BoltType::from(book.language.clone())),
+        ("publisher", BoltType::from(book.publisher.clone())),
+        ("year", BoltType::from(book.year)),
+        ("pages", BoltType::from(book.pages)),
+        ("description", BoltType::from(book.description.clone())),
+        ("subjects", BoltType::from(book.subjects.clone())),
+    ])
+}
+
+/// Reads a book of a batch. Dumps have the odd malformed ISBN; such a book
+/// is seeded without it rather than left out.
+fn seed_book(row: &Value) -> Result<Book, ImportError> {
+    match new_book(row) {
+        Err(ImportError::Invalid(errors)) if errors.iter().all(|e| e.field() == "isbn") => {
+            let mut row = row.clone();
+            if let Some(fields) = row.as_object_mut() {
+                fields.remove("isbn");
+            }
+            new_book(&row)
+        }
+        book => book,
+    }
+}
+
+fn is_blank(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(text) => text.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        _ => false,
+    }
+}
+
+/// The changes made by filling in the fields an existing book lacked, given
+/// the values it had `before`. These follow the `SET` of
+/// [`DatabaseService::seed_books`].
+fn enrichment(before: &Value, book: &Book) -> Vec<FieldChange> {
+    let after = serde_json::to_value(book).unwrap_or_default();
+    ENRICHED_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let old = before.get(field).cloned().unwrap_or(Value::Null);
+            let new = after.get(field).cloned().unwrap_or(Value::Null);
+            (is_blank(&old) && !is_blank(&new)).then(|| FieldChange {
+                field: field.to_string(),
+                old,
+                new,
+            })
+        })
+        .collect()
+}
+
+impl DatabaseService {
+    /// Writes a batch of books from an external catalog dump in a single
+    /// query. Each row holds the JSON fields of a book and the `source_key`
+    /// of its record, so loading the same dump again finds the books it
+    /// created. New books are linked to authors, genres, series and works in
+    /// bulk; unlike books added one at a time they start without a revision.
+    /// Existing books that get missing fields filled in get a revision
+    /// listing those fields.
+    pub async fn seed_books(&self, rows: &[Value]) -> Result<SeedReport> {
+        let mut report = SeedReport::default();
+        let mut books: HashMap<String, Book> = HashMap::new();
+        let mut params: Vec<HashMap<&str, BoltType>> = vec![];
+        for (index, row) in rows.iter().enumerate() {
+            let key = row
+                .get("source_key")
+                .and_then(Value::as_str)
+                .filter(|key| !key.is_empty());
+            let book = match key {
+                Some(_) => seed_book(row),
+                None => Err(ImportError::Invalid(vec![FieldError::new(
+                    "source_key",
+                    "a seeded book needs the key of its source record",
+                )])),
+            };
+            match (key, book) {
+                (Some(key), Ok(book)) => {
+                    params.push(seed_row(key, &book));
+                    books.insert(key.to_string(), book);
+                }
+                (_, Err(ImportError::Invalid(errors))) => {
+                    report.invalid.push(InvalidSeed { index, errors })
+                }
+                _ => {}
+            }
+        }
+        if params.is_empty() {
+            return Ok(report);
+        }
+
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "UNWIND $rows AS row
+                    CALL {
+                        WITH row
+                        OPTIONAL MATCH (:SourceRecord {key: row.key})-[:DESCRIBES]->(b:Book)
+                        RETURN b AS known
+                        LIMIT 1
+                    }
+                    CALL {
+                        WITH row, known
+                        OPTIONAL MATCH (b:Book {isbn: row.isbn})
+                        WHERE known IS NULL
+                        RETURN b AS same
+                        ORDER BY b.created_at
+                        LIMIT 1
+                    }
+                    WITH row, coalesce(known, same) AS existing
+                    CALL {
+                        WITH row, existing
+                        WITH row, existing WHERE existing IS NULL
+                        CREATE (b:Book {id: randomUUID(), title: row.title, author: row.author,
+                            genre: row.genre, cover: row.cover, isbn: row.isbn, format: row.format,
+                            language: row.language, publisher: row.publisher, year: row.year,
+                            pages: row.pages, description: row.description, subjects: row.subjects,
+                            created_at: datetime(), version: 1})
+                        RETURN b, 'created' AS outcome, NULL AS before
+                        UNION
+                        WITH row, existing
+                        WITH row, existing WHERE existing IS NOT NULL
+                        WITH row, existing AS b,
+                            existing {.cover, .isbn, .format, .language, .publisher, .year,
+                                .pages, .description, .subjects} AS before,
+                            (coalesce(existing.cover, '') = '' AND row.cover <> '')
+                            OR (existing.isbn IS NULL AND row.isbn IS NOT NULL)
+                            OR (existing.format IS NULL AND row.format IS NOT NULL)
+                            OR (existing.language IS NULL AND row.language IS NOT NULL)
+                            OR (existing.publisher IS NULL AND row.publisher IS NOT NULL)
+                            OR (existing.year IS NULL AND row.year IS NOT NULL)
+                            OR (existing.pages IS NULL AND row.pages IS NOT NULL)
+                            OR (existing.description IS NULL AND row.description IS NOT NULL)
+                            OR (size(coalesce(existing.subjects, [])) = 0 AND size(row.subjects) > 0)
+                            AS enriched
+                        SET b.cover = CASE WHEN coalesce(b.cover, '') = '' THEN row.cover ELSE b.cover END,
+                            b.isbn = coalesce(b.isbn, row.isbn),
+                            b.format = coalesce(b.format, row.format),
+                            b.language = coalesce(b.language, row.language),
+                            b.publisher = coalesce(b.publisher, row.publisher),
+                            b.year = coalesce(b.year, row.year),
+                            b.pages = coalesce(b.pages, row.pages),
+                            b.description = coalesce(b.description, row.description),
+                            b.subjects = CASE WHEN size(coalesce(b.subjects, [])) = 0
+                                THEN row.subjects ELSE b.subjects END,
+                            b.version = coalesce(b.version, 1) + CASE WHEN enriched THEN 1 ELSE 0 END
+                        RETURN b, CASE WHEN enriched THEN 'enriched' ELSE 'unchanged' END AS outcome,
+                            before
+                    }
+                    MERGE (s:SourceRecord {key: row.key})
+                    WITH row, b, outcome, before, s
+                    OPTIONAL MATCH (s)-[old:DESCRIBES]->(other)
+                    WHERE other <> b
+                    DELETE old
+                    MERGE (s)-[:DESCRIBES]->(b)
+                    RETURN DISTINCT row.key AS key, b.id AS id, outcome, before",
+                )
+                .param("rows", params),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not seed books: {}", e);
+                e
+            })?;
+
+        let mut contributors = vec![];
+        let mut genres = vec![];
+        let mut series = vec![];
+        let mut works = vec![];
+        let mut revisions = vec![];
+        while let Some(row) = result.next().await? {
+            let key: String = row.get("key").map_err(Error::DeserializationError)?;
+            let id: String = row.get("id").map_err(Error::DeserializationError)?;
+            let outcome: String = row.get("outcome").map_err(Error::DeserializationError)?;
+            match outcome.as_str() {
+                "created" => report.created += 1,
+                "enriched" => report.enriched += 1,
+                _ => report.unchanged += 1,
+            }
+            match (books.get(&key), outcome.as_str()) {
+                (Some(book), "created") => {
+                    contributors.extend(contributor_links(&id, book));
+                    genres.extend(genre_links(&id, book));
+                    series.extend(series_links(&id, book));
+                    works.push(work_link(&id, &book.title, &book.credited_author()));
+                }
+                (Some(book), "enriched") => {
+                    let before: Value = row.get("before").unwrap_or_default();
+                    revisions.push((id, enrichment(&before, book)));
+                }
+                _ => {}
+            }
+        }
+        self.record_revisions(RevisionAction::Updated, revisions)
+            .await?;
+        self.write_contributor_links(contributors).await?;
+        self.write_genre_links(genres).await?;
+        self.write_series_links(series).await?;
+        self.write_work_links(works).await?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json::json;
+
+    use super::*;
+
+    #[test]
+    fn seeds_books_with_a_malformed_isbn_without_it() {
+        let book = seed_book(&json!({
+            "title": "Dune",
+            "author": "Frank Herbert",
+            "isbn": "0441172718",
+        }))
+        .unwrap();
+        assert_eq!(book.isbn, None);
+        assert!(seed_book(&json!({ "author": "Frank Herbert", "isbn": "0441172718" })).is_err());
+    }
+
+    #[test]
+    fn lists_the_fields_an_enrichment_fills_in() {
+        let book = seed_book(&json!({
+            "title": "Dune",
+            "author": "Frank Herbert",
+            "cover": "https://covers.example.com/dune.jpg",
+            "isbn": "0441172717",
+            "pages": 412,
+            "subjects": ["science fiction"],
+        }))
+        .unwrap();
+        let before = json!({ "cover": "", "isbn": null, "pages": 400, "subjects": [] });
+        let fields: Vec<String> = enrichment(&before, &book)
+            .into_iter()
+            .map(|change| change.field)
+            .collect();
+        assert_eq!(fields, ["cover", "isbn", "subjects"]);
+    }
+}
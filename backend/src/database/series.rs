@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::works::has_read_work;
+use super::{book_fields, book_from_row, Book, DatabaseService};
+use crate::normalize::series_key;
+
+/// A book's place in a series through a `PART_OF` relationship. Volumes may
+/// be fractional, e.g. 1.5 for a novella set between the first two books.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    series_id: Option<String>,
+    name: String,
+    volume: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Series {
+    id: String,
+    name: String,
+    books: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesVolume {
+    volume: f64,
+    #[serde(flatten)]
+    book: Book,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesDetail {
+    id: String,
+    name: String,
+    books: Vec<SeriesVolume>,
+}
+
+/// A `WHERE` predicate that rejects the book bound to `book_var` if it is a
+/// later volume of a series in which the user bound to `user_var` has not
+/// read any edition of an earlier volume yet.
+pub(super) fn reading_order_filter(user_var: &str, book_var: &str) -> String {
+    format!(
+        "NOT EXISTS {{
+            MATCH ({b})-[later:PART_OF]->(:Series)<-[earlier:PART_OF]-(previous:Book)
+            WHERE earlier.volume < later.volume AND NOT {}
+        }}",
+        has_read_work(user_var, "previous"),
+        b = book_var
+    )
+}
+
+pub(super) fn series_links(book_id: &str, book: &Book) -> Vec<HashMap<&'static str, BoltType>> {
+    book.series
+        .iter()
+        .filter(|entry| !series_key(&entry.name).is_empty())
+        .map(|entry| {
+            HashMap::from([
+                ("book_id", BoltType::from(book_id)),
+                ("key", BoltType::from(series_key(&entry.name))),
+                ("name", BoltType::from(entry.name.trim())),
+                ("volume", BoltType::from(entry.volume)),
+            ])
+        })
+        .collect()
+}
+
+impl DatabaseService {
+    /// Replaces the `PART_OF` relationships of a book. Unknown series are
+    /// created.
+    pub(super) async fn link_series(&self, book_id: &str, book: &Book) -> Result<()> {
+        self.graph
+            .run(
+                query("MATCH (:Book {id: $book_id})-[p:PART_OF]->(:Series) DELETE p")
+                    .param("book_id", book_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not unlink series of book with id '{}': {}",
+                    book_id, e
+                );
+                e
+            })?;
+
+        self.write_series_links(series_links(book_id, book)).await
+    }
+
+    pub(super) async fn write_series_links(
+        &self,
+        links: Vec<HashMap<&str, BoltType>>,
+    ) -> Result<()> {
+        if links.is_empty() {
+            return Ok(());
+        }
+        self.graph
+            .run(
+                query(
+                    "UNWIND $links AS link
+                    MATCH (b:Book {id: link.book_id})
+                    MERGE (s:Series {key: link.key})
+                    ON CREATE SET s.id = randomUUID(), s.name = link.name
+                    MERGE (b)-[p:PART_OF]->(s)
+                    SET p.volume = link.volume",
+                )
+                .param("links", links),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not link books to series: {}", e);
+                e
+            })
+    }
+
+    pub async fn get_all_series(&self) -> Result<Vec<Series>> {
+        let mut result = self
+            .graph
+            .execute(query(
+                "MATCH (s:Series)
+                RETURN s.id, s.name, COUNT { (s)<-[:PART_OF]-(:Book) } AS books
+                ORDER BY s.name",
+            ))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch series: {}", e);
+                e
+            })?;
+
+        let mut series: Vec<Series> = vec![];
+        while let Some(row) = result.next().await? {
+            series.push(Series {
+                id: row.get("s.id").unwrap_or_default(),
+                name: row.get("s.name").unwrap_or_default(),
+                books: row.get("books").unwrap_or_default(),
+            });
+        }
+        Ok(series)
+    }
+
+    /// Returns a series with its books in reading order.
+    pub async fn get_series(&self, id: &str) -> Result<Option<SeriesDetail>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (s:Series {{id: $id}})
+                    OPTIONAL MATCH (b:Book)-[p:PART_OF]->(s)
+                    RETURN s.id, s.name, p.volume AS volume, {}
+                    ORDER BY p.volume, b.title",
+                    book_fields("b")
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch series with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut series: Option<SeriesDetail> = None;
+        while let Some(row) = result.next().await? {
+            let detail = series.get_or_insert_with(|| SeriesDetail {
+                id: row.get("s.id").unwrap_or_default(),
+                name: row.get("s.name").unwrap_or_default(),
+                books: vec![],
+            });
+            if let Ok(volume) = row.get::<f64>("volume") {
+                detail.books.push(SeriesVolume {
+                    volume,
+                    book: book_from_row(&row, "b"),
+                });
+            }
+        }
+        Ok(series)
+    }
+}
@@ -0,0 +1,87 @@
+use neo4rs::*;
+use serde::Serialize;
+
+use super::{book_fields, book_from_row, Book, DatabaseService};
+
+/// A list a user keeps their reads on, as kept in the `shelves` of their
+/// `HAS_READ` edges.
+#[derive(Debug, Serialize)]
+pub struct Shelf {
+    name: String,
+    books: i64,
+}
+
+impl Shelf {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn books(&self) -> i64 {
+        self.books
+    }
+}
+
+impl DatabaseService {
+    /// Returns the shelves of a user by name.
+    pub async fn get_shelves(&self, user_id: i32) -> Result<Vec<Shelf>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $user_id})-[r:HAS_READ]->(:Book)
+                    UNWIND coalesce(r.shelves, []) AS shelf
+                    RETURN shelf, count(*) AS books
+                    ORDER BY shelf",
+                )
+                .param("user_id", user_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch shelves of user with id '{}': {}",
+                    user_id, e
+                );
+                e
+            })?;
+
+        let mut shelves: Vec<Shelf> = vec![];
+        while let Some(row) = result.next().await? {
+            shelves.push(Shelf {
+                name: row.get("shelf").unwrap_or_default(),
+                books: row.get("books").unwrap_or_default(),
+            });
+        }
+        Ok(shelves)
+    }
+
+    /// Returns the books on one of a user's shelves, most recently read first.
+    pub async fn get_shelf_books(&self, user_id: i32, shelf: &str) -> Result<Vec<Book>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (:User {{id: $user_id}})-[r:HAS_READ]->(b:Book)
+                    WHERE $shelf IN coalesce(r.shelves, [])
+                    RETURN {}
+                    ORDER BY r.read_at DESC, b.title",
+                    book_fields("b")
+                ))
+                .param("user_id", user_id)
+                .param("shelf", shelf),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch shelf '{}' of user with id '{}': {}",
+                    shelf, user_id, e
+                );
+                e
+            })?;
+
+        let mut books: Vec<Book> = vec![];
+        while let Some(row) = result.next().await? {
+            books.push(book_from_row(&row, "b"));
+        }
+        Ok(books)
+    }
+}
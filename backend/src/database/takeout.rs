@@ -0,0 +1,361 @@
+use neo4rs::*;
+use serde::Serialize;
+
+use super::{Challenge, DatabaseService, ReadingList};
+
+/// Everything the graph holds about one user, for a personal data export.
+/// Dates are ISO 8601 strings.
+#[derive(Debug, Serialize)]
+pub struct PersonalData {
+    pub profile: Profile,
+    pub reads: Vec<ReadRecord>,
+    pub connections: Vec<Connection>,
+    pub followed_authors: Vec<FollowedAuthor>,
+    pub clubs: Vec<ClubMembership>,
+    pub posts: Vec<PostRecord>,
+    pub activity: Vec<ActivityRecord>,
+    pub lists: Vec<ReadingList>,
+    pub challenges: Vec<Challenge>,
+}
+
+/// The properties of the `User` node.
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub id: i32,
+    pub name: String,
+    pub requires_follow_approval: bool,
+    pub muted_event_types: Vec<String>,
+    pub version: i64,
+}
+
+/// A `HAS_READ` edge with the book it points to. `shelves` are the lists
+/// the book is on, kept from imports.
+#[derive(Debug, Serialize)]
+pub struct ReadRecord {
+    pub book_id: String,
+    pub title: String,
+    pub author: String,
+    pub read_at: Option<String>,
+    pub rating: Option<i64>,
+    pub rated_at: Option<String>,
+    pub review: Option<String>,
+    pub reviewed_at: Option<String>,
+    pub shelves: Vec<String>,
+    pub imported_from: Option<String>,
+}
+
+/// Another user the user is related to: `following`, `follower`,
+/// `requested` (a pending request of the user), `requested_by` or `muted`.
+#[derive(Debug, Serialize)]
+pub struct Connection {
+    pub relation: String,
+    pub user_id: i32,
+    pub name: String,
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FollowedAuthor {
+    pub author_id: String,
+    pub name: String,
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClubMembership {
+    pub club_id: String,
+    pub name: String,
+    pub role: String,
+    pub joined_at: Option<String>,
+}
+
+/// A post of the user in a club's discussion thread.
+#[derive(Debug, Serialize)]
+pub struct PostRecord {
+    pub id: String,
+    pub club_id: String,
+    pub section_id: String,
+    pub body: String,
+    pub created_at: Option<String>,
+}
+
+/// An event the user published to their followers' feeds, with the book,
+/// reading list or challenge it is about.
+#[derive(Debug, Serialize)]
+pub struct ActivityRecord {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub book_id: Option<String>,
+    pub list_id: Option<String>,
+    pub challenge_id: Option<String>,
+    pub rating: Option<i64>,
+    pub review: Option<String>,
+    pub created_at: Option<String>,
+}
+
+impl DatabaseService {
+    /// Collects the personal data of a user, or `None` if the user does not
+    /// exist.
+    pub async fn get_personal_data(&self, id: i32) -> Result<Option<PersonalData>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id})
+                    RETURN u.id, u.name,
+                        coalesce(u.requires_follow_approval, false) AS requires_follow_approval,
+                        coalesce(u.muted_event_types, []) AS muted_event_types,
+                        coalesce(u.version, 1) AS version",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch profile of user with id '{}': {}", id, e);
+                e
+            })?;
+        let Some(row) = result.next().await? else {
+            return Ok(None);
+        };
+        let profile = Profile {
+            id: row.get("u.id").unwrap_or_default(),
+            name: row.get("u.name").unwrap_or_default(),
+            requires_follow_approval: row.get("requires_follow_approval").unwrap_or_default(),
+            muted_event_types: row.get("muted_event_types").unwrap_or_default(),
+            version: row.get("version").unwrap_or_default(),
+        };
+
+        Ok(Some(PersonalData {
+            profile,
+            reads: self.get_read_records(id).await?,
+            connections: self.get_connections(id).await?,
+            followed_authors: self.get_followed_authors(id).await?,
+            clubs: self.get_club_memberships(id).await?,
+            posts: self.get_post_records(id).await?,
+            activity: self.get_activity_records(id).await?,
+            lists: self.get_lists(id).await?,
+            challenges: self.get_challenges(id).await?,
+        }))
+    }
+
+    async fn get_read_records(&self, id: i32) -> Result<Vec<ReadRecord>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $id})-[r:HAS_READ]->(b:Book)
+                    RETURN b.id, b.title, b.author, toString(r.read_at) AS read_at, r.rating,
+                        toString(r.rated_at) AS rated_at, r.review,
+                        toString(r.reviewed_at) AS reviewed_at,
+                        coalesce(r.shelves, []) AS shelves, r.imported_from
+                    ORDER BY r.read_at, b.title",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch reads of user with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut reads = vec![];
+        while let Some(row) = result.next().await? {
+            reads.push(ReadRecord {
+                book_id: row.get("b.id").unwrap_or_default(),
+                title: row.get("b.title").unwrap_or_default(),
+                author: row.get("b.author").unwrap_or_default(),
+                read_at: row.get("read_at").ok(),
+                rating: row.get("r.rating").ok(),
+                rated_at: row.get("rated_at").ok(),
+                review: row.get("r.review").ok(),
+                reviewed_at: row.get("reviewed_at").ok(),
+                shelves: row.get("shelves").unwrap_or_default(),
+                imported_from: row.get("r.imported_from").ok(),
+            });
+        }
+        Ok(reads)
+    }
+
+    async fn get_connections(&self, id: i32) -> Result<Vec<Connection>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id})
+                    CALL {
+                        WITH u
+                        MATCH (u)-[r:FOLLOWS]->(other:User)
+                        RETURN 'following' AS relation, other, r.since AS since
+                        UNION
+                        WITH u
+                        MATCH (u)<-[r:FOLLOWS]-(other:User)
+                        RETURN 'follower' AS relation, other, r.since AS since
+                        UNION
+                        WITH u
+                        MATCH (u)-[r:REQUESTED_FOLLOW]->(other:User)
+                        RETURN 'requested' AS relation, other, r.requested_at AS since
+                        UNION
+                        WITH u
+                        MATCH (u)<-[r:REQUESTED_FOLLOW]-(other:User)
+                        RETURN 'requested_by' AS relation, other, r.requested_at AS since
+                        UNION
+                        WITH u
+                        MATCH (u)-[:MUTES]->(other:User)
+                        RETURN 'muted' AS relation, other, NULL AS since
+                    }
+                    RETURN relation, other.id AS user_id, other.name AS name,
+                        toString(since) AS since
+                    ORDER BY relation, name",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch connections of user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        let mut connections = vec![];
+        while let Some(row) = result.next().await? {
+            connections.push(Connection {
+                relation: row.get("relation").unwrap_or_default(),
+                user_id: row.get("user_id").unwrap_or_default(),
+                name: row.get("name").unwrap_or_default(),
+                since: row.get("since").ok(),
+            });
+        }
+        Ok(connections)
+    }
+
+    async fn get_followed_authors(&self, id: i32) -> Result<Vec<FollowedAuthor>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $id})-[f:FOLLOWS]->(a:Author)
+                    RETURN a.id, a.name, toString(f.since) AS since
+                    ORDER BY a.name",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not fetch followed authors of user with id '{}': {}",
+                    id, e
+                );
+                e
+            })?;
+
+        let mut authors = vec![];
+        while let Some(row) = result.next().await? {
+            authors.push(FollowedAuthor {
+                author_id: row.get("a.id").unwrap_or_default(),
+                name: row.get("a.name").unwrap_or_default(),
+                since: row.get("since").ok(),
+            });
+        }
+        Ok(authors)
+    }
+
+    async fn get_club_memberships(&self, id: i32) -> Result<Vec<ClubMembership>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $id})-[m:MEMBER_OF]->(c:Club)
+                    RETURN c.id, c.name, m.role, toString(m.joined_at) AS joined_at
+                    ORDER BY m.joined_at",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch clubs of user with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut clubs = vec![];
+        while let Some(row) = result.next().await? {
+            clubs.push(ClubMembership {
+                club_id: row.get("c.id").unwrap_or_default(),
+                name: row.get("c.name").unwrap_or_default(),
+                role: row.get("m.role").unwrap_or_default(),
+                joined_at: row.get("joined_at").ok(),
+            });
+        }
+        Ok(clubs)
+    }
+
+    async fn get_post_records(&self, id: i32) -> Result<Vec<PostRecord>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $id})-[:POSTED]->(p:Post)-[:IN_SECTION]->(s:Section)
+                        <-[:HAS_SECTION]-(c:Club)
+                    RETURN p.id, c.id, s.id, p.body, toString(p.created_at) AS created_at
+                    ORDER BY p.created_at",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch posts of user with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut posts = vec![];
+        while let Some(row) = result.next().await? {
+            posts.push(PostRecord {
+                id: row.get("p.id").unwrap_or_default(),
+                club_id: row.get("c.id").unwrap_or_default(),
+                section_id: row.get("s.id").unwrap_or_default(),
+                body: row.get("p.body").unwrap_or_default(),
+                created_at: row.get("created_at").ok(),
+            });
+        }
+        Ok(posts)
+    }
+
+    async fn get_activity_records(&self, id: i32) -> Result<Vec<ActivityRecord>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (:User {id: $id})-[:PERFORMED]->(e:Event)
+                    OPTIONAL MATCH (e)-[:ABOUT]->(b:Book)
+                    OPTIONAL MATCH (e)-[:ABOUT]->(l:ReadingList)
+                    OPTIONAL MATCH (e)-[:ABOUT]->(c:Challenge)
+                    RETURN e.id, e.type, b.id, l.id, c.id, e.rating, e.review,
+                        toString(e.created_at) AS created_at
+                    ORDER BY e.created_at",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch activity of user with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut activity = vec![];
+        while let Some(row) = result.next().await? {
+            activity.push(ActivityRecord {
+                id: row.get("e.id").unwrap_or_default(),
+                event_type: row.get("e.type").unwrap_or_default(),
+                book_id: row.get("b.id").ok(),
+                list_id: row.get("l.id").ok(),
+                challenge_id: row.get("c.id").ok(),
+                rating: row.get("e.rating").ok(),
+                review: row.get("e.review").ok(),
+                created_at: row.get("created_at").ok(),
+            });
+        }
+        Ok(activity)
+    }
+}
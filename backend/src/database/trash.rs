@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use neo4rs::*;
+use serde::Serialize;
+
+use super::{book_fields, book_from_row, user_from_row, Book, DatabaseService, User, USER_FIELDS};
+
+/// How long deleted books and users stay in the trash before they are purged.
+const TRASH_RETENTION_DAYS: i64 = 30;
+
+/// How often the trash is checked for items past their retention window.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A deleted book or user with the time it was deleted and the time it will
+/// be purged unless restored.
+#[derive(Debug, Serialize)]
+pub struct Trashed<T> {
+    #[serde(flatten)]
+    item: T,
+    deleted_at: String,
+    purge_at: String,
+}
+
+const TRASH_FIELDS: &str = "toString(n.deleted_at) AS deleted_at,
+    toString(n.deleted_at + duration({days: $retention})) AS purge_at";
+
+fn trashed<T>(row: &Row, item: T) -> Trashed<T> {
+    Trashed {
+        item,
+        deleted_at: row.get("deleted_at").unwrap_or_default(),
+        purge_at: row.get("purge_at").unwrap_or_default(),
+    }
+}
+
+impl DatabaseService {
+    /// Moves a book to the trash. The book becomes a `DeletedBook`, which hides
+    /// it from listings and recommendations but keeps its reads, credits and
+    /// other relationships so it can be restored. Returns `false` if the book
+    /// is no longer at version `expected`.
+    pub async fn delete_book(&self, id: &str, expected: i64) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (b:Book {id: $id})
+                    WHERE coalesce(b.version, 1) = $expected
+                    REMOVE b:Book
+                    SET b:DeletedBook, b.deleted_at = datetime()
+                    RETURN b.id",
+                )
+                .param("id", id)
+                .param("expected", expected),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not delete book with id '{}': {}", id, e);
+                e
+            })?;
+        Ok(result.next().await?.is_some())
+    }
+
+    /// Moves a user to the trash as a `DeletedUser`, keeping their reads and
+    /// follows so they can be restored. Returns `false` if the user is no
+    /// longer at version `expected`.
+    pub async fn delete_user(&self, id: i32, expected: i64) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:User {id: $id})
+                    WHERE coalesce(u.version, 1) = $expected
+                    REMOVE u:User
+                    SET u:DeletedUser, u.deleted_at = datetime()
+                    RETURN u.id",
+                )
+                .param("id", id)
+                .param("expected", expected),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not delete user with id '{}': {}", id, e);
+                e
+            })?;
+        Ok(result.next().await?.is_some())
+    }
+
+    pub async fn get_deleted_books(&self) -> Result<Vec<Trashed<Book>>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (b:DeletedBook)
+                    WITH b, b AS n
+                    RETURN {}, {}
+                    ORDER BY b.deleted_at DESC",
+                    book_fields("b"),
+                    TRASH_FIELDS
+                ))
+                .param("retention", TRASH_RETENTION_DAYS),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch deleted books: {}", e);
+                e
+            })?;
+
+        let mut books: Vec<Trashed<Book>> = vec![];
+        while let Some(row) = result.next().await? {
+            books.push(trashed(&row, book_from_row(&row, "b")));
+        }
+        Ok(books)
+    }
+
+    pub async fn get_deleted_users(&self) -> Result<Vec<Trashed<User>>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (u:DeletedUser)
+                    WITH u, u AS n
+                    RETURN {}, {}
+                    ORDER BY u.deleted_at DESC",
+                    USER_FIELDS, TRASH_FIELDS
+                ))
+                .param("retention", TRASH_RETENTION_DAYS),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch deleted users: {}", e);
+                e
+            })?;
+
+        let mut users: Vec<Trashed<User>> = vec![];
+        while let Some(row) = result.next().await? {
+            users.push(trashed(&row, user_from_row(&row)));
+        }
+        Ok(users)
+    }
+
+    /// Takes a book out of the trash with all of its relationships. Returns
+    /// `false` if no deleted book has this id.
+    pub async fn restore_book(&self, id: &str) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (b:DeletedBook {id: $id})
+                    REMOVE b:DeletedBook, b.deleted_at
+                    SET b:Book, b.version = coalesce(b.version, 1) + 1
+                    RETURN b.id",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not restore book with id '{}': {}", id, e);
+                e
+            })?;
+        Ok(result.next().await?.is_some())
+    }
+
+    pub async fn restore_user(&self, id: i32) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:DeletedUser {id: $id})
+                    REMOVE u:DeletedUser, u.deleted_at
+                    SET u:User, u.version = coalesce(u.version, 1) + 1
+                    RETURN u.id",
+                )
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not restore user with id '{}': {}", id, e);
+                e
+            })?;
+        Ok(result.next().await?.is_some())
+    }
+
+    /// Permanently deletes books and users that have been in the trash for
+    /// longer than [`TRASH_RETENTION_DAYS`], together with the revisions,
+    /// redirects and source records of the books and any work left without
+    /// editions. Users are erased with their posts and feed events.
+    pub async fn purge_trash(&self) -> Result<()> {
+        self.graph
+            .run(
+                query(
+                    "MATCH (b:DeletedBook)
+                    WHERE b.deleted_at < datetime() - duration({days: $retention})
+                    OPTIONAL MATCH (b)-[:EDITION_OF]->(w:Work)
+                    CALL {
+                        WITH b
+                        MATCH (b)-[:HAS_REVISION]->(r:Revision)
+                        DETACH DELETE r
+                    }
+                    CALL {
+                        WITH b
+                        MATCH (r:BookRedirect)-[:REDIRECTS_TO]->(b)
+                        DETACH DELETE r
+                    }
+                    CALL {
+                        WITH b
+                        MATCH (s:SourceRecord)-[:DESCRIBES]->(b)
+                        DETACH DELETE s
+                    }
+                    DETACH DELETE b
+                    WITH DISTINCT w
+                    WHERE w IS NOT NULL AND NOT (w)<-[:EDITION_OF]-()
+                    DELETE w",
+                )
+                .param("retention", TRASH_RETENTION_DAYS),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not purge deleted books: {}", e);
+                e
+            })?;
+
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (u:DeletedUser)
+                    WHERE u.deleted_at < datetime() - duration({days: $retention})
+                    RETURN u.id AS id",
+                )
+                .param("retention", TRASH_RETENTION_DAYS),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not purge deleted users: {}", e);
+                e
+            })?;
+        let mut ids: Vec<i32> = vec![];
+        while let Some(row) = result.next().await? {
+            ids.push(row.get("id").map_err(Error::DeserializationError)?);
+        }
+        for id in ids {
+            self.erase_user(id, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Purges the trash every [`PURGE_INTERVAL`] for as long as the server
+    /// runs.
+    pub async fn purge_trash_periodically(self) {
+        let mut interval = rocket::tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = self.purge_trash().await;
+        }
+    }
+}
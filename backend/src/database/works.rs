@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use neo4rs::*;
+use serde::{Deserialize, Serialize};
+
+use super::{book_fields, book_from_row, Book, DatabaseService};
+use crate::normalize::work_key;
+
+/// The physical or digital form of an edition.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditionFormat {
+    Hardcover,
+    Paperback,
+    Ebook,
+    Audiobook,
+    Other,
+}
+
+impl EditionFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EditionFormat::Hardcover => "hardcover",
+            EditionFormat::Paperback => "paperback",
+            EditionFormat::Ebook => "ebook",
+            EditionFormat::Audiobook => "audiobook",
+            EditionFormat::Other => "other",
+        }
+    }
+}
+
+/// A work with all of its editions. Every `Book` node is an edition linked to
+/// its work through `EDITION_OF`.
+#[derive(Debug, Serialize)]
+pub struct Work {
+    id: String,
+    title: String,
+    editions: Vec<Book>,
+}
+
+/// A predicate that holds if the user bound to `user_var` has read any
+/// edition of the work the book bound to `book_var` belongs to.
+pub(super) fn has_read_work(user_var: &str, book_var: &str) -> String {
+    format!(
+        "EXISTS {{
+            MATCH ({u})-[:HAS_READ]->(:Book)-[:EDITION_OF]->(read_work:Work)
+            WHERE ({b})-[:EDITION_OF]->(read_work)
+        }}",
+        u = user_var,
+        b = book_var
+    )
+}
+
+pub(super) fn work_link(
+    book_id: &str,
+    title: &str,
+    author: &str,
+) -> HashMap<&'static str, BoltType> {
+    HashMap::from([
+        ("book_id", BoltType::from(book_id)),
+        ("key", BoltType::from(work_key(title, author))),
+        ("title", BoltType::from(title.trim())),
+    ])
+}
+
+impl DatabaseService {
+    /// Links a book to its work. An explicit `work_id` moves the edition to
+    /// that work; otherwise an edition without a work joins the work with the
+    /// same title and first author, which is created if needed.
+    pub(super) async fn link_work(&self, book_id: &str, book: &Book) -> Result<()> {
+        if let Some(work_id) = book.work_id.as_deref() {
+            self.graph
+                .run(
+                    query(
+                        "MATCH (b:Book {id: $book_id}), (w:Work {id: $work_id})
+                        OPTIONAL MATCH (b)-[old:EDITION_OF]->(previous:Work)
+                        WHERE previous <> w
+                        DELETE old
+                        MERGE (b)-[:EDITION_OF]->(w)
+                        WITH previous
+                        WHERE previous IS NOT NULL AND NOT (previous)<-[:EDITION_OF]-(:Book)
+                        DELETE previous",
+                    )
+                    .param("book_id", book_id)
+                    .param("work_id", work_id),
+                )
+                .await
+                .map_err(|e| {
+                    println!(
+                        "Could not link book with id '{}' to work with id '{}': {}",
+                        book_id, work_id, e
+                    );
+                    e
+                })?;
+        }
+        self.write_work_links(vec![work_link(
+            book_id,
+            &book.title,
+            &book.credited_author(),
+        )])
+        .await
+    }
+
+    pub(super) async fn write_work_links(&self, links: Vec<HashMap<&str, BoltType>>) -> Result<()> {
+        if links.is_empty() {
+            return Ok(());
+        }
+        self.graph
+            .run(
+                query(
+                    "UNWIND $links AS link
+                    MATCH (b:Book {id: link.book_id})
+                    WHERE NOT (b)-[:EDITION_OF]->(:Work)
+                    MERGE (w:Work {key: link.key})
+                    ON CREATE SET w.id = randomUUID(), w.title = link.title
+                    MERGE (b)-[:EDITION_OF]->(w)",
+                )
+                .param("links", links),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not link books to works: {}", e);
+                e
+            })
+    }
+
+    /// Groups books that are not linked to a work yet into works by title and
+    /// first author, so existing duplicate editions end up under one work.
+    pub(super) async fn migrate_works(&self) -> Result<()> {
+        let mut result = self
+            .graph
+            .execute(query(
+                "MATCH (b:Book)
+                WHERE NOT (b)-[:EDITION_OF]->(:Work)
+                RETURN b.id, b.title, b.author",
+            ))
+            .await
+            .map_err(|e| {
+                println!("Could not fetch books to migrate works: {}", e);
+                e
+            })?;
+
+        let mut links: Vec<HashMap<&str, BoltType>> = vec![];
+        while let Some(row) = result.next().await? {
+            let id: String = row.get("b.id").unwrap_or_default();
+            let title: String = row.get("b.title").unwrap_or_default();
+            let author: String = row.get("b.author").unwrap_or_default();
+            links.push(work_link(&id, &title, &author));
+        }
+
+        while !links.is_empty() {
+            let batch: Vec<_> = links.drain(..links.len().min(1000)).collect();
+            self.write_work_links(batch).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_work(&self, id: &str) -> Result<Option<Work>> {
+        let mut result = self
+            .graph
+            .execute(
+                query(&format!(
+                    "MATCH (w:Work {{id: $id}})<-[:EDITION_OF]-(b:Book)
+                    RETURN w.id, w.title, {}
+                    ORDER BY b.year, b.title",
+                    book_fields("b")
+                ))
+                .param("id", id),
+            )
+            .await
+            .map_err(|e| {
+                println!("Could not fetch work with id '{}': {}", id, e);
+                e
+            })?;
+
+        let mut work: Option<Work> = None;
+        while let Some(row) = result.next().await? {
+            work.get_or_insert_with(|| Work {
+                id: row.get("w.id").unwrap_or_default(),
+                title: row.get("w.title").unwrap_or_default(),
+                editions: vec![],
+            })
+            .editions
+            .push(book_from_row(&row, "b"));
+        }
+        Ok(work)
+    }
+
+    /// Merges a duplicate work, e.g. a translation that was catalogued under
+    /// its own title, into another. All editions move to the survivor.
+    pub async fn merge_work(&self, id: &str, into_id: &str) -> Result<bool> {
+        let mut result = self
+            .graph
+            .execute(
+                query(
+                    "MATCH (duplicate:Work {id: $id}), (survivor:Work {id: $into_id})
+                    WHERE duplicate <> survivor
+                    CALL {
+                        WITH duplicate, survivor
+                        MATCH (b:Book|DeletedBook)-[:EDITION_OF]->(duplicate)
+                        MERGE (b)-[:EDITION_OF]->(survivor)
+                    }
+                    DETACH DELETE duplicate
+                    RETURN survivor.id AS id",
+                )
+                .param("id", id)
+                .param("into_id", into_id),
+            )
+            .await
+            .map_err(|e| {
+                println!(
+                    "Could not merge work with id '{}' into work with id '{}': {}",
+                    id, into_id, e
+                );
+                e
+            })?;
+
+        Ok(result.next().await?.is_some())
+    }
+}
@@ -0,0 +1,500 @@
+use isolang::Language;
+use rocket::{
+    http::{ContentType, Header},
+    serde::json::{serde_json, Value},
+    FromFormField, Responder,
+};
+
+use crate::{database::Book, import::CSV_COLUMNS, normalize::split_authors};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromFormField)]
+pub enum ExportFormat {
+    /// The columns of a CSV import, so an export can be edited and imported
+    /// again.
+    #[field(value = "csv")]
+    Csv,
+    /// One book per line as returned by `GET /books/<id>`.
+    #[field(value = "jsonl")]
+    Jsonl,
+    #[field(value = "marcxml")]
+    Marcxml,
+    #[field(value = "bibtex")]
+    Bibtex,
+    #[field(value = "ris")]
+    Ris,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType::CSV,
+            ExportFormat::Jsonl => ContentType::new("application", "x-ndjson"),
+            ExportFormat::Marcxml => ContentType::new("application", "marcxml+xml"),
+            ExportFormat::Bibtex => ContentType::new("application", "x-bibtex"),
+            ExportFormat::Ris => ContentType::new("application", "x-research-info-systems"),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Marcxml => "xml",
+            ExportFormat::Bibtex => "bib",
+            ExportFormat::Ris => "ris",
+        }
+    }
+
+    /// What comes before the first book.
+    pub fn header(&self) -> String {
+        match self {
+            ExportFormat::Csv => csv_line(CSV_COLUMNS.iter().map(|column| column.to_string())),
+            ExportFormat::Marcxml => "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <collection xmlns=\"http://www.loc.gov/MARC21/slim\">\n"
+                .to_string(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn record(&self, book: &Book) -> String {
+        let book = serde_json::to_value(book).unwrap_or_default();
+        match self {
+            ExportFormat::Csv => csv_record(&book),
+            ExportFormat::Jsonl => format!("{}\n", book),
+            ExportFormat::Marcxml => marc_record(&book),
+            ExportFormat::Bibtex => bibtex_entry(&book),
+            ExportFormat::Ris => ris_record(&book),
+        }
+    }
+
+    /// What comes after the last book.
+    pub fn footer(&self) -> String {
+        match self {
+            ExportFormat::Marcxml => "</collection>\n".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A response that browsers save as a file.
+#[derive(Responder)]
+pub struct Download<R> {
+    inner: R,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+impl<R> Download<R> {
+    pub fn new(inner: R, content_type: ContentType, file_name: &str) -> Self {
+        Download {
+            inner,
+            content_type,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        }
+    }
+}
+
+/// A field of a book as text, or `None` if it is empty.
+pub fn field(book: &Value, name: &str) -> Option<String> {
+    match &book[name] {
+        Value::String(value) if !value.trim().is_empty() => Some(value.trim().to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+pub fn list(book: &Value, name: &str) -> Vec<String> {
+    book[name]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+/// The people credited on a book with their roles, falling back to the
+/// free-text `author` field.
+pub fn credits(book: &Value) -> Vec<(String, String)> {
+    let contributors: Vec<(String, String)> = book["contributors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|contributor| {
+            Some((
+                contributor["name"].as_str()?.to_string(),
+                contributor["role"].as_str().unwrap_or("author").to_string(),
+            ))
+        })
+        .collect();
+    if !contributors.is_empty() {
+        return contributors;
+    }
+    field(book, "author")
+        .map(|author| split_authors(&author))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| (name, "author".to_string()))
+        .collect()
+}
+
+fn names_with_role(book: &Value, role: &str) -> Vec<String> {
+    credits(book)
+        .into_iter()
+        .filter(|(_, credited)| credited == role)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The first series the book belongs to, with its volume.
+fn series(book: &Value) -> Option<(String, String)> {
+    let entry = book["series"].as_array()?.first()?;
+    let volume = entry["volume"].as_f64().map(|volume| match volume.fract() {
+        0.0 => format!("{}", volume as i64),
+        _ => volume.to_string(),
+    });
+    Some((
+        entry["name"].as_str()?.to_string(),
+        volume.unwrap_or_default(),
+    ))
+}
+
+fn csv_line(cells: impl Iterator<Item = String>) -> String {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let _ = writer.write_record(cells.collect::<Vec<String>>());
+    writer
+        .into_inner()
+        .ok()
+        .and_then(|line| String::from_utf8(line).ok())
+        .unwrap_or_default()
+}
+
+fn csv_record(book: &Value) -> String {
+    csv_line(CSV_COLUMNS.iter().map(|column| match *column {
+        "subjects" => list(book, "subjects").join("; "),
+        column => field(book, column).unwrap_or_default(),
+    }))
+}
+
+pub fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn datafield(tag: &str, indicators: &str, subfields: &[(char, String)]) -> String {
+    let mut indicators = indicators.chars();
+    let mut xml = format!(
+        "    <datafield tag=\"{}\" ind1=\"{}\" ind2=\"{}\">\n",
+        tag,
+        indicators.next().unwrap_or(' '),
+        indicators.next().unwrap_or(' ')
+    );
+    for (code, value) in subfields {
+        xml.push_str(&format!(
+            "      <subfield code=\"{}\">{}</subfield>\n",
+            code,
+            xml_escape(value)
+        ));
+    }
+    xml.push_str("    </datafield>\n");
+    xml
+}
+
+/// Languages whose MARC code, the bibliographic ISO 639-2 code, differs from
+/// their ISO 639-3 code.
+pub(crate) const MARC_LANGUAGE_CODES: [(&str, &str); 20] = [
+    ("bod", "tib"),
+    ("ces", "cze"),
+    ("cym", "wel"),
+    ("deu", "ger"),
+    ("ell", "gre"),
+    ("eus", "baq"),
+    ("fas", "per"),
+    ("fra", "fre"),
+    ("hye", "arm"),
+    ("isl", "ice"),
+    ("kat", "geo"),
+    ("mkd", "mac"),
+    ("mri", "mao"),
+    ("msa", "may"),
+    ("mya", "bur"),
+    ("nld", "dut"),
+    ("ron", "rum"),
+    ("slk", "slo"),
+    ("sqi", "alb"),
+    ("zho", "chi"),
+];
+
+/// The MARC code of a language stored as an ISO 639-1 or ISO 639-3 code,
+/// e.g. "eng" for "en" and "fre" for "fr".
+fn marc_language(code: &str) -> Option<&'static str> {
+    let language = match code.len() {
+        2 => Language::from_639_1(code),
+        _ => Language::from_639_3(code),
+    }?;
+    let code = language.to_639_3();
+    Some(
+        MARC_LANGUAGE_CODES
+            .iter()
+            .find(|(iso, _)| *iso == code)
+            .map_or(code, |(_, marc)| marc),
+    )
+}
+
+/// A MARC 21 bibliographic record with the fields a library catalog needs
+/// to identify and describe the book.
+fn marc_record(book: &Value) -> String {
+    let mut xml = String::from("  <record>\n    <leader>00000nam a2200000 i 4500</leader>\n");
+    if let Some(id) = field(book, "id") {
+        xml.push_str(&format!(
+            "    <controlfield tag=\"001\">{}</controlfield>\n",
+            xml_escape(&id)
+        ));
+    }
+    if let Some(isbn) = field(book, "isbn") {
+        xml.push_str(&datafield("020", "  ", &[('a', isbn)]));
+    }
+    if let Some(language) = field(book, "language").and_then(|code| marc_language(&code)) {
+        xml.push_str(&datafield("041", "  ", &[('a', language.to_string())]));
+    }
+    let credits = credits(book);
+    let (main, added) = match credits.split_first() {
+        Some((main, added)) if main.1 == "author" => (Some(main), added),
+        _ => (None, credits.as_slice()),
+    };
+    if let Some((name, role)) = main {
+        xml.push_str(&datafield(
+            "100",
+            "1 ",
+            &[('a', name.clone()), ('e', role.clone())],
+        ));
+    }
+    xml.push_str(&datafield(
+        "245",
+        if main.is_some() { "10" } else { "00" },
+        &[('a', field(book, "title").unwrap_or_default())],
+    ));
+    let publication: Vec<(char, String)> =
+        [('b', field(book, "publisher")), ('c', field(book, "year"))]
+            .into_iter()
+            .filter_map(|(code, value)| Some((code, value?)))
+            .collect();
+    if !publication.is_empty() {
+        xml.push_str(&datafield("264", " 1", &publication));
+    }
+    if let Some(pages) = field(book, "pages") {
+        xml.push_str(&datafield(
+            "300",
+            "  ",
+            &[('a', format!("{} pages", pages))],
+        ));
+    }
+    if let Some((name, volume)) = series(book) {
+        xml.push_str(&datafield("490", "0 ", &[('a', name), ('v', volume)]));
+    }
+    if let Some(description) = field(book, "description") {
+        xml.push_str(&datafield("520", "  ", &[('a', description)]));
+    }
+    for subject in list(book, "subjects") {
+        xml.push_str(&datafield("650", " 4", &[('a', subject)]));
+    }
+    for genre in list(book, "genres") {
+        xml.push_str(&datafield("655", " 4", &[('a', genre)]));
+    }
+    for (name, role) in added {
+        xml.push_str(&datafield(
+            "700",
+            "1 ",
+            &[('a', name.clone()), ('e', role.clone())],
+        ));
+    }
+    if let Some(cover) = field(book, "cover") {
+        xml.push_str(&datafield(
+            "856",
+            "42",
+            &[('3', "Cover image".to_string()), ('u', cover)],
+        ));
+    }
+    xml.push_str("  </record>\n");
+    xml
+}
+
+fn bibtex_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn bibtex_entry(book: &Value) -> String {
+    let (series, volume) = series(book).unzip();
+    let fields = [
+        ("title", field(book, "title")),
+        (
+            "author",
+            Some(names_with_role(book, "author").join(" and ")),
+        ),
+        (
+            "editor",
+            Some(names_with_role(book, "editor").join(" and ")),
+        ),
+        (
+            "translator",
+            Some(names_with_role(book, "translator").join(" and ")),
+        ),
+        ("publisher", field(book, "publisher")),
+        ("year", field(book, "year")),
+        ("isbn", field(book, "isbn")),
+        ("language", field(book, "language")),
+        ("series", series),
+        ("volume", volume),
+        ("pagetotal", field(book, "pages")),
+        ("abstract", field(book, "description")),
+        ("keywords", Some(list(book, "subjects").join(", "))),
+    ];
+    let mut entry = format!("@book{{{},\n", field(book, "id").unwrap_or_default());
+    for (name, value) in fields {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            entry.push_str(&format!("  {} = {{{}}},\n", name, bibtex_escape(&value)));
+        }
+    }
+    entry.push_str("}\n\n");
+    entry
+}
+
+fn ris_record(book: &Value) -> String {
+    let mut lines: Vec<(&str, String)> = vec![("TY", "BOOK".to_string())];
+    lines.extend(field(book, "id").map(|id| ("ID", id)));
+    lines.extend(field(book, "title").map(|title| ("TI", title)));
+    for (name, role) in credits(book) {
+        lines.push((
+            match role.as_str() {
+                "editor" => "ED",
+                "translator" => "A4",
+                _ => "AU",
+            },
+            name,
+        ));
+    }
+    if let Some((name, volume)) = series(book) {
+        lines.push(("T2", name));
+        lines.push(("VL", volume));
+    }
+    lines.extend(field(book, "publisher").map(|publisher| ("PB", publisher)));
+    lines.extend(field(book, "year").map(|year| ("PY", year)));
+    lines.extend(field(book, "isbn").map(|isbn| ("SN", isbn)));
+    lines.extend(field(book, "language").map(|language| ("LA", language)));
+    lines.extend(field(book, "pages").map(|pages| ("SP", pages)));
+    lines.extend(field(book, "description").map(|description| ("AB", description)));
+    for subject in list(book, "subjects") {
+        lines.push(("KW", subject));
+    }
+    lines.push(("ER", String::new()));
+    lines
+        .into_iter()
+        .map(|(tag, value)| format!("{}  - {}\r\n", tag, value.replace(['\r', '\n'], " ")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json::json;
+
+    use super::*;
+
+    fn dune() -> Value {
+        json!({
+            "id": "b1",
+            "title": "Dune",
+            "author": "Frank Herbert",
+            "isbn": "9780441172719",
+            "language": "en",
+            "publisher": "Ace",
+            "year": 1990,
+            "pages": 535,
+            "subjects": ["Science fiction", "Deserts"],
+            "series": [{ "name": "Dune", "volume": 1.0 }],
+            "contributors": [
+                { "name": "Frank Herbert", "role": "author" },
+                { "name": "John Schoenherr", "role": "illustrator" },
+            ],
+        })
+    }
+
+    #[test]
+    fn converts_languages_to_marc_codes() {
+        assert_eq!(marc_language("en"), Some("eng"));
+        assert_eq!(marc_language("fr"), Some("fre"));
+        assert_eq!(marc_language("de"), Some("ger"));
+        assert_eq!(marc_language("haw"), Some("haw"));
+        assert_eq!(marc_language("xx"), None);
+    }
+
+    #[test]
+    fn writes_marc_records() {
+        let record = marc_record(&dune());
+        assert!(record.contains("<controlfield tag=\"001\">b1</controlfield>"));
+        assert!(record.contains(
+            "<datafield tag=\"041\" ind1=\" \" ind2=\" \">\n      <subfield code=\"a\">eng</subfield>"
+        ));
+        assert!(record.contains("<datafield tag=\"245\" ind1=\"1\" ind2=\"0\">"));
+        assert!(record.contains("<subfield code=\"a\">535 pages</subfield>"));
+        assert!(record.contains("<subfield code=\"v\">1</subfield>"));
+        assert!(record.contains(
+            "<datafield tag=\"700\" ind1=\"1\" ind2=\" \">\n      <subfield code=\"a\">John Schoenherr</subfield>"
+        ));
+    }
+
+    #[test]
+    fn writes_csv_records_in_import_columns() {
+        assert_eq!(
+            csv_record(&dune()),
+            "b1,Dune,Frank Herbert,,,9780441172719,,en,Ace,1990,535,,Science fiction; Deserts\n"
+        );
+    }
+
+    #[test]
+    fn writes_bibtex_entries() {
+        let mut book = dune();
+        book["title"] = json!("Dune & 100% more_sand");
+        assert_eq!(
+            bibtex_entry(&book),
+            "@book{b1,\n  title = {Dune \\& 100\\% more\\_sand},\n  author = {Frank Herbert},\n  \
+            publisher = {Ace},\n  year = {1990},\n  isbn = {9780441172719},\n  language = {en},\n  \
+            series = {Dune},\n  volume = {1},\n  pagetotal = {535},\n  \
+            keywords = {Science fiction, Deserts},\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn writes_ris_records() {
+        let record = ris_record(&dune());
+        assert!(
+            record.starts_with("TY  - BOOK\r\nID  - b1\r\nTI  - Dune\r\nAU  - Frank Herbert\r\n")
+        );
+        assert!(record.contains("T2  - Dune\r\nVL  - 1\r\n"));
+        assert!(record.ends_with("KW  - Deserts\r\nER  - \r\n"));
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            xml_escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
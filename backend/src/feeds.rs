@@ -0,0 +1,148 @@
+use chrono::{SecondsFormat, Utc};
+use rocket::{
+    http::ContentType,
+    serde::json::{serde_json, Value},
+};
+use serde::Serialize;
+
+use crate::{
+    export::{credits, field, list, xml_escape},
+    opds::OPDS_ROOT,
+};
+
+/// Where the feeds are served, for the links in them.
+pub const FEEDS_ROOT: &str = "/api/feeds";
+
+/// The secret token of a user's private feeds with the address of each feed:
+/// the Atom feed of recommendations and the user's OPDS catalog.
+#[derive(Debug, Serialize)]
+pub struct FeedToken {
+    token: String,
+    recommendations: String,
+    opds: String,
+}
+
+impl FeedToken {
+    pub fn new(token: String) -> Self {
+        FeedToken {
+            recommendations: format!("{}/recommendations/{}", FEEDS_ROOT, token),
+            opds: format!("{}/users/{}", OPDS_ROOT, token),
+            token,
+        }
+    }
+}
+
+pub fn content_type() -> ContentType {
+    ContentType::new("application", "atom+xml")
+}
+
+/// An Atom feed of books for feed readers. `id` names the feed for good, so
+/// it must not contain anything secret such as the feed token. Entries keep
+/// the same id across updates of the feed, so readers only show books that
+/// are new to it; books with an `added_at` time show it as their update time.
+pub fn atom_feed<T: Serialize>(id: &str, title: &str, href: &str, books: &[T]) -> String {
+    let updated = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        \x20 <id>urn:book-recommender:feed:{}</id>\n\
+        \x20 <title>{}</title>\n\
+        \x20 <updated>{}</updated>\n\
+        \x20 <author><name>Book Recommender</name></author>\n\
+        \x20 <link rel=\"self\" href=\"{}\" type=\"application/atom+xml\"/>\n",
+        xml_escape(id),
+        xml_escape(title),
+        updated,
+        xml_escape(href)
+    );
+    for book in books {
+        let book = serde_json::to_value(book).unwrap_or_default();
+        xml.push_str(&reader_entry(id, &book, &updated));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// The Atom entry of a book under the id `id`: its title, authors, genres,
+/// description and a link to its record in the API, followed by the `extra`
+/// elements of the feed it is listed in. Shared by these feeds and the OPDS
+/// catalog.
+pub fn atom_entry(id: &str, book: &Value, updated: &str, extra: &str) -> String {
+    let mut xml = format!(
+        "  <entry>\n    <id>{}</id>\n    <title>{}</title>\n    <updated>{}</updated>\n",
+        xml_escape(id),
+        xml_escape(&field(book, "title").unwrap_or_default()),
+        field(book, "added_at").unwrap_or_else(|| updated.to_string())
+    );
+    for name in authors(book) {
+        xml.push_str(&format!(
+            "    <author><name>{}</name></author>\n",
+            xml_escape(&name)
+        ));
+    }
+    for genre in list(book, "genres") {
+        xml.push_str(&format!(
+            "    <category term=\"{0}\" label=\"{0}\"/>\n",
+            xml_escape(&genre)
+        ));
+    }
+    xml.push_str(&format!(
+        "    <link rel=\"alternate\" href=\"/api/books/{}\" type=\"application/json\"/>\n",
+        xml_escape(&field(book, "id").unwrap_or_default())
+    ));
+    if let Some(description) = field(book, "description") {
+        xml.push_str(&format!(
+            "    <summary>{}</summary>\n",
+            xml_escape(&description)
+        ));
+    }
+    xml.push_str(extra);
+    xml.push_str("  </entry>\n");
+    xml
+}
+
+fn authors(book: &Value) -> Vec<String> {
+    credits(book)
+        .into_iter()
+        .filter(|(_, role)| role == "author")
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The entry of a book in a feed for readers, with an id scoped to the feed
+/// and the book as its content.
+fn reader_entry(feed_id: &str, book: &Value, updated: &str) -> String {
+    let id = format!(
+        "urn:book-recommender:feed:{}:{}",
+        feed_id,
+        field(book, "id").unwrap_or_default()
+    );
+    let content = format!(
+        "    <content type=\"html\">{}</content>\n",
+        xml_escape(&entry_html(book))
+    );
+    atom_entry(&id, book, updated, &content)
+}
+
+/// The entry as a reader shows it: the cover, title, authors and
+/// description.
+fn entry_html(book: &Value) -> String {
+    let title = field(book, "title").unwrap_or_default();
+    let authors = authors(book);
+    let mut html = String::new();
+    if let Some(cover) = field(book, "cover") {
+        html.push_str(&format!(
+            "<p><img src=\"{}\" alt=\"{}\"/></p>",
+            xml_escape(&cover),
+            xml_escape(&title)
+        ));
+    }
+    html.push_str(&format!("<h2>{}</h2>", xml_escape(&title)));
+    if !authors.is_empty() {
+        html.push_str(&format!("<p>by {}</p>", xml_escape(&authors.join(", "))));
+    }
+    if let Some(description) = field(book, "description") {
+        html.push_str(&format!("<p>{}</p>", xml_escape(&description)));
+    }
+    html
+}
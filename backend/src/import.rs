@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use rocket::{
+    serde::json::{
+        json,
+        serde_json::{self, Map},
+        Value,
+    },
+    FromFormField,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{FieldError, LibraryEntry},
+    normalize::normalize_isbn,
+};
+
+/// Book fields that can be set from a CSV column. `subjects` holds a
+/// semicolon-separated list.
+pub const CSV_COLUMNS: [&str; 13] = [
+    "id",
+    "title",
+    "author",
+    "genre",
+    "cover",
+    "isbn",
+    "format",
+    "language",
+    "publisher",
+    "year",
+    "pages",
+    "description",
+    "subjects",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, FromFormField)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    #[field(value = "csv")]
+    Csv,
+    #[field(value = "jsonl")]
+    Jsonl,
+    /// A Goodreads library export, imported as a user's reads.
+    #[field(value = "goodreads")]
+    Goodreads,
+    /// A StoryGraph library export, imported as a user's reads.
+    #[field(value = "storygraph")]
+    Storygraph,
+    /// JSON Lines of [`LibraryEntry`], imported as a user's reads. Tools like
+    /// the Calibre importer convert other libraries to it.
+    #[field(value = "library")]
+    Library,
+}
+
+impl ImportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Jsonl => "jsonl",
+            ImportFormat::Goodreads => "goodreads",
+            ImportFormat::Storygraph => "storygraph",
+            ImportFormat::Library => "library",
+        }
+    }
+
+    /// Whether the file holds a user's reading history rather than catalog
+    /// entries.
+    pub fn is_library(&self) -> bool {
+        matches!(
+            self,
+            ImportFormat::Goodreads | ImportFormat::Storygraph | ImportFormat::Library
+        )
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "csv" => Some(ImportFormat::Csv),
+            "jsonl" => Some(ImportFormat::Jsonl),
+            "goodreads" => Some(ImportFormat::Goodreads),
+            "storygraph" => Some(ImportFormat::Storygraph),
+            "library" => Some(ImportFormat::Library),
+            _ => None,
+        }
+    }
+}
+
+/// One row of an import file with its 1-based number: the JSON fields of the
+/// book it describes (a [`LibraryEntry`] for library exports), or why it
+/// could not be read.
+pub struct ImportRow {
+    pub number: usize,
+    pub fields: Result<Value, Vec<FieldError>>,
+}
+
+/// Splits an import file into rows. Errors that affect the whole file, like
+/// an unknown CSV column, reject it before any row is imported.
+pub fn parse_rows(format: ImportFormat, file: &str) -> Result<Vec<ImportRow>, FieldError> {
+    match format {
+        ImportFormat::Csv => parse_csv(file),
+        ImportFormat::Jsonl | ImportFormat::Library => Ok(parse_jsonl(file)),
+        ImportFormat::Goodreads => parse_library(file, GOODREADS_COLUMNS, goodreads_entry),
+        ImportFormat::Storygraph => parse_library(file, STORYGRAPH_COLUMNS, storygraph_entry),
+    }
+}
+
+fn parse_csv(file: &str) -> Result<Vec<ImportRow>, FieldError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(file.as_bytes());
+    let mut columns: Vec<&'static str> = vec![];
+    for header in reader
+        .headers()
+        .map_err(|e| FieldError::new("file", e.to_string()))?
+    {
+        let header = header.to_lowercase();
+        match CSV_COLUMNS.iter().find(|column| **column == header) {
+            Some(column) => columns.push(column),
+            None => {
+                return Err(FieldError::new(
+                    "file",
+                    format!(
+                        "unknown column '{}', expected any of {}",
+                        header,
+                        CSV_COLUMNS.join(", ")
+                    ),
+                ))
+            }
+        }
+    }
+    if !columns.contains(&"title") {
+        return Err(FieldError::new("file", "a 'title' column is required"));
+    }
+
+    Ok(reader
+        .records()
+        .enumerate()
+        .map(|(i, record)| ImportRow {
+            number: i + 1,
+            fields: record
+                .map_err(|e| vec![FieldError::new("row", e.to_string())])
+                .and_then(|record| csv_fields(&columns, &record)),
+        })
+        .collect())
+}
+
+/// The book fields of a CSV record. Empty cells are left out, so they keep
+/// the current value when the row updates a book.
+fn csv_fields(
+    columns: &[&'static str],
+    record: &csv::StringRecord,
+) -> Result<Value, Vec<FieldError>> {
+    let mut fields = Map::new();
+    let mut errors: Vec<FieldError> = vec![];
+    for (&column, cell) in columns.iter().zip(record.iter()) {
+        if cell.is_empty() {
+            continue;
+        }
+        let value = match column {
+            "subjects" => Value::from(
+                cell.split(';')
+                    .map(str::trim)
+                    .filter(|subject| !subject.is_empty())
+                    .collect::<Vec<&str>>(),
+            ),
+            "year" | "pages" => match cell.parse::<i64>() {
+                Ok(number) => Value::from(number),
+                Err(_) => {
+                    errors.push(FieldError::new(
+                        column,
+                        format!("'{}' is not a whole number", cell),
+                    ));
+                    continue;
+                }
+            },
+            _ => Value::from(cell),
+        };
+        fields.insert(column.to_string(), value);
+    }
+    match errors.is_empty() {
+        true => Ok(Value::Object(fields)),
+        false => Err(errors),
+    }
+}
+
+fn parse_jsonl(file: &str) -> Vec<ImportRow> {
+    file.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| ImportRow {
+            number: i + 1,
+            fields: match serde_json::from_str::<Value>(line) {
+                Ok(fields) if fields.is_object() => Ok(fields),
+                Ok(_) => Err(vec![FieldError::new("row", "row must be a JSON object")]),
+                Err(e) => Err(vec![FieldError::new("row", e.to_string())]),
+            },
+        })
+        .collect()
+}
+
+/// Columns a Goodreads export must have; they also tell it apart from other
+/// CSV files.
+const GOODREADS_COLUMNS: &[&str] = &["Title", "Author", "Exclusive Shelf"];
+const STORYGRAPH_COLUMNS: &[&str] = &["Title", "Authors", "Read Status"];
+
+type LibraryRecord = HashMap<String, String>;
+
+fn parse_library(
+    file: &str,
+    required: &[&str],
+    entry: fn(&LibraryRecord) -> LibraryEntry,
+) -> Result<Vec<ImportRow>, FieldError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(file.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| FieldError::new("file", e.to_string()))?
+        .clone();
+    if let Some(missing) = required
+        .iter()
+        .find(|column| !headers.iter().any(|header| header == **column))
+    {
+        return Err(FieldError::new(
+            "file",
+            format!("the export has no '{}' column", missing),
+        ));
+    }
+
+    Ok(reader
+        .records()
+        .enumerate()
+        .map(|(i, record)| ImportRow {
+            number: i + 1,
+            fields: record
+                .and_then(|record| record.deserialize::<LibraryRecord>(Some(&headers)))
+                .map_err(|e| vec![FieldError::new("row", e.to_string())])
+                .map(|record| json!(entry(&record))),
+        })
+        .collect())
+}
+
+fn cell<'a>(record: &'a LibraryRecord, column: &str) -> Option<&'a str> {
+    record
+        .get(column)
+        .map(String::as_str)
+        .filter(|value| !value.is_empty())
+}
+
+/// Goodreads quotes ISBNs as spreadsheet formulas, e.g. `="0441172717"`.
+fn isbn_cell(record: &LibraryRecord, column: &str) -> Option<String> {
+    cell(record, column)
+        .map(|value| value.trim_start_matches('=').trim_matches('"').to_string())
+        .filter(|value| normalize_isbn(value).is_some())
+}
+
+fn number_cell(record: &LibraryRecord, column: &str) -> Option<i64> {
+    cell(record, column).and_then(|value| value.parse().ok())
+}
+
+/// Reads the `YYYY/MM/DD` dates both services export as `YYYY-MM-DD`.
+fn date_cell(record: &LibraryRecord, column: &str) -> Option<String> {
+    let value = cell(record, column)?;
+    ["%Y/%m/%d", "%Y-%m-%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+fn list_cell(record: &LibraryRecord, column: &str) -> Vec<String> {
+    cell(record, column)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Maps the bindings and formats other services use to an edition format.
+fn format_cell(record: &LibraryRecord, column: &str) -> Option<&'static str> {
+    let value = cell(record, column)?.to_lowercase();
+    if value.contains("audio") {
+        Some("audiobook")
+    } else if value.contains("kindle") || value.contains("ebook") || value == "digital" {
+        Some("ebook")
+    } else if value.contains("hardcover") {
+        Some("hardcover")
+    } else if value.contains("paperback") {
+        Some("paperback")
+    } else {
+        None
+    }
+}
+
+/// The book fields of a library export row. Fields that are not set are
+/// left out, so an existing book is matched without being changed.
+fn library_book(fields: &[(&str, Option<Value>)]) -> Value {
+    Value::Object(
+        fields
+            .iter()
+            .filter_map(|(field, value)| Some((field.to_string(), value.clone()?)))
+            .collect(),
+    )
+}
+
+fn goodreads_entry(record: &LibraryRecord) -> LibraryEntry {
+    let author = std::iter::once(cell(record, "Author"))
+        .chain(
+            cell(record, "Additional Authors")
+                .into_iter()
+                .flat_map(|authors| authors.split(','))
+                .map(|author| Some(author.trim())),
+        )
+        .flatten()
+        .collect::<Vec<&str>>()
+        .join("; ");
+    let mut shelves = list_cell(record, "Bookshelves");
+    let status = cell(record, "Exclusive Shelf")
+        .unwrap_or_default()
+        .to_string();
+    if !status.is_empty() && !shelves.contains(&status) {
+        shelves.push(status.clone());
+    }
+    LibraryEntry {
+        source: ImportFormat::Goodreads.as_str().to_string(),
+        book: library_book(&[
+            ("title", cell(record, "Title").map(Value::from)),
+            ("author", Some(Value::from(author))),
+            (
+                "isbn",
+                isbn_cell(record, "ISBN13")
+                    .or_else(|| isbn_cell(record, "ISBN"))
+                    .map(Value::from),
+            ),
+            ("publisher", cell(record, "Publisher").map(Value::from)),
+            ("format", format_cell(record, "Binding").map(Value::from)),
+            (
+                "pages",
+                number_cell(record, "Number of Pages").map(Value::from),
+            ),
+            (
+                "year",
+                number_cell(record, "Year Published").map(Value::from),
+            ),
+        ]),
+        status,
+        rating: number_cell(record, "My Rating").filter(|rating| (1..=5).contains(rating)),
+        read_at: date_cell(record, "Date Read").or_else(|| date_cell(record, "Date Added")),
+        review: cell(record, "My Review").map(str::to_string),
+        shelves,
+    }
+}
+
+fn storygraph_entry(record: &LibraryRecord) -> LibraryEntry {
+    let author = list_cell(record, "Authors").join("; ");
+    let status = cell(record, "Read Status").unwrap_or_default().to_string();
+    let mut shelves = list_cell(record, "Tags");
+    if !status.is_empty() && !shelves.contains(&status) {
+        shelves.push(status.clone());
+    }
+    LibraryEntry {
+        source: ImportFormat::Storygraph.as_str().to_string(),
+        book: library_book(&[
+            ("title", cell(record, "Title").map(Value::from)),
+            ("author", Some(Value::from(author))),
+            ("isbn", isbn_cell(record, "ISBN/UID").map(Value::from)),
+            ("format", format_cell(record, "Format").map(Value::from)),
+        ]),
+        status,
+        // StoryGraph allows quarter stars; ratings here are whole stars.
+        rating: cell(record, "Star Rating")
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(|rating| rating.round() as i64)
+            .filter(|rating| (1..=5).contains(rating)),
+        read_at: date_cell(record, "Last Date Read").or_else(|| date_cell(record, "Date Added")),
+        review: cell(record, "Review").map(str::to_string),
+        shelves,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(row: &ImportRow) -> &Value {
+        row.fields.as_ref().expect("row should parse")
+    }
+
+    #[test]
+    fn parses_csv_rows_into_book_fields() {
+        let file = "Title,Author,Year,Subjects,Cover\n\
+            Dune,Frank Herbert,1965,science fiction; politics ;,\n";
+        let rows = parse_csv(file).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].number, 1);
+        assert_eq!(
+            fields(&rows[0]),
+            &json!({
+                "title": "Dune",
+                "author": "Frank Herbert",
+                "year": 1965,
+                "subjects": ["science fiction", "politics"],
+            })
+        );
+    }
+
+    #[test]
+    fn reports_csv_cells_that_are_not_numbers() {
+        let rows = parse_csv("title,pages\nDune,many\n").unwrap();
+        let errors = rows[0].fields.as_ref().unwrap_err();
+        assert_eq!(json!(errors)[0]["field"], "pages");
+    }
+
+    #[test]
+    fn rejects_csv_files_with_unknown_columns_or_no_title() {
+        assert!(parse_csv("title,colour\nDune,red\n").is_err());
+        assert!(parse_csv("author\nFrank Herbert\n").is_err());
+    }
+
+    #[test]
+    fn parses_json_lines_keeping_line_numbers() {
+        let rows = parse_jsonl("{\"title\": \"Dune\"}\n\n[1, 2]\nnot json\n");
+        let numbers: Vec<usize> = rows.iter().map(|row| row.number).collect();
+        assert_eq!(numbers, [1, 3, 4]);
+        assert_eq!(fields(&rows[0]), &json!({ "title": "Dune" }));
+        assert!(rows[1].fields.is_err());
+        assert!(rows[2].fields.is_err());
+    }
+
+    fn record(cells: &[(&str, &str)]) -> LibraryRecord {
+        cells
+            .iter()
+            .map(|(column, value)| (column.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_goodreads_rows() {
+        let entry = goodreads_entry(&record(&[
+            ("Title", "Good Omens"),
+            ("Author", "Terry Pratchett"),
+            ("Additional Authors", "Neil Gaiman"),
+            ("ISBN", "=\"0060853980\""),
+            ("ISBN13", "=\"\""),
+            ("My Rating", "4"),
+            ("Binding", "Mass Market Paperback"),
+            ("Number of Pages", "432"),
+            ("Date Read", "2023/05/07"),
+            ("Date Added", "2023/01/02"),
+            ("Bookshelves", "favorites, humor"),
+            ("Exclusive Shelf", "read"),
+        ]));
+        assert_eq!(entry.source, "goodreads");
+        assert_eq!(
+            entry.book,
+            json!({
+                "title": "Good Omens",
+                "author": "Terry Pratchett; Neil Gaiman",
+                "isbn": "0060853980",
+                "format": "paperback",
+                "pages": 432,
+            })
+        );
+        assert_eq!(entry.status, "read");
+        assert_eq!(entry.rating, Some(4));
+        assert_eq!(entry.read_at.as_deref(), Some("2023-05-07"));
+        assert_eq!(entry.shelves, ["favorites", "humor", "read"]);
+    }
+
+    #[test]
+    fn leaves_unrated_goodreads_rows_without_a_rating() {
+        let entry = goodreads_entry(&record(&[
+            ("Title", "Dune"),
+            ("Author", "Frank Herbert"),
+            ("My Rating", "0"),
+            ("Date Added", "2024/02/29"),
+            ("Exclusive Shelf", "to-read"),
+        ]));
+        assert_eq!(entry.rating, None);
+        assert_eq!(entry.read_at.as_deref(), Some("2024-02-29"));
+        assert_eq!(entry.shelves, ["to-read"]);
+    }
+
+    #[test]
+    fn reads_storygraph_rows() {
+        let entry = storygraph_entry(&record(&[
+            ("Title", "Piranesi"),
+            ("Authors", "Susanna Clarke"),
+            ("ISBN/UID", "9781635575637"),
+            ("Format", "digital"),
+            ("Read Status", "read"),
+            ("Last Date Read", "2022/11/30"),
+            ("Star Rating", "4.75"),
+            ("Tags", "fantasy, mystery"),
+            ("Review", "Beautiful."),
+        ]));
+        assert_eq!(entry.source, "storygraph");
+        assert_eq!(
+            entry.book,
+            json!({
+                "title": "Piranesi",
+                "author": "Susanna Clarke",
+                "isbn": "9781635575637",
+                "format": "ebook",
+            })
+        );
+        assert_eq!(entry.rating, Some(5));
+        assert_eq!(entry.read_at.as_deref(), Some("2022-11-30"));
+        assert_eq!(entry.review.as_deref(), Some("Beautiful."));
+        assert_eq!(entry.shelves, ["fantasy", "mystery", "read"]);
+    }
+
+    #[test]
+    fn rejects_library_exports_missing_a_required_column() {
+        assert!(parse_rows(
+            ImportFormat::Goodreads,
+            "Title,Author\nDune,Frank Herbert\n"
+        )
+        .is_err());
+        let rows = parse_rows(
+            ImportFormat::Storygraph,
+            "Title,Authors,Read Status\nDune,Frank Herbert,read\n",
+        )
+        .unwrap();
+        assert_eq!(fields(&rows[0])["status"], "read");
+    }
+}
@@ -0,0 +1,540 @@
+use rocket::serde::json::{json, serde_json, Value};
+use serde::Serialize;
+use sqlx::{types::Json, FromRow, PgPool};
+
+mod deletion;
+mod takeout;
+
+pub use deletion::AccountDeletion;
+pub use takeout::{Takeout, TakeoutArchive};
+
+use crate::{
+    database::{BookIndex, DatabaseService, FieldError, ImportError, LibraryEntry, Resolution},
+    import::{parse_rows, ImportFormat},
+};
+
+/// A bulk import and its progress. Counts cover the rows processed so far.
+/// Library imports belong to `user_id` and count `matched`, `created`,
+/// `unmatched` and `skipped` reads; catalog imports count `created`,
+/// `updated` and `unchanged` books.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ImportJob {
+    id: i32,
+    user_id: Option<i32>,
+    format: String,
+    dry_run: bool,
+    status: String,
+    error: Option<String>,
+    total: i32,
+    processed: i32,
+    created: i32,
+    updated: i32,
+    unchanged: i32,
+    matched: i32,
+    unmatched: i32,
+    skipped: i32,
+    failed: i32,
+    created_at: String,
+    finished_at: Option<String>,
+}
+
+/// The outcome of one row of an import with the field changes, validation
+/// errors, or the candidate books of a row left `unmatched` for review.
+/// Library rows also carry the book as it appeared in the export.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ImportRowReport {
+    #[serde(rename = "row")]
+    row_number: i32,
+    outcome: String,
+    book_id: Option<String>,
+    changes: Json<Value>,
+    errors: Json<Value>,
+    candidates: Json<Value>,
+    book: Json<Value>,
+}
+
+const JOB_COLUMNS: &str = "id, user_id, format, dry_run, status, error, total, processed,
+    created, updated, unchanged, matched, unmatched, skipped, failed,
+    created_at::TEXT AS created_at, finished_at::TEXT AS finished_at";
+
+/// Adds a row with the outcome bound to `$2` to the job's counts.
+const COUNT_OUTCOME: &str = "created = created + ($2 = 'created')::INTEGER,
+    updated = updated + ($2 = 'updated')::INTEGER,
+    unchanged = unchanged + ($2 = 'unchanged')::INTEGER,
+    matched = matched + ($2 = 'matched')::INTEGER,
+    unmatched = unmatched + ($2 = 'unmatched')::INTEGER,
+    skipped = skipped + ($2 = 'skipped')::INTEGER,
+    failed = failed + ($2 = 'invalid')::INTEGER";
+
+#[derive(FromRow)]
+struct PendingImport {
+    user_id: Option<i32>,
+    format: String,
+    dry_run: bool,
+    payload: String,
+    processed: i32,
+}
+
+/// A row of a library import claimed for resolving, with the entry it was
+/// imported from.
+#[derive(FromRow)]
+struct ClaimedRow {
+    user_id: i32,
+    book: Json<Value>,
+}
+
+/// The report of one processed row, as stored in `import_job_rows`. Library
+/// rows keep their whole [`LibraryEntry`] in `book`, so a row left for review
+/// can be resolved without reading the file again.
+struct RowResult {
+    outcome: &'static str,
+    book_id: Option<String>,
+    changes: Value,
+    errors: Value,
+    candidates: Value,
+    book: Value,
+}
+
+impl RowResult {
+    fn new(outcome: &'static str, book_id: Option<String>) -> Self {
+        RowResult {
+            outcome,
+            book_id,
+            changes: json!([]),
+            errors: json!([]),
+            candidates: json!([]),
+            book: Value::Null,
+        }
+    }
+
+    fn invalid(errors: Vec<FieldError>) -> Self {
+        RowResult {
+            errors: json!(errors),
+            ..RowResult::new("invalid", None)
+        }
+    }
+}
+
+/// The idempotency key under which a row creates its book. The book is
+/// written before the row's outcome is recorded, so a row interrupted in
+/// between by a restart is imported again and must find the same book.
+fn row_idempotency_key(id: i32, row: usize) -> String {
+    format!("import:{}:{}", id, row)
+}
+
+/// Imports one parsed row: a read for library imports, which come with the
+/// importing user and the catalog index, or else a catalog book.
+async fn import_row(
+    database: &DatabaseService,
+    fields: &Value,
+    library: Option<(i32, &mut BookIndex)>,
+    dry_run: bool,
+    idempotency_key: &str,
+) -> Result<RowResult, ImportError> {
+    match library {
+        Some((user_id, index)) => {
+            let entry: LibraryEntry = serde_json::from_value(fields.clone())
+                .map_err(|e| ImportError::Invalid(vec![FieldError::new("row", e.to_string())]))?;
+            let read = database
+                .import_read(user_id, &entry, index, dry_run, Some(idempotency_key))
+                .await?;
+            Ok(RowResult {
+                candidates: json!(read.candidates),
+                book: json!(entry),
+                ..RowResult::new(read.outcome.as_str(), read.book_id)
+            })
+        }
+        None => {
+            let imported = database
+                .import_book(fields, dry_run, Some(idempotency_key))
+                .await?;
+            Ok(RowResult {
+                changes: json!(imported.changes),
+                ..RowResult::new(imported.outcome.as_str(), imported.book_id)
+            })
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JobService {
+    pool: PgPool,
+}
+
+impl JobService {
+    pub fn new(pool: PgPool) -> Self {
+        JobService { pool }
+    }
+
+    pub async fn migrate(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS import_jobs (
+            id SERIAL PRIMARY KEY,
+            format TEXT NOT NULL,
+            dry_run BOOLEAN NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'queued',
+            error TEXT,
+            total INTEGER NOT NULL,
+            processed INTEGER NOT NULL DEFAULT 0,
+            created INTEGER NOT NULL DEFAULT 0,
+            updated INTEGER NOT NULL DEFAULT 0,
+            unchanged INTEGER NOT NULL DEFAULT 0,
+            failed INTEGER NOT NULL DEFAULT 0,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            finished_at TIMESTAMPTZ)",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS import_job_rows (
+            job_id INTEGER NOT NULL REFERENCES import_jobs (id) ON DELETE CASCADE,
+            row_number INTEGER NOT NULL,
+            outcome TEXT NOT NULL,
+            book_id TEXT,
+            changes JSONB NOT NULL,
+            errors JSONB NOT NULL,
+            PRIMARY KEY (job_id, row_number))",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            "ALTER TABLE import_jobs
+            ADD COLUMN IF NOT EXISTS user_id INTEGER,
+            ADD COLUMN IF NOT EXISTS matched INTEGER NOT NULL DEFAULT 0,
+            ADD COLUMN IF NOT EXISTS unmatched INTEGER NOT NULL DEFAULT 0,
+            ADD COLUMN IF NOT EXISTS skipped INTEGER NOT NULL DEFAULT 0",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            "ALTER TABLE import_job_rows
+            ADD COLUMN IF NOT EXISTS candidates JSONB NOT NULL DEFAULT '[]',
+            ADD COLUMN IF NOT EXISTS book JSONB NOT NULL DEFAULT 'null'",
+        )
+        .execute(&self.pool)
+        .await?;
+        // Jobs that were running when the server stopped are picked up again
+        // by `resume_imports`.
+        sqlx::query("UPDATE import_jobs SET status = 'queued' WHERE status = 'running'")
+            .execute(&self.pool)
+            .await?;
+        // Rows claimed by a `resolve_row` that never finished go back up for
+        // review.
+        sqlx::query("UPDATE import_job_rows SET outcome = 'unmatched' WHERE outcome = 'resolving'")
+            .execute(&self.pool)
+            .await?;
+        self.migrate_takeouts().await?;
+        self.migrate_deletions().await
+    }
+
+    /// Queues an import of `file`, into the reading history of `user_id` for
+    /// library exports. The file is checked for errors that affect every row
+    /// before the job is created; row errors are reported as the job runs.
+    pub async fn create_import(
+        &self,
+        format: ImportFormat,
+        dry_run: bool,
+        file: &str,
+        user_id: Option<i32>,
+    ) -> Result<ImportJob, Vec<FieldError>> {
+        if format.is_library() != user_id.is_some() {
+            let message = match user_id {
+                Some(_) => format!("{} files are catalog imports", format.as_str()),
+                None => format!("{} exports are imported for a user", format.as_str()),
+            };
+            return Err(vec![FieldError::new("format", message)]);
+        }
+        let rows = parse_rows(format, file).map_err(|error| vec![error])?;
+        sqlx::query_as::<_, ImportJob>(&format!(
+            "INSERT INTO import_jobs (user_id, format, dry_run, payload, total)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {}",
+            JOB_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(format.as_str())
+        .bind(dry_run)
+        .bind(file)
+        .bind(rows.len() as i32)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
+            println!("Could not create import job: {}", e);
+            vec![FieldError::new("file", "the import could not be queued")]
+        })
+    }
+
+    /// Returns the imports of a user, or the catalog imports for `None`,
+    /// newest first.
+    pub async fn get_imports(&self, user_id: Option<i32>) -> Result<Vec<ImportJob>, sqlx::Error> {
+        sqlx::query_as::<_, ImportJob>(&format!(
+            "SELECT {} FROM import_jobs WHERE user_id IS NOT DISTINCT FROM $1 ORDER BY id DESC",
+            JOB_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_import(&self, id: i32) -> Result<Option<ImportJob>, sqlx::Error> {
+        sqlx::query_as::<_, ImportJob>(&format!(
+            "SELECT {} FROM import_jobs WHERE id = $1",
+            JOB_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Returns the row reports of an import in file order, optionally only
+    /// those with one outcome.
+    pub async fn get_import_rows(
+        &self,
+        id: i32,
+        outcome: Option<&str>,
+    ) -> Result<Vec<ImportRowReport>, sqlx::Error> {
+        sqlx::query_as::<_, ImportRowReport>(
+            "SELECT row_number, outcome, book_id, changes, errors, candidates,
+                coalesce(book -> 'book', 'null') AS book
+            FROM import_job_rows
+            WHERE job_id = $1 AND ($2::TEXT IS NULL OR outcome = $2)
+            ORDER BY row_number",
+        )
+        .bind(id)
+        .bind(outcome)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Queues a failed import again so it continues after its last processed
+    /// row. Returns `false` if the job did not fail.
+    pub async fn retry_import(&self, id: i32) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE import_jobs SET status = 'queued', error = NULL
+            WHERE id = $1 AND status = 'failed'",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Runs every queued import, e.g. those interrupted by a restart.
+    pub async fn resume_imports(self, database: DatabaseService) {
+        let ids = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM import_jobs WHERE status = 'queued' ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_default();
+        for id in ids {
+            self.run_import(&database, id).await;
+        }
+    }
+
+    /// Imports the remaining rows of a queued job, recording each row's
+    /// outcome together with the job's progress so an interrupted job resumes
+    /// where it stopped. A database error stops the job as `failed`.
+    pub async fn run_import(&self, database: &DatabaseService, id: i32) {
+        if let Err(e) = self.process_import(database, id).await {
+            println!("Could not run import job {}: {}", id, e);
+            self.finish_import(id, "failed", Some(&e.to_string())).await;
+        }
+    }
+
+    async fn process_import(&self, database: &DatabaseService, id: i32) -> Result<(), sqlx::Error> {
+        let Some(job) = sqlx::query_as::<_, PendingImport>(
+            "UPDATE import_jobs SET status = 'running'
+            WHERE id = $1 AND status = 'queued'
+            RETURNING user_id, format, dry_run, payload, processed",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(());
+        };
+        let Some(format) = ImportFormat::parse(&job.format) else {
+            self.finish_import(id, "failed", Some("unknown import format"))
+                .await;
+            return Ok(());
+        };
+        let rows = match parse_rows(format, &job.payload) {
+            Ok(rows) => rows,
+            Err(_) => {
+                self.finish_import(id, "failed", Some("the file can no longer be read"))
+                    .await;
+                return Ok(());
+            }
+        };
+
+        let mut index = match (job.user_id, format.is_library()) {
+            (Some(_), true) => Some(database.book_index().await),
+            _ => None,
+        };
+
+        for row in rows.into_iter().skip(job.processed as usize) {
+            let result = match row.fields {
+                Err(errors) => Ok(RowResult::invalid(errors)),
+                Ok(fields) => {
+                    let library = job.user_id.zip(index.as_mut());
+                    let key = row_idempotency_key(id, row.number);
+                    import_row(database, &fields, library, job.dry_run, &key).await
+                }
+            };
+            let result = match result {
+                Ok(result) => result,
+                Err(ImportError::Invalid(errors)) => RowResult::invalid(errors),
+                Err(ImportError::Database) => {
+                    self.finish_import(
+                        id,
+                        "failed",
+                        Some("the catalog database could not be reached"),
+                    )
+                    .await;
+                    return Ok(());
+                }
+            };
+            self.record_row(id, row.number as i32, result).await?;
+        }
+        self.finish_import(id, "completed", None).await;
+        Ok(())
+    }
+
+    async fn record_row(&self, id: i32, row: i32, result: RowResult) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO import_job_rows
+                (job_id, row_number, outcome, book_id, changes, errors, candidates, book)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (job_id, row_number) DO NOTHING",
+        )
+        .bind(id)
+        .bind(row)
+        .bind(result.outcome)
+        .bind(result.book_id)
+        .bind(Json(result.changes))
+        .bind(Json(result.errors))
+        .bind(Json(result.candidates))
+        .bind(Json(result.book))
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(&format!(
+            "UPDATE import_jobs SET processed = processed + 1, {} WHERE id = $1",
+            COUNT_OUTCOME
+        ))
+        .bind(id)
+        .bind(result.outcome)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await
+    }
+
+    /// Settles a row of a library import that was left unmatched, as the
+    /// user chose on the review screen. Returns `false` if the row is not
+    /// waiting for review. The row is claimed first, so a second request for
+    /// the same row finds it already taken and does nothing.
+    pub async fn resolve_row(
+        &self,
+        database: &DatabaseService,
+        id: i32,
+        row: i32,
+        resolution: &Resolution,
+    ) -> Result<bool, ImportError> {
+        let claimed = sqlx::query_as::<_, ClaimedRow>(
+            "UPDATE import_job_rows r SET outcome = 'resolving'
+            FROM import_jobs j
+            WHERE r.job_id = j.id AND j.id = $1 AND r.row_number = $2
+                AND r.outcome = 'unmatched' AND NOT j.dry_run AND j.user_id IS NOT NULL
+            RETURNING j.user_id, r.book",
+        )
+        .bind(id)
+        .bind(row)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            println!("Could not claim row {} of import job {}: {}", row, id, e);
+            ImportError::Database
+        })?;
+        let Some(claimed) = claimed else {
+            return Ok(false);
+        };
+
+        let resolved = match serde_json::from_value::<LibraryEntry>(claimed.book.0) {
+            Ok(entry) => {
+                let key = row_idempotency_key(id, row as usize);
+                database
+                    .resolve_read(claimed.user_id, &entry, resolution, Some(&key))
+                    .await
+            }
+            Err(e) => {
+                println!("Could not read row {} of import job {}: {}", row, id, e);
+                Err(ImportError::Database)
+            }
+        };
+        let (outcome, book_id) = match resolved {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                self.release_row(id, row).await;
+                return Err(error);
+            }
+        };
+
+        let recorded = async {
+            let mut transaction = self.pool.begin().await?;
+            sqlx::query(
+                "UPDATE import_job_rows SET outcome = $3, book_id = $4, candidates = '[]'
+                WHERE job_id = $1 AND row_number = $2 AND outcome = 'resolving'",
+            )
+            .bind(id)
+            .bind(row)
+            .bind(outcome.as_str())
+            .bind(book_id)
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query(&format!(
+                "UPDATE import_jobs SET unmatched = unmatched - 1, {} WHERE id = $1",
+                COUNT_OUTCOME
+            ))
+            .bind(id)
+            .bind(outcome.as_str())
+            .execute(&mut *transaction)
+            .await?;
+            transaction.commit().await
+        };
+        if let Err(e) = recorded.await {
+            println!("Could not resolve row {} of import job {}: {}", row, id, e);
+            self.release_row(id, row).await;
+            return Err(ImportError::Database);
+        }
+        Ok(true)
+    }
+
+    /// Puts a claimed row back up for review after resolving it failed.
+    async fn release_row(&self, id: i32, row: i32) {
+        let _ = sqlx::query(
+            "UPDATE import_job_rows SET outcome = 'unmatched'
+            WHERE job_id = $1 AND row_number = $2 AND outcome = 'resolving'",
+        )
+        .bind(id)
+        .bind(row)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| println!("Could not release row {} of import job {}: {}", row, id, e));
+    }
+
+    async fn finish_import(&self, id: i32, status: &str, error: Option<&str>) {
+        let _ = sqlx::query(
+            "UPDATE import_jobs SET status = $2, error = $3,
+                finished_at = CASE WHEN $2 = 'completed' THEN now() END
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| println!("Could not update import job {}: {}", id, e));
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use sqlx::FromRow;
+
+use super::JobService;
+use crate::{auth::AuthService, database::DatabaseService};
+
+/// How long a requested deletion can still be cancelled.
+const DELETION_GRACE_DAYS: i32 = 14;
+
+/// How often deletions past their grace period are carried out.
+const DELETION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A request to delete an account. It stays `scheduled` until
+/// `scheduled_for`, is `running` while it is carried out, then becomes
+/// `completed` or, if the user changed their mind in time, `cancelled`. The
+/// record is kept as an audit trail of the deletion and holds nothing about
+/// the user but their former id.
+#[derive(Debug, Serialize, FromRow)]
+pub struct AccountDeletion {
+    id: i32,
+    user_id: i32,
+    keep_signals: bool,
+    status: String,
+    error: Option<String>,
+    requested_at: String,
+    scheduled_for: String,
+    cancelled_at: Option<String>,
+    completed_at: Option<String>,
+}
+
+const DELETION_COLUMNS: &str = "id, user_id, keep_signals, status, error,
+    requested_at::TEXT AS requested_at, scheduled_for::TEXT AS scheduled_for,
+    cancelled_at::TEXT AS cancelled_at, completed_at::TEXT AS completed_at";
+
+impl JobService {
+    pub(super) async fn migrate_deletions(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS account_deletions (
+            id SERIAL PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            keep_signals BOOLEAN NOT NULL,
+            status TEXT NOT NULL DEFAULT 'scheduled',
+            error TEXT,
+            requested_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            scheduled_for TIMESTAMPTZ NOT NULL,
+            cancelled_at TIMESTAMPTZ,
+            completed_at TIMESTAMPTZ)",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("UPDATE account_deletions SET status = 'scheduled' WHERE status = 'running'")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Schedules the deletion of an account after the grace period. Asking
+    /// again while a deletion is scheduled only updates `keep_signals`.
+    pub async fn request_deletion(
+        &self,
+        user_id: i32,
+        keep_signals: bool,
+    ) -> Result<AccountDeletion, sqlx::Error> {
+        let scheduled = sqlx::query_as::<_, AccountDeletion>(&format!(
+            "UPDATE account_deletions SET keep_signals = $2
+            WHERE user_id = $1 AND status = 'scheduled'
+            RETURNING {}",
+            DELETION_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(keep_signals)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(deletion) = scheduled {
+            return Ok(deletion);
+        }
+        sqlx::query_as::<_, AccountDeletion>(&format!(
+            "INSERT INTO account_deletions (user_id, keep_signals, scheduled_for)
+            VALUES ($1, $2, now() + make_interval(days => $3))
+            RETURNING {}",
+            DELETION_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(keep_signals)
+        .bind(DELETION_GRACE_DAYS)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Returns the latest deletion request of a user.
+    pub async fn get_deletion(&self, user_id: i32) -> Result<Option<AccountDeletion>, sqlx::Error> {
+        sqlx::query_as::<_, AccountDeletion>(&format!(
+            "SELECT {} FROM account_deletions WHERE user_id = $1 ORDER BY id DESC LIMIT 1",
+            DELETION_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Cancels a scheduled deletion. Returns `false` if none was scheduled.
+    pub async fn cancel_deletion(&self, user_id: i32) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE account_deletions SET status = 'cancelled', cancelled_at = now()
+            WHERE user_id = $1 AND status = 'scheduled'",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes every account whose grace period is over: the graph user and
+    /// their content, the credentials, and the user's imports and data
+    /// exports. Due deletions are claimed first, so they can no longer be
+    /// cancelled and no other run picks them up. A deletion that fails is
+    /// scheduled again and tried on the next run.
+    pub async fn run_deletions(&self, auth: &AuthService, database: &DatabaseService) {
+        let due = sqlx::query_as::<_, (i32, i32, bool)>(
+            "UPDATE account_deletions SET status = 'running'
+            WHERE status = 'scheduled' AND scheduled_for <= now()
+            RETURNING id, user_id, keep_signals",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_default();
+        for (id, user_id, keep_signals) in due {
+            let error = match database.erase_user(user_id, keep_signals).await {
+                Ok(()) => self.delete_user_data(auth, user_id).await.err(),
+                Err(_) => Some("the graph database could not be reached".to_string()),
+            };
+            let _ = sqlx::query(
+                "UPDATE account_deletions
+                SET status = CASE WHEN $2::TEXT IS NULL THEN 'completed' ELSE 'scheduled' END,
+                    error = $2,
+                    completed_at = CASE WHEN $2::TEXT IS NULL THEN now() END
+                WHERE id = $1 AND status = 'running'",
+            )
+            .bind(id)
+            .bind(error)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| println!("Could not update account deletion {}: {}", id, e));
+        }
+    }
+
+    async fn delete_user_data(&self, auth: &AuthService, user_id: i32) -> Result<(), String> {
+        match auth.deregister(user_id).await {
+            Ok(()) | Err(sqlx::Error::RowNotFound) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+        for table in ["import_jobs", "takeouts"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE user_id = $1", table))
+                .bind(user_id)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Carries out due deletions every [`DELETION_INTERVAL`] for as long as
+    /// the server runs.
+    pub async fn run_deletions_periodically(self, auth: AuthService, database: DatabaseService) {
+        let mut interval = rocket::tokio::time::interval(DELETION_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.run_deletions(&auth, &database).await;
+        }
+    }
+}
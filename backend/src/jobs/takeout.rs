@@ -0,0 +1,344 @@
+use std::{
+    io::{self, Cursor, Write},
+    time::Duration,
+};
+
+use rocket::serde::json::{json, serde_json};
+use serde::Serialize;
+use sqlx::FromRow;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use super::JobService;
+use crate::{
+    auth::{self, AuthService},
+    database::{DatabaseService, PersonalData},
+};
+
+/// How long a finished archive can be downloaded.
+const TAKEOUT_TTL_DAYS: i32 = 7;
+
+/// How often archives past their download window are dropped.
+const TAKEOUT_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A requested export of a user's personal data. Once `completed`, the
+/// archive is served at `GET /takeouts/<token>` until `expires_at`, after
+/// which the status reads `expired`. The token is only returned when the
+/// export is requested, never when exports are listed.
+#[derive(Debug, Serialize, FromRow)]
+pub struct Takeout {
+    id: i32,
+    user_id: i32,
+    status: String,
+    error: Option<String>,
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    size: Option<i32>,
+    created_at: String,
+    finished_at: Option<String>,
+    expires_at: Option<String>,
+}
+
+const TAKEOUT_COLUMNS: &str = "id, user_id,
+    CASE WHEN expires_at <= now() THEN 'expired' ELSE status END AS status,
+    error,
+    octet_length(archive) AS size,
+    created_at::TEXT AS created_at, finished_at::TEXT AS finished_at,
+    expires_at::TEXT AS expires_at";
+
+/// A finished archive, or `expired` once its download window has passed.
+pub enum TakeoutArchive {
+    Ready(Vec<u8>),
+    Expired,
+}
+
+fn csv_file(header: &[&str], rows: Vec<Vec<String>>) -> csv::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(header)?;
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+fn text<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(T::to_string).unwrap_or_default()
+}
+
+/// Packs the personal data into a ZIP of JSON files, with CSV copies of the
+/// reads, reviews, shelves and connections for spreadsheets.
+fn build_archive(account: &auth::User, data: &PersonalData) -> io::Result<Vec<u8>> {
+    let reads = csv_file(
+        &[
+            "book_id",
+            "title",
+            "author",
+            "read_at",
+            "rating",
+            "rated_at",
+            "shelves",
+            "imported_from",
+        ],
+        data.reads
+            .iter()
+            .map(|read| {
+                vec![
+                    read.book_id.clone(),
+                    read.title.clone(),
+                    read.author.clone(),
+                    text(&read.read_at),
+                    text(&read.rating),
+                    text(&read.rated_at),
+                    read.shelves.join("; "),
+                    text(&read.imported_from),
+                ]
+            })
+            .collect(),
+    )?;
+    let reviews = csv_file(
+        &["book_id", "title", "rating", "review", "reviewed_at"],
+        data.reads
+            .iter()
+            .filter(|read| read.review.is_some())
+            .map(|read| {
+                vec![
+                    read.book_id.clone(),
+                    read.title.clone(),
+                    text(&read.rating),
+                    text(&read.review),
+                    text(&read.reviewed_at),
+                ]
+            })
+            .collect(),
+    )?;
+    let shelves = csv_file(
+        &["shelf", "book_id", "title"],
+        data.reads
+            .iter()
+            .flat_map(|read| {
+                read.shelves
+                    .iter()
+                    .map(|shelf| vec![shelf.clone(), read.book_id.clone(), read.title.clone()])
+            })
+            .collect(),
+    )?;
+    let connections = csv_file(
+        &["relation", "user_id", "name", "since"],
+        data.connections
+            .iter()
+            .map(|connection| {
+                vec![
+                    connection.relation.clone(),
+                    connection.user_id.to_string(),
+                    connection.name.clone(),
+                    text(&connection.since),
+                ]
+            })
+            .collect(),
+    )?;
+
+    let account = json!({ "id": account.id, "username": account.username });
+    let files: Vec<(&str, Vec<u8>)> = vec![
+        ("account.json", serde_json::to_vec_pretty(&account)?),
+        ("profile.json", serde_json::to_vec_pretty(&data.profile)?),
+        ("reads.json", serde_json::to_vec_pretty(&data.reads)?),
+        ("reads.csv", reads),
+        ("reviews.csv", reviews),
+        ("shelves.csv", shelves),
+        (
+            "connections.json",
+            serde_json::to_vec_pretty(&data.connections)?,
+        ),
+        ("connections.csv", connections),
+        (
+            "followed_authors.json",
+            serde_json::to_vec_pretty(&data.followed_authors)?,
+        ),
+        ("clubs.json", serde_json::to_vec_pretty(&data.clubs)?),
+        ("posts.json", serde_json::to_vec_pretty(&data.posts)?),
+        ("activity.json", serde_json::to_vec_pretty(&data.activity)?),
+        ("lists.json", serde_json::to_vec_pretty(&data.lists)?),
+        (
+            "challenges.json",
+            serde_json::to_vec_pretty(&data.challenges)?,
+        ),
+    ];
+
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in files {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(&contents)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+impl JobService {
+    pub(super) async fn migrate_takeouts(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS takeouts (
+            id SERIAL PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            status TEXT NOT NULL DEFAULT 'queued',
+            error TEXT,
+            token TEXT UNIQUE NOT NULL DEFAULT gen_random_uuid()::TEXT,
+            archive BYTEA,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            finished_at TIMESTAMPTZ,
+            expires_at TIMESTAMPTZ)",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("UPDATE takeouts SET status = 'queued' WHERE status = 'running'")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Queues an export of a user's data and returns it with the token its
+    /// archive will be downloaded with. While one is still queued or running,
+    /// that one is returned instead of starting another.
+    pub async fn create_takeout(&self, user_id: i32) -> Result<Takeout, sqlx::Error> {
+        let pending = sqlx::query_as::<_, Takeout>(&format!(
+            "SELECT {}, token FROM takeouts
+            WHERE user_id = $1 AND status IN ('queued', 'running')
+            ORDER BY id LIMIT 1",
+            TAKEOUT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(takeout) = pending {
+            return Ok(takeout);
+        }
+        sqlx::query_as::<_, Takeout>(&format!(
+            "INSERT INTO takeouts (user_id) VALUES ($1) RETURNING {}, token",
+            TAKEOUT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Returns the exports of a user, newest first, without their tokens.
+    pub async fn get_takeouts(&self, user_id: i32) -> Result<Vec<Takeout>, sqlx::Error> {
+        sqlx::query_as::<_, Takeout>(&format!(
+            "SELECT {} FROM takeouts WHERE user_id = $1 ORDER BY id DESC",
+            TAKEOUT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns the archive of a completed export by its download token.
+    pub async fn get_takeout_archive(
+        &self,
+        token: &str,
+    ) -> Result<Option<TakeoutArchive>, sqlx::Error> {
+        let archive = sqlx::query_as::<_, (bool, Option<Vec<u8>>)>(
+            "SELECT expires_at <= now(), archive FROM takeouts
+            WHERE token = $1 AND status = 'completed'",
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(archive.map(|archive| match archive {
+            (false, Some(archive)) => TakeoutArchive::Ready(archive),
+            _ => TakeoutArchive::Expired,
+        }))
+    }
+
+    /// Drops the archives past their download window. Their rows stay, so
+    /// the exports still show up as `expired`.
+    async fn purge_expired_takeouts(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE takeouts SET archive = NULL
+            WHERE expires_at <= now() AND archive IS NOT NULL",
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            println!("Could not drop expired takeouts: {}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    /// Drops expired archives every [`TAKEOUT_PURGE_INTERVAL`] for as long
+    /// as the server runs.
+    pub async fn purge_expired_takeouts_periodically(self) {
+        let mut interval = rocket::tokio::time::interval(TAKEOUT_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = self.purge_expired_takeouts().await;
+        }
+    }
+
+    /// Runs every queued export.
+    pub async fn resume_takeouts(self, auth: AuthService, database: DatabaseService) {
+        let ids = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM takeouts WHERE status = 'queued' ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_default();
+        for id in ids {
+            self.run_takeout(&auth, &database, id).await;
+        }
+    }
+
+    async fn run_takeout(&self, auth: &AuthService, database: &DatabaseService, id: i32) {
+        let Ok(Some(user_id)) = sqlx::query_scalar::<_, i32>(
+            "UPDATE takeouts SET status = 'running'
+            WHERE id = $1 AND status = 'queued'
+            RETURNING user_id",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        else {
+            return;
+        };
+
+        let account = match auth.get_user(user_id).await {
+            Ok(Some(account)) => account,
+            Ok(None) => return self.fail_takeout(id, "the account no longer exists").await,
+            Err(e) => return self.fail_takeout(id, &e.to_string()).await,
+        };
+        let data = match database.get_personal_data(user_id).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return self.fail_takeout(id, "the user no longer exists").await,
+            Err(_) => {
+                return self
+                    .fail_takeout(id, "the graph database could not be reached")
+                    .await
+            }
+        };
+        let archive = match build_archive(&account, &data) {
+            Ok(archive) => archive,
+            Err(e) => return self.fail_takeout(id, &e.to_string()).await,
+        };
+
+        let _ = sqlx::query(
+            "UPDATE takeouts SET status = 'completed', archive = $2, finished_at = now(),
+                expires_at = now() + make_interval(days => $3)
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(archive)
+        .bind(TAKEOUT_TTL_DAYS)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| println!("Could not store takeout {}: {}", id, e));
+    }
+
+    async fn fail_takeout(&self, id: i32, error: &str) {
+        println!("Could not run takeout {}: {}", id, error);
+        let _ = sqlx::query("UPDATE takeouts SET status = 'failed', error = $2 WHERE id = $1")
+            .bind(id)
+            .bind(error)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| println!("Could not update takeout {}: {}", id, e));
+    }
+}
@@ -1,24 +1,47 @@
 mod auth;
 mod database;
-
-use std::option;
+mod export;
+mod feeds;
+mod import;
+mod jobs;
+mod merge_patch;
+mod normalize;
+mod opds;
+mod preconditions;
 
 use anyhow::Context;
 use auth::AuthService;
-use database::{Book, DatabaseService, User};
+use database::{
+    Author, AuthorUpdate, AuthoredBook, Book, BookFilter, Challenge, Club, DatabaseService,
+    DuplicateCandidate, EventKind, FeedCursor, FeedPage, FieldError, FollowStatus, Genre,
+    ImportError, NewChallenge, NewClub, NewGenre, NewPost, NewReadingList, NewSection, PatchError,
+    Post, PrivacySettings, ReadingList, ReadingStats, Recommendation, RecommendationMode,
+    Resolution, Revision, Section, SeedReport, Series, SeriesDetail, Trashed, User, Work,
+};
+use export::{Download, ExportFormat};
+use feeds::{FeedToken, FEEDS_ROOT};
 use futures::lock::Mutex;
+use import::ImportFormat;
+use jobs::{AccountDeletion, ImportJob, ImportRowReport, JobService, Takeout, TakeoutArchive};
+use opds::{Feed, FeedKind, NavigationEntry, OpdsVersion, OPDS_ROOT};
+use preconditions::{IfMatch, IfNoneMatch, Rejected, Tagged};
 use rocket::{
+    data::{Data, ToByteUnit},
     delete,
     fairing::{Fairing, Info, Kind},
     form::Form,
     get,
-    http::Header,
-    options, post, put, routes,
-    serde::json::Json,
+    http::{uri::Origin, ContentType, Header, RawStr, Status},
+    options, patch, post, put,
+    request::{FromRequest, Outcome},
+    response::{status::BadRequest, stream::TextStream},
+    routes,
+    serde::json::{Json, Value},
     FromForm, Request, Response, State,
 };
 use shuttle_runtime::SecretStore;
 use sqlx::PgPool;
+use std::convert::Infallible;
 
 pub struct CORS;
 
@@ -39,8 +62,9 @@ impl Fairing for CORS {
         ));
         response.set_header(Header::new(
             "Access-Control-Allow-Headers",
-            "Content-Type, Authorization",
+            "Content-Type, Authorization, Idempotency-Key, If-Match, If-None-Match, X-User-Id",
         ));
+        response.set_header(Header::new("Access-Control-Expose-Headers", "ETag"));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
 
         if request.method() == rocket::http::Method::Options {
@@ -50,6 +74,82 @@ impl Fairing for CORS {
     }
 }
 
+/// The optional `Idempotency-Key` header, which makes retried creation
+/// requests return the original result instead of creating a duplicate.
+struct IdempotencyKey(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = request
+            .headers()
+            .get_one("Idempotency-Key")
+            .map(str::trim)
+            .filter(|key| !key.is_empty() && key.len() <= 255)
+            .map(str::to_string);
+        Outcome::Success(IdempotencyKey(key))
+    }
+}
+
+/// The optional `X-User-Id` header naming the user who makes a catalog
+/// change, recorded in the book's revisions.
+struct Editor(Option<i32>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Editor {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user_id = request
+            .headers()
+            .get_one("X-User-Id")
+            .and_then(|id| id.trim().parse().ok());
+        Outcome::Success(Editor(user_id))
+    }
+}
+
+/// The user who signed a request with their username and password, sent as
+/// HTTP Basic credentials in the `Authorization` header. Requests without
+/// valid credentials are rejected with 401 Unauthorized.
+struct Account(i32);
+
+impl Account {
+    /// Rejects requests about another user's private data with 403 Forbidden.
+    fn owns(&self, id: i32) -> Result<(), Status> {
+        match self.0 == id {
+            true => Ok(()),
+            false => Err(Status::Forbidden),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Account {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some((username, password)) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(auth::basic_credentials)
+        else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+        let Outcome::Success(auth_service) = request.guard::<&State<Mutex<AuthService>>>().await
+        else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let auth_service = auth_service.lock().await.clone();
+        match auth_service.login(&username, &password).await {
+            Ok(Some(id)) => Outcome::Success(Account(id)),
+            Ok(None) => Outcome::Error((Status::Unauthorized, ())),
+            Err(_) => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
 #[derive(FromForm)]
 struct UserRequest {
     #[field(name = "username")]
@@ -98,7 +198,7 @@ async fn login(
         .await
     {
         Ok(Some(id)) => Json(Some(auth::User {
-            id: id,
+            id,
             username: user_form.username.clone(),
             password_hash: String::new(),
             salt: String::new(),
@@ -132,16 +232,23 @@ async fn register(
     }
 }
 
-#[options("/auth/users/<id>")]
-async fn options_auth_users_id(id: i32) -> &'static str {
+#[options("/auth/users/<_>")]
+async fn options_auth_users_id() -> &'static str {
     ""
 }
 
+/// Schedules the full deletion of the account, as `POST /users/<id>/deletion`
+/// does without keeping reading signals.
 #[delete("/auth/users/<id>")]
-async fn deregister(id: i32, auth_service: &State<Mutex<AuthService>>) -> Json<bool> {
+async fn deregister(
+    id: i32,
+    account: Account,
+    job_service: &State<Mutex<JobService>>,
+) -> Result<Json<bool>, Status> {
     tracing::info!("TRACING");
-    let auth_service = auth_service.lock().await;
-    Json(auth_service.deregister(id).await.is_ok())
+    account.owns(id)?;
+    let job_service = job_service.lock().await;
+    Ok(Json(job_service.request_deletion(id, false).await.is_ok()))
 }
 
 #[options("/books")]
@@ -149,38 +256,87 @@ async fn options_books() -> &'static str {
     ""
 }
 
-#[get("/books")]
-async fn get_all_books(database_service: &State<Mutex<DatabaseService>>) -> Json<Vec<Book>> {
+#[get("/books?<filter..>")]
+async fn get_all_books(
+    filter: BookFilter,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Book>> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.get_all_books().await)
+    Json(database_service.get_all_books(&filter).await)
+}
+
+#[options("/books/export")]
+async fn options_books_export() -> &'static str {
+    ""
 }
 
-#[options("/books/<id>")]
-async fn options_books_id(id: &str) -> &'static str {
+#[get("/books/export?<format>&<filter..>")]
+async fn export_books(
+    format: ExportFormat,
+    filter: BookFilter,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Download<TextStream![String]>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await.clone();
+    let mut books = database_service
+        .stream_books(&filter)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    // A database error ends the export without its footer, so a truncated
+    // MARCXML file does not pass for a complete one.
+    let stream = TextStream! {
+        yield format.header();
+        while let Some(book) = books.next().await {
+            match book {
+                Ok(book) => yield format.record(&book),
+                Err(_) => return,
+            }
+        }
+        yield format.footer();
+    };
+    Ok(Download::new(
+        stream,
+        format.content_type(),
+        &format!("catalog.{}", format.extension()),
+    ))
+}
+
+#[options("/books/<_>")]
+async fn options_books_id() -> &'static str {
     ""
 }
 
 #[get("/books/<id>")]
 async fn get_book(
     id: &str,
+    if_none_match: IfNoneMatch,
     database_service: &State<Mutex<DatabaseService>>,
-) -> Json<Option<Book>> {
+) -> Tagged<Option<Book>> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.get_book(&id).await)
+    let book = database_service.get_book(id).await;
+    let version = book.as_ref().map(Book::version);
+    Tagged::new(book, version, &if_none_match)
 }
 
 #[post("/books", format = "application/json", data = "<book>")]
 async fn add_book(
     book: Json<Book>,
+    idempotency_key: IdempotencyKey,
+    editor: Editor,
     database_service: &State<Mutex<DatabaseService>>,
-) -> Json<String> {
+) -> Result<Json<String>, BadRequest<Json<Vec<FieldError>>>> {
     tracing::info!("TRACING");
+    let mut book = book.into_inner();
+    book.validate().map_err(|errors| BadRequest(Json(errors)))?;
     let database_service = database_service.lock().await;
-    match database_service.add_book(&book).await {
-        Some(id) => Json(id),
-        None => Json(String::new()),
+    match database_service
+        .add_book(&book, idempotency_key.0.as_deref(), editor.0)
+        .await
+    {
+        Some(id) => Ok(Json(id)),
+        None => Ok(Json(String::new())),
     }
 }
 
@@ -188,18 +344,204 @@ async fn add_book(
 async fn update_book(
     id: &str,
     book: Json<Book>,
+    if_match: IfMatch,
+    editor: Editor,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Rejected> {
+    tracing::info!("TRACING");
+    let mut book = book.into_inner();
+    book.validate()?;
+    let database_service = database_service.lock().await;
+    let version = database_service
+        .book_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service
+        .edit_book(id, &book, editor.0, Some(expected))
+        .await
+    {
+        Ok(true) => Ok(Json(true)),
+        Ok(false) => Err(Status::PreconditionFailed.into()),
+        Err(_) => Ok(Json(false)),
+    }
+}
+
+#[options("/books/duplicates")]
+async fn options_books_duplicates() -> &'static str {
+    ""
+}
+
+#[get("/books/duplicates?<limit>")]
+async fn get_duplicate_books(
+    limit: Option<usize>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<DuplicateCandidate>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .find_duplicate_books(limit.unwrap_or(50).min(500))
+            .await,
+    )
+}
+
+#[options("/books/seed")]
+async fn options_books_seed() -> &'static str {
+    ""
+}
+
+#[post("/books/seed", format = "application/json", data = "<books>")]
+async fn seed_books(
+    books: Json<Vec<Value>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<SeedReport>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    database_service
+        .seed_books(&books)
+        .await
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[options("/books/<_>/merge")]
+async fn options_books_id_merge() -> &'static str {
+    ""
+}
+
+#[post("/books/<id>/merge", format = "application/json", data = "<into_id>")]
+async fn merge_book(
+    id: &str,
+    into_id: Json<String>,
+    editor: Editor,
     database_service: &State<Mutex<DatabaseService>>,
 ) -> Json<bool> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.edit_book(id, &book).await.is_ok())
+    Json(
+        database_service
+            .merge_book(id, &into_id, editor.0)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[patch("/books/<id>", data = "<patch>")]
+async fn patch_book(
+    id: &str,
+    patch: Json<Value>,
+    if_match: IfMatch,
+    editor: Editor,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Rejected> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let version = database_service
+        .book_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service
+        .patch_book(id, &patch, editor.0, expected)
+        .await
+    {
+        Ok(()) => Ok(Json(true)),
+        Err(PatchError::Stale) => Err(Status::PreconditionFailed.into()),
+        Err(PatchError::Invalid(errors)) => Err(errors.into()),
+        Err(_) => Ok(Json(false)),
+    }
+}
+
+#[options("/books/<_>/revisions")]
+async fn options_books_id_revisions() -> &'static str {
+    ""
+}
+
+#[get("/books/<id>/revisions")]
+async fn get_book_revisions(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Revision>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_book_revisions(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/books/<_>/revisions/<_>")]
+async fn options_books_id_revisions_number() -> &'static str {
+    ""
+}
+
+#[get("/books/<id>/revisions/<number>")]
+async fn get_book_revision(
+    id: &str,
+    number: i64,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Option<Revision>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_book_revision(id, number)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/books/<_>/revisions/<_>/revert")]
+async fn options_books_id_revisions_number_revert() -> &'static str {
+    ""
+}
+
+#[post("/books/<id>/revisions/<number>/revert")]
+async fn revert_book(
+    id: &str,
+    number: i64,
+    if_match: IfMatch,
+    editor: Editor,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let version = database_service
+        .book_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service
+        .revert_book(id, number, editor.0, expected)
+        .await
+    {
+        Ok(Some(reverted)) => Ok(Json(reverted)),
+        Ok(None) => Err(Status::PreconditionFailed),
+        Err(_) => Ok(Json(false)),
+    }
 }
 
 #[delete("/books/<id>")]
-async fn delete_book(id: &str, database_service: &State<Mutex<DatabaseService>>) -> Json<bool> {
+async fn delete_book(
+    id: &str,
+    if_match: IfMatch,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Status> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.delete_book(id).await.is_ok())
+    let version = database_service
+        .book_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service.delete_book(id, expected).await {
+        Ok(true) => Ok(Json(true)),
+        Ok(false) => Err(Status::PreconditionFailed),
+        Err(_) => Ok(Json(false)),
+    }
 }
 
 #[get("/users")]
@@ -209,16 +551,22 @@ async fn get_all_users(database_service: &State<Mutex<DatabaseService>>) -> Json
     Json(database_service.get_all_users().await)
 }
 
-#[options("/users/<id>")]
-async fn options_users_id(id: i32) -> &'static str {
+#[options("/users/<_>")]
+async fn options_users_id() -> &'static str {
     ""
 }
 
 #[get("/users/<id>")]
-async fn get_user(id: i32, database_service: &State<Mutex<DatabaseService>>) -> Json<Option<User>> {
+async fn get_user(
+    id: i32,
+    if_none_match: IfNoneMatch,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Tagged<Option<User>> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.get_user(id).await)
+    let user = database_service.get_user(id).await;
+    let version = user.as_ref().map(User::version);
+    Tagged::new(user, version, &if_none_match)
 }
 
 #[options("/users")]
@@ -243,93 +591,1864 @@ async fn add_user(
 async fn update_user(
     id: i32,
     user: Json<User>,
+    if_match: IfMatch,
     database_service: &State<Mutex<DatabaseService>>,
-) -> Json<bool> {
+) -> Result<Json<bool>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let version = database_service
+        .user_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service.edit_user(id, &user, expected).await {
+        Ok(true) => Ok(Json(true)),
+        Ok(false) => Err(Status::PreconditionFailed),
+        Err(_) => Ok(Json(false)),
+    }
+}
+
+#[patch("/users/<id>", data = "<patch>")]
+async fn patch_user(
+    id: i32,
+    patch: Json<Value>,
+    if_match: IfMatch,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Rejected> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.edit_user(id, &user).await.is_ok())
+    let version = database_service
+        .user_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service.patch_user(id, &patch, expected).await {
+        Ok(()) => Ok(Json(true)),
+        Err(PatchError::Stale) => Err(Status::PreconditionFailed.into()),
+        Err(PatchError::Invalid(errors)) => Err(errors.into()),
+        Err(_) => Ok(Json(false)),
+    }
 }
 
 #[delete("/users/<id>")]
-async fn delete_user(id: i32, database_service: &State<Mutex<DatabaseService>>) -> Json<bool> {
+async fn delete_user(
+    id: i32,
+    if_match: IfMatch,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Status> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.delete_user(id).await.is_ok())
+    let version = database_service
+        .user_version(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let expected = if_match.check(version)?;
+    match database_service.delete_user(id, expected).await {
+        Ok(true) => Ok(Json(true)),
+        Ok(false) => Err(Status::PreconditionFailed),
+        Err(_) => Ok(Json(false)),
+    }
 }
 
-#[options("/users/<id>/books")]
-async fn options_users_id_books(id: i32) -> &'static str {
+#[options("/trash/books")]
+async fn options_trash_books() -> &'static str {
     ""
 }
 
-#[get("/users/<id>/books")]
-async fn get_user_books(
+#[get("/trash/books")]
+async fn get_deleted_books(
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Trashed<Book>>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_deleted_books()
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/trash/books/<_>/restore")]
+async fn options_trash_books_id_restore() -> &'static str {
+    ""
+}
+
+#[post("/trash/books/<id>/restore")]
+async fn restore_book(id: &str, database_service: &State<Mutex<DatabaseService>>) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.restore_book(id).await.unwrap_or_default())
+}
+
+#[options("/trash/users")]
+async fn options_trash_users() -> &'static str {
+    ""
+}
+
+#[get("/trash/users")]
+async fn get_deleted_users(
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Trashed<User>>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_deleted_users()
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/trash/users/<_>/restore")]
+async fn options_trash_users_id_restore() -> &'static str {
+    ""
+}
+
+#[post("/trash/users/<id>/restore")]
+async fn restore_user(id: i32, database_service: &State<Mutex<DatabaseService>>) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.restore_user(id).await.unwrap_or_default())
+}
+
+#[options("/users/<_>/privacy")]
+async fn options_users_id_privacy() -> &'static str {
+    ""
+}
+
+#[put(
+    "/users/<id>/privacy",
+    format = "application/json",
+    data = "<settings>"
+)]
+async fn update_privacy(
     id: i32,
+    settings: Json<PrivacySettings>,
     database_service: &State<Mutex<DatabaseService>>,
-) -> Json<Vec<Book>> {
+) -> Json<bool> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.get_user_books(id).await)
+    Json(database_service.update_privacy(id, &settings).await.is_ok())
+}
+
+#[options("/users/<_>/followers")]
+async fn options_users_id_followers() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/followers")]
+async fn get_followers(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<User>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_followers(id).await.unwrap_or_default())
+}
+
+#[options("/users/<_>/following")]
+async fn options_users_id_following() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/following")]
+async fn get_following(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<User>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_following(id).await.unwrap_or_default())
 }
 
 #[post(
-    "/users/<user_id>/books",
+    "/users/<id>/following",
     format = "application/json",
-    data = "<book_id>"
+    data = "<target_id>"
 )]
-async fn add_book_to_user(
-    user_id: i32,
-    book_id: &str,
+async fn follow_user(
+    id: i32,
+    target_id: Json<i32>,
     database_service: &State<Mutex<DatabaseService>>,
-) -> Json<bool> {
+) -> Json<Option<FollowStatus>> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
     Json(
         database_service
-            .add_book_to_user(user_id, book_id)
+            .follow_user(id, *target_id)
             .await
-            .is_ok(),
+            .unwrap_or_default(),
     )
 }
 
-#[delete(
-    "/users/<user_id>/books",
-    format = "application/json",
-    data = "<book_id>"
-)]
-async fn remove_book_from_user(
-    user_id: i32,
-    book_id: &str,
+#[options("/users/<_>/following/<_>")]
+async fn options_users_id_following_id() -> &'static str {
+    ""
+}
+
+#[delete("/users/<id>/following/<target_id>")]
+async fn unfollow_user(
+    id: i32,
+    target_id: i32,
     database_service: &State<Mutex<DatabaseService>>,
 ) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.unfollow_user(id, target_id).await.is_ok())
+}
+
+#[options("/users/<_>/follow-requests")]
+async fn options_users_id_follow_requests() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/follow-requests")]
+async fn get_follow_requests(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<User>> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
     Json(
         database_service
-            .remove_book_from_user(user_id, book_id)
+            .get_follow_requests(id)
             .await
-            .is_ok(),
+            .unwrap_or_default(),
     )
 }
 
-#[options("/users/<id>/recommendations")]
-async fn options_users_id_recommendations(id: i32) -> &'static str {
+#[options("/users/<_>/follow-requests/<_>")]
+async fn options_users_id_follow_requests_id() -> &'static str {
     ""
 }
 
-#[get("/users/<id>/recommendations")]
-async fn get_book_recommendations(
+#[post("/users/<id>/follow-requests/<requester_id>")]
+async fn approve_follow_request(
     id: i32,
+    requester_id: i32,
     database_service: &State<Mutex<DatabaseService>>,
-) -> Json<Vec<Book>> {
+) -> Json<bool> {
     tracing::info!("TRACING");
     let database_service = database_service.lock().await;
-    Json(database_service.recommend_books(id).await)
+    Json(
+        database_service
+            .approve_follow_request(id, requester_id)
+            .await
+            .is_ok(),
+    )
 }
 
-#[shuttle_runtime::main]
-async fn main(
-    #[shuttle_runtime::Secrets] secrets: SecretStore,
+#[delete("/users/<id>/follow-requests/<requester_id>")]
+async fn reject_follow_request(
+    id: i32,
+    requester_id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .reject_follow_request(id, requester_id)
+            .await
+            .is_ok(),
+    )
+}
+
+#[options("/users/<_>/books")]
+async fn options_users_id_books() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/books")]
+async fn get_user_books(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Book>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_user_books(id).await)
+}
+
+#[post(
+    "/users/<user_id>/books",
+    format = "application/json",
+    data = "<book_id>"
+)]
+async fn add_book_to_user(
+    user_id: i32,
+    book_id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .add_book_to_user(user_id, book_id)
+            .await
+            .is_ok(),
+    )
+}
+
+#[delete(
+    "/users/<user_id>/books",
+    format = "application/json",
+    data = "<book_id>"
+)]
+async fn remove_book_from_user(
+    user_id: i32,
+    book_id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .remove_book_from_user(user_id, book_id)
+            .await
+            .is_ok(),
+    )
+}
+
+#[options("/users/<_>/books/<_>/rating")]
+async fn options_users_id_books_id_rating() -> &'static str {
+    ""
+}
+
+#[put(
+    "/users/<user_id>/books/<book_id>/rating",
+    format = "application/json",
+    data = "<rating>"
+)]
+async fn rate_book(
+    user_id: i32,
+    book_id: &str,
+    rating: Json<u8>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    if !(1..=5).contains(&*rating) {
+        return Json(false);
+    }
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .rate_book(user_id, book_id, *rating)
+            .await
+            .is_ok(),
+    )
+}
+
+#[options("/users/<_>/books/<_>/review")]
+async fn options_users_id_books_id_review() -> &'static str {
+    ""
+}
+
+#[put(
+    "/users/<user_id>/books/<book_id>/review",
+    format = "application/json",
+    data = "<review>"
+)]
+async fn review_book(
+    user_id: i32,
+    book_id: &str,
+    review: Json<String>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    if review.trim().is_empty() {
+        return Json(false);
+    }
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .review_book(user_id, book_id, &review)
+            .await
+            .is_ok(),
+    )
+}
+
+#[options("/users/<_>/feed")]
+async fn options_users_id_feed() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/feed?<cursor>&<limit>")]
+async fn get_feed(
+    id: i32,
+    cursor: Option<&str>,
+    limit: Option<i64>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<Option<FeedPage>>, Status> {
+    tracing::info!("TRACING");
+    let cursor = match cursor {
+        Some(cursor) => Some(FeedCursor::parse(cursor).ok_or(Status::BadRequest)?),
+        None => None,
+    };
+    let limit = limit.unwrap_or(20).clamp(1, 100);
+    let database_service = database_service.lock().await;
+    Ok(Json(
+        database_service
+            .get_feed(id, cursor.as_ref(), limit)
+            .await
+            .ok(),
+    ))
+}
+
+#[options("/users/<_>/feed/muted-users")]
+async fn options_users_id_feed_muted_users() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/feed/muted-users")]
+async fn get_muted_users(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<User>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_muted_users(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[post(
+    "/users/<id>/feed/muted-users",
+    format = "application/json",
+    data = "<target_id>"
+)]
+async fn mute_user(
+    id: i32,
+    target_id: Json<i32>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.mute_user(id, *target_id).await.is_ok())
+}
+
+#[options("/users/<_>/feed/muted-users/<_>")]
+async fn options_users_id_feed_muted_users_id() -> &'static str {
+    ""
+}
+
+#[delete("/users/<id>/feed/muted-users/<target_id>")]
+async fn unmute_user(
+    id: i32,
+    target_id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.unmute_user(id, target_id).await.is_ok())
+}
+
+#[options("/users/<_>/feed/muted-types")]
+async fn options_users_id_feed_muted_types() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/feed/muted-types")]
+async fn get_muted_event_types(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<String>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_muted_event_types(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[put(
+    "/users/<id>/feed/muted-types",
+    format = "application/json",
+    data = "<kinds>"
+)]
+async fn set_muted_event_types(
+    id: i32,
+    kinds: Json<Vec<EventKind>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .set_muted_event_types(id, &kinds)
+            .await
+            .is_ok(),
+    )
+}
+
+#[options("/users/<_>/lists")]
+async fn options_users_id_lists() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/lists")]
+async fn get_lists(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<Vec<ReadingList>>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    database_service
+        .get_lists(id)
+        .await
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[post("/users/<id>/lists", format = "application/json", data = "<list>")]
+async fn create_list(
+    id: i32,
+    list: Json<NewReadingList>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<Json<String>>, Status> {
+    tracing::info!("TRACING");
+    if !list.is_valid() {
+        return Err(Status::BadRequest);
+    }
+    let database_service = database_service.lock().await;
+    database_service
+        .create_list(id, &list)
+        .await
+        .map(|id| id.map(Json))
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[options("/lists/<_>/books/<_>")]
+async fn options_lists_id_books_id() -> &'static str {
+    ""
+}
+
+#[put("/lists/<id>/books/<book_id>")]
+async fn add_book_to_list(
+    id: &str,
+    book_id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<()>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    match database_service.add_book_to_list(id, book_id).await {
+        Ok(true) => Ok(Some(())),
+        Ok(false) => Ok(None),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[delete("/lists/<id>/books/<book_id>")]
+async fn remove_book_from_list(
+    id: &str,
+    book_id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .remove_book_from_list(id, book_id)
+            .await
+            .is_ok(),
+    )
+}
+
+#[options("/users/<_>/challenges")]
+async fn options_users_id_challenges() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/challenges")]
+async fn get_challenges(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<Vec<Challenge>>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    database_service
+        .get_challenges(id)
+        .await
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[post(
+    "/users/<id>/challenges",
+    format = "application/json",
+    data = "<challenge>"
+)]
+async fn start_challenge(
+    id: i32,
+    challenge: Json<NewChallenge>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<Json<String>>, Status> {
+    tracing::info!("TRACING");
+    if !challenge.is_valid() {
+        return Err(Status::BadRequest);
+    }
+    let database_service = database_service.lock().await;
+    database_service
+        .start_challenge(id, &challenge)
+        .await
+        .map(|id| id.map(Json))
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[options("/users/<_>/stats")]
+async fn options_users_id_stats() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/stats")]
+async fn get_reading_stats(
+    id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Option<ReadingStats>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.reading_stats(id).await.unwrap_or_default())
+}
+
+#[options("/users/<_>/recommendations")]
+async fn options_users_id_recommendations() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/recommendations?<mode>&<genre>")]
+async fn get_book_recommendations(
+    id: i32,
+    mode: Option<RecommendationMode>,
+    genre: Option<&str>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Recommendation>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    match mode.unwrap_or_default() {
+        RecommendationMode::CoReaders => Json(
+            database_service
+                .recommend_books(id, genre)
+                .await
+                .into_iter()
+                .map(|book| Recommendation::new(book, None))
+                .collect(),
+        ),
+        RecommendationMode::Following => Json(
+            database_service
+                .recommend_books_from_following(id, genre)
+                .await
+                .unwrap_or_default(),
+        ),
+    }
+}
+
+#[options("/clubs")]
+async fn options_clubs() -> &'static str {
+    ""
+}
+
+#[get("/clubs")]
+async fn get_all_clubs(database_service: &State<Mutex<DatabaseService>>) -> Json<Vec<Club>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_all_clubs().await.unwrap_or_default())
+}
+
+#[post("/clubs", format = "application/json", data = "<club>")]
+async fn create_club(
+    club: Json<NewClub>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<String> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    match database_service.create_club(&club).await {
+        Ok(Some(id)) => Json(id),
+        _ => Json(String::new()),
+    }
+}
+
+#[options("/clubs/<_>")]
+async fn options_clubs_id() -> &'static str {
+    ""
+}
+
+#[get("/clubs/<id>")]
+async fn get_club(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Option<Club>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_club(id).await.unwrap_or_default())
+}
+
+#[options("/clubs/<_>/members")]
+async fn options_clubs_id_members() -> &'static str {
+    ""
+}
+
+#[get("/clubs/<id>/members")]
+async fn get_club_members(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<User>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_club_members(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[post("/clubs/<id>/members", format = "application/json", data = "<user_id>")]
+async fn join_club(
+    id: &str,
+    user_id: Json<i32>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.join_club(id, *user_id).await.is_ok())
+}
+
+#[options("/clubs/<_>/members/<_>")]
+async fn options_clubs_id_members_id() -> &'static str {
+    ""
+}
+
+#[delete("/clubs/<id>/members/<user_id>")]
+async fn leave_club(
+    id: &str,
+    user_id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.leave_club(id, user_id).await.is_ok())
+}
+
+#[options("/clubs/<_>/book")]
+async fn options_clubs_id_book() -> &'static str {
+    ""
+}
+
+#[put("/clubs/<id>/book", format = "application/json", data = "<book_id>")]
+async fn set_club_book(
+    id: &str,
+    book_id: Json<String>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.set_club_book(id, &book_id).await.is_ok())
+}
+
+#[options("/clubs/<_>/schedule")]
+async fn options_clubs_id_schedule() -> &'static str {
+    ""
+}
+
+#[get("/clubs/<id>/schedule")]
+async fn get_club_schedule(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Section>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_club_schedule(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[put(
+    "/clubs/<id>/schedule",
+    format = "application/json",
+    data = "<sections>"
+)]
+async fn set_club_schedule(
+    id: &str,
+    sections: Json<Vec<NewSection>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .set_club_schedule(id, &sections)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/clubs/<_>/sections/<_>/posts")]
+async fn options_clubs_id_sections_id_posts() -> &'static str {
+    ""
+}
+
+#[get("/clubs/<id>/sections/<section_id>/posts")]
+async fn get_section_posts(
+    id: &str,
+    section_id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Post>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_section_posts(id, section_id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[post(
+    "/clubs/<id>/sections/<section_id>/posts",
+    format = "application/json",
+    data = "<post>"
+)]
+async fn add_section_post(
+    id: &str,
+    section_id: &str,
+    post: Json<NewPost>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<String> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    match database_service
+        .add_section_post(id, section_id, &post)
+        .await
+    {
+        Ok(Some(id)) => Json(id),
+        _ => Json(String::new()),
+    }
+}
+
+#[options("/clubs/<_>/recommendations")]
+async fn options_clubs_id_recommendations() -> &'static str {
+    ""
+}
+
+#[get("/clubs/<id>/recommendations")]
+async fn get_club_recommendations(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<Recommendation>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .recommend_books_for_club(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/authors")]
+async fn options_authors() -> &'static str {
+    ""
+}
+
+#[get("/authors")]
+async fn get_all_authors(database_service: &State<Mutex<DatabaseService>>) -> Json<Vec<Author>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_all_authors().await.unwrap_or_default())
+}
+
+#[options("/authors/<_>")]
+async fn options_authors_id() -> &'static str {
+    ""
+}
+
+#[get("/authors/<id>")]
+async fn get_author(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Option<Author>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_author(id).await.unwrap_or_default())
+}
+
+#[put("/authors/<id>", format = "application/json", data = "<author>")]
+async fn update_author(
+    id: &str,
+    author: Json<AuthorUpdate>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.edit_author(id, &author).await.is_ok())
+}
+
+#[options("/authors/<_>/works")]
+async fn options_authors_id_works() -> &'static str {
+    ""
+}
+
+#[get("/authors/<id>/works")]
+async fn get_author_works(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<AuthoredBook>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_author_works(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/authors/<_>/followers")]
+async fn options_authors_id_followers() -> &'static str {
+    ""
+}
+
+#[get("/authors/<id>/followers")]
+async fn get_author_followers(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Vec<User>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .get_author_followers(id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[post(
+    "/authors/<id>/followers",
+    format = "application/json",
+    data = "<user_id>"
+)]
+async fn follow_author(
+    id: &str,
+    user_id: Json<i32>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.follow_author(id, *user_id).await.is_ok())
+}
+
+#[options("/authors/<_>/followers/<_>")]
+async fn options_authors_id_followers_id() -> &'static str {
+    ""
+}
+
+#[delete("/authors/<id>/followers/<user_id>")]
+async fn unfollow_author(
+    id: &str,
+    user_id: i32,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.unfollow_author(id, user_id).await.is_ok())
+}
+
+#[options("/authors/<_>/merge")]
+async fn options_authors_id_merge() -> &'static str {
+    ""
+}
+
+#[post("/authors/<id>/merge", format = "application/json", data = "<into_id>")]
+async fn merge_author(
+    id: &str,
+    into_id: Json<String>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.merge_author(id, &into_id).await.is_ok())
+}
+
+#[options("/genres")]
+async fn options_genres() -> &'static str {
+    ""
+}
+
+#[get("/genres")]
+async fn get_genre_tree(database_service: &State<Mutex<DatabaseService>>) -> Json<Vec<Genre>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_genre_tree().await.unwrap_or_default())
+}
+
+#[post("/genres", format = "application/json", data = "<genre>")]
+async fn add_genre(
+    genre: Json<NewGenre>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<String> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    match database_service.add_genre(&genre).await {
+        Ok(Some(id)) => Json(id),
+        _ => Json(String::new()),
+    }
+}
+
+#[options("/genres/<_>")]
+async fn options_genres_id() -> &'static str {
+    ""
+}
+
+#[put("/genres/<id>", format = "application/json", data = "<genre>")]
+async fn update_genre(
+    id: &str,
+    genre: Json<NewGenre>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .edit_genre(id, &genre)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[delete("/genres/<id>")]
+async fn delete_genre(id: &str, database_service: &State<Mutex<DatabaseService>>) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.delete_genre(id).await.is_ok())
+}
+
+#[options("/genres/<_>/merge")]
+async fn options_genres_id_merge() -> &'static str {
+    ""
+}
+
+#[post("/genres/<id>/merge", format = "application/json", data = "<into_id>")]
+async fn merge_genre(
+    id: &str,
+    into_id: Json<String>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .merge_genre(id, &into_id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/series")]
+async fn options_series() -> &'static str {
+    ""
+}
+
+#[get("/series")]
+async fn get_all_series(database_service: &State<Mutex<DatabaseService>>) -> Json<Vec<Series>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_all_series().await.unwrap_or_default())
+}
+
+#[options("/series/<_>")]
+async fn options_series_id() -> &'static str {
+    ""
+}
+
+#[get("/series/<id>")]
+async fn get_series(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Option<SeriesDetail>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_series(id).await.unwrap_or_default())
+}
+
+#[options("/works/<_>")]
+async fn options_works_id() -> &'static str {
+    ""
+}
+
+#[get("/works/<id>")]
+async fn get_work(
+    id: &str,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<Option<Work>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(database_service.get_work(id).await.unwrap_or_default())
+}
+
+#[options("/works/<_>/merge")]
+async fn options_works_id_merge() -> &'static str {
+    ""
+}
+
+#[post("/works/<id>/merge", format = "application/json", data = "<into_id>")]
+async fn merge_work(
+    id: &str,
+    into_id: Json<String>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    Json(
+        database_service
+            .merge_work(id, &into_id)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+/// The largest file `POST /imports` accepts.
+const MAX_IMPORT_SIZE_MIB: u8 = 64;
+
+#[options("/imports")]
+async fn options_imports() -> &'static str {
+    ""
+}
+
+/// Reads an uploaded import file, which must be UTF-8 text within the size
+/// limit.
+async fn read_import_file(file: Data<'_>) -> Result<String, BadRequest<Json<Vec<FieldError>>>> {
+    file.open(MAX_IMPORT_SIZE_MIB.mebibytes())
+        .into_string()
+        .await
+        .ok()
+        .filter(|file| file.is_complete())
+        .ok_or_else(|| {
+            BadRequest(Json(vec![FieldError::new(
+                "file",
+                format!(
+                    "the file must be UTF-8 text of at most {} MiB",
+                    MAX_IMPORT_SIZE_MIB
+                ),
+            )]))
+        })
+        .map(|file| file.into_inner())
+}
+
+#[post("/imports?<format>&<dry_run>", data = "<file>")]
+async fn create_import(
+    format: ImportFormat,
+    dry_run: Option<bool>,
+    file: Data<'_>,
+    job_service: &State<Mutex<JobService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<ImportJob>, BadRequest<Json<Vec<FieldError>>>> {
+    tracing::info!("TRACING");
+    let file = read_import_file(file).await?;
+    let job_service = job_service.lock().await.clone();
+    let job = job_service
+        .create_import(format, dry_run.unwrap_or_default(), &file, None)
+        .await
+        .map_err(|errors| BadRequest(Json(errors)))?;
+    let database_service = database_service.lock().await.clone();
+    rocket::tokio::spawn(job_service.resume_imports(database_service));
+    Ok(Json(job))
+}
+
+#[get("/imports")]
+async fn get_imports(job_service: &State<Mutex<JobService>>) -> Json<Vec<ImportJob>> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await;
+    Json(job_service.get_imports(None).await.unwrap_or_default())
+}
+
+#[options("/users/<_>/imports")]
+async fn options_users_id_imports() -> &'static str {
+    ""
+}
+
+#[post("/users/<id>/imports?<format>&<dry_run>", data = "<file>")]
+async fn create_user_import(
+    id: i32,
+    format: ImportFormat,
+    dry_run: Option<bool>,
+    file: Data<'_>,
+    job_service: &State<Mutex<JobService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<Json<ImportJob>>, BadRequest<Json<Vec<FieldError>>>> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await.clone();
+    if database_service.get_user(id).await.is_none() {
+        return Ok(None);
+    }
+    let file = read_import_file(file).await?;
+    let job_service = job_service.lock().await.clone();
+    let job = job_service
+        .create_import(format, dry_run.unwrap_or_default(), &file, Some(id))
+        .await
+        .map_err(|errors| BadRequest(Json(errors)))?;
+    rocket::tokio::spawn(job_service.resume_imports(database_service));
+    Ok(Some(Json(job)))
+}
+
+#[get("/users/<id>/imports")]
+async fn get_user_imports(id: i32, job_service: &State<Mutex<JobService>>) -> Json<Vec<ImportJob>> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await;
+    Json(job_service.get_imports(Some(id)).await.unwrap_or_default())
+}
+
+#[options("/imports/<_>")]
+async fn options_imports_id() -> &'static str {
+    ""
+}
+
+#[get("/imports/<id>")]
+async fn get_import(id: i32, job_service: &State<Mutex<JobService>>) -> Json<Option<ImportJob>> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await;
+    Json(job_service.get_import(id).await.unwrap_or_default())
+}
+
+#[options("/imports/<_>/rows")]
+async fn options_imports_id_rows() -> &'static str {
+    ""
+}
+
+#[get("/imports/<id>/rows?<outcome>")]
+async fn get_import_rows(
+    id: i32,
+    outcome: Option<&str>,
+    job_service: &State<Mutex<JobService>>,
+) -> Json<Vec<ImportRowReport>> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await;
+    Json(
+        job_service
+            .get_import_rows(id, outcome)
+            .await
+            .unwrap_or_default(),
+    )
+}
+
+#[options("/imports/<_>/rows/<_>/resolve")]
+async fn options_imports_id_rows_row_resolve() -> &'static str {
+    ""
+}
+
+#[post("/imports/<id>/rows/<row>/resolve", data = "<resolution>")]
+async fn resolve_import_row(
+    id: i32,
+    row: i32,
+    resolution: Json<Resolution>,
+    job_service: &State<Mutex<JobService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Json<bool>, Rejected> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await.clone();
+    let database_service = database_service.lock().await;
+    match job_service
+        .resolve_row(&database_service, id, row, &resolution)
+        .await
+    {
+        Ok(resolved) => Ok(Json(resolved)),
+        Err(ImportError::Invalid(errors)) => Err(errors.into()),
+        Err(ImportError::Database) => Err(Status::InternalServerError.into()),
+    }
+}
+
+#[options("/imports/<_>/retry")]
+async fn options_imports_id_retry() -> &'static str {
+    ""
+}
+
+#[post("/imports/<id>/retry")]
+async fn retry_import(
+    id: i32,
+    job_service: &State<Mutex<JobService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Json<bool> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await.clone();
+    let retried = job_service.retry_import(id).await.unwrap_or_default();
+    if retried {
+        let database_service = database_service.lock().await.clone();
+        rocket::tokio::spawn(job_service.resume_imports(database_service));
+    }
+    Json(retried)
+}
+
+#[options("/users/<_>/deletion")]
+async fn options_users_id_deletion() -> &'static str {
+    ""
+}
+
+#[post("/users/<id>/deletion?<keep_signals>")]
+async fn request_deletion(
+    id: i32,
+    keep_signals: Option<bool>,
+    account: Account,
+    job_service: &State<Mutex<JobService>>,
+) -> Result<Json<AccountDeletion>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let job_service = job_service.lock().await;
+    job_service
+        .request_deletion(id, keep_signals.unwrap_or_default())
+        .await
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[get("/users/<id>/deletion")]
+async fn get_deletion(
+    id: i32,
+    account: Account,
+    job_service: &State<Mutex<JobService>>,
+) -> Result<Json<Option<AccountDeletion>>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let job_service = job_service.lock().await;
+    Ok(Json(job_service.get_deletion(id).await.unwrap_or_default()))
+}
+
+#[delete("/users/<id>/deletion")]
+async fn cancel_deletion(
+    id: i32,
+    account: Account,
+    job_service: &State<Mutex<JobService>>,
+) -> Result<Json<bool>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let job_service = job_service.lock().await;
+    Ok(Json(
+        job_service.cancel_deletion(id).await.unwrap_or_default(),
+    ))
+}
+
+#[options("/users/<_>/takeouts")]
+async fn options_users_id_takeouts() -> &'static str {
+    ""
+}
+
+#[post("/users/<id>/takeouts")]
+async fn create_takeout(
+    id: i32,
+    account: Account,
+    job_service: &State<Mutex<JobService>>,
+    auth_service: &State<Mutex<AuthService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<Json<Takeout>>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let database_service = database_service.lock().await.clone();
+    if database_service.get_user(id).await.is_none() {
+        return Ok(None);
+    }
+    let job_service = job_service.lock().await.clone();
+    let takeout = job_service
+        .create_takeout(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let auth_service = auth_service.lock().await.clone();
+    rocket::tokio::spawn(job_service.resume_takeouts(auth_service, database_service));
+    Ok(Some(Json(takeout)))
+}
+
+#[get("/users/<id>/takeouts")]
+async fn get_takeouts(
+    id: i32,
+    account: Account,
+    job_service: &State<Mutex<JobService>>,
+) -> Result<Json<Vec<Takeout>>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let job_service = job_service.lock().await;
+    Ok(Json(job_service.get_takeouts(id).await.unwrap_or_default()))
+}
+
+#[options("/takeouts/<_>")]
+async fn options_takeouts_token() -> &'static str {
+    ""
+}
+
+#[get("/takeouts/<token>")]
+async fn download_takeout(
+    token: &str,
+    job_service: &State<Mutex<JobService>>,
+) -> Result<Download<Vec<u8>>, Status> {
+    tracing::info!("TRACING");
+    let job_service = job_service.lock().await;
+    match job_service.get_takeout_archive(token).await {
+        Ok(Some(TakeoutArchive::Ready(archive))) => Ok(Download::new(
+            archive,
+            ContentType::ZIP,
+            "book-recommender-data.zip",
+        )),
+        Ok(Some(TakeoutArchive::Expired)) => Err(Status::Gone),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[options("/opds")]
+async fn options_opds() -> &'static str {
+    ""
+}
+
+#[get("/opds")]
+async fn opds_root(version: OpdsVersion) -> (ContentType, String) {
+    tracing::info!("TRACING");
+    let entries = vec![
+        NavigationEntry::new(
+            "New books",
+            format!("{}/new", OPDS_ROOT),
+            FeedKind::Acquisition,
+            Some("The latest additions to the catalog".to_string()),
+        ),
+        NavigationEntry::new(
+            "All books",
+            format!("{}/books", OPDS_ROOT),
+            FeedKind::Acquisition,
+            None,
+        ),
+        NavigationEntry::new(
+            "Genres",
+            format!("{}/genres", OPDS_ROOT),
+            FeedKind::Navigation,
+            None,
+        ),
+        NavigationEntry::new(
+            "Authors",
+            format!("{}/authors", OPDS_ROOT),
+            FeedKind::Navigation,
+            None,
+        ),
+    ];
+    Feed::navigation(OPDS_ROOT, "Book Recommender", entries, None).render(version)
+}
+
+#[options("/opds/search.xml")]
+async fn options_opds_search() -> &'static str {
+    ""
+}
+
+#[get("/opds/search.xml")]
+async fn opds_search() -> (ContentType, String) {
+    tracing::info!("TRACING");
+    (
+        ContentType::new("application", "opensearchdescription+xml"),
+        opds::open_search_description(),
+    )
+}
+
+#[options("/opds/new")]
+async fn options_opds_new() -> &'static str {
+    ""
+}
+
+#[get("/opds/new?<page>")]
+async fn opds_new(
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<(ContentType, String), Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let books = database_service
+        .get_new_books(opds::NEW_BOOKS)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(Feed::acquisition(&uri.to_string(), "New books", &books, page).render(version))
+}
+
+#[options("/opds/books")]
+async fn options_opds_books() -> &'static str {
+    ""
+}
+
+#[get("/opds/books?<page>&<filter..>")]
+async fn opds_books(
+    page: Option<usize>,
+    filter: BookFilter,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<(ContentType, String), Status> {
+    tracing::info!("TRACING");
+    let (skip, limit) = opds::page_window(page);
+    let database_service = database_service.lock().await;
+    let books = database_service
+        .get_books_window(&filter, skip, limit)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(Feed::acquisition_window(&uri.to_string(), "Books", &books, page).render(version))
+}
+
+/// Navigation entries for genres: genres with subgenres lead to their own
+/// navigation feed, the others straight to their books.
+fn genre_entries(genres: &[Genre]) -> Vec<NavigationEntry> {
+    genres
+        .iter()
+        .map(|genre| {
+            let summary = Some(format!("{} books", genre.books()));
+            if genre.children().is_empty() {
+                NavigationEntry::new(
+                    genre.name(),
+                    format!("{}/books?genre={}", OPDS_ROOT, genre.id()),
+                    FeedKind::Acquisition,
+                    summary,
+                )
+            } else {
+                NavigationEntry::new(
+                    genre.name(),
+                    format!("{}/genres/{}", OPDS_ROOT, genre.id()),
+                    FeedKind::Navigation,
+                    summary,
+                )
+            }
+        })
+        .collect()
+}
+
+#[options("/opds/genres")]
+async fn options_opds_genres() -> &'static str {
+    ""
+}
+
+#[get("/opds/genres?<page>")]
+async fn opds_genres(
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<(ContentType, String), Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let genres = database_service
+        .get_genre_tree()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let entries = genre_entries(&genres);
+    Ok(Feed::navigation(&uri.to_string(), "Genres", entries, page).render(version))
+}
+
+#[options("/opds/genres/<_>")]
+async fn options_opds_genres_id() -> &'static str {
+    ""
+}
+
+#[get("/opds/genres/<id>?<page>")]
+async fn opds_genre(
+    id: &str,
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<(ContentType, String)>, Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let genres = database_service
+        .get_genre_tree()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(genre) = Genre::find(&genres, id) else {
+        return Ok(None);
+    };
+    let name = genre.name();
+    let mut entries = vec![NavigationEntry::new(
+        &format!("All {}", name),
+        format!("{}/books?genre={}", OPDS_ROOT, id),
+        FeedKind::Acquisition,
+        None,
+    )];
+    entries.extend(genre_entries(genre.children()));
+    Ok(Some(
+        Feed::navigation(&uri.to_string(), name, entries, page).render(version),
+    ))
+}
+
+#[options("/opds/authors")]
+async fn options_opds_authors() -> &'static str {
+    ""
+}
+
+#[get("/opds/authors?<page>")]
+async fn opds_authors(
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<(ContentType, String), Status> {
+    tracing::info!("TRACING");
+    let (skip, limit) = opds::page_window(page);
+    let database_service = database_service.lock().await;
+    let authors = database_service
+        .get_authors_window(skip, limit)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let entries = authors
+        .iter()
+        .map(|author| {
+            NavigationEntry::new(
+                author.name(),
+                format!("{}/books?author={}", OPDS_ROOT, author.id()),
+                FeedKind::Acquisition,
+                Some(format!("{} books", author.works())),
+            )
+        })
+        .collect();
+    Ok(Feed::navigation_window(&uri.to_string(), "Authors", entries, page).render(version))
+}
+
+/// The user a feed token belongs to. Private feeds are addressed by the
+/// token, so only those the user gave the address to can read them.
+async fn feed_owner(
+    token: &str,
+    auth_service: &State<Mutex<AuthService>>,
+) -> Result<Option<i32>, Status> {
+    let auth_service = auth_service.lock().await.clone();
+    auth_service
+        .feed_token_user(token)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[options("/opds/users/<_>")]
+async fn options_opds_users_token() -> &'static str {
+    ""
+}
+
+#[get("/opds/users/<token>")]
+async fn opds_user(
+    token: &str,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    auth_service: &State<Mutex<AuthService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<(ContentType, String)>, Status> {
+    tracing::info!("TRACING");
+    let Some(id) = feed_owner(token, auth_service).await? else {
+        return Ok(None);
+    };
+    let database_service = database_service.lock().await;
+    let Some(user) = database_service.get_user(id).await else {
+        return Ok(None);
+    };
+    let shelves = database_service
+        .get_shelves(id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let mut entries = vec![
+        NavigationEntry::new(
+            "Read",
+            format!("{}/users/{}/books", OPDS_ROOT, token),
+            FeedKind::Acquisition,
+            None,
+        ),
+        NavigationEntry::new(
+            "Recommended for you",
+            format!("{}/users/{}/recommendations", OPDS_ROOT, token),
+            FeedKind::Acquisition,
+            None,
+        ),
+    ];
+    entries.extend(shelves.iter().map(|shelf| {
+        NavigationEntry::new(
+            shelf.name(),
+            format!(
+                "{}/users/{}/shelves/{}",
+                OPDS_ROOT,
+                token,
+                RawStr::new(shelf.name()).percent_encode()
+            ),
+            FeedKind::Acquisition,
+            Some(format!("{} books", shelf.books())),
+        )
+    }));
+    let title = format!("{}'s books", user.name());
+    Ok(Some(
+        Feed::navigation(&uri.to_string(), &title, entries, None).render(version),
+    ))
+}
+
+#[options("/opds/users/<_>/books")]
+async fn options_opds_users_token_books() -> &'static str {
+    ""
+}
+
+#[get("/opds/users/<token>/books?<page>")]
+async fn opds_user_books(
+    token: &str,
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    auth_service: &State<Mutex<AuthService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<(ContentType, String)>, Status> {
+    tracing::info!("TRACING");
+    let Some(id) = feed_owner(token, auth_service).await? else {
+        return Ok(None);
+    };
+    let database_service = database_service.lock().await;
+    let books = database_service.get_user_books(id).await;
+    Ok(Some(
+        Feed::acquisition(&uri.to_string(), "Read", &books, page).render(version),
+    ))
+}
+
+#[options("/opds/users/<_>/shelves/<_>")]
+async fn options_opds_users_token_shelves_shelf() -> &'static str {
+    ""
+}
+
+#[get("/opds/users/<token>/shelves/<shelf>?<page>")]
+async fn opds_user_shelf(
+    token: &str,
+    shelf: &str,
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    auth_service: &State<Mutex<AuthService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<(ContentType, String)>, Status> {
+    tracing::info!("TRACING");
+    let Some(id) = feed_owner(token, auth_service).await? else {
+        return Ok(None);
+    };
+    let database_service = database_service.lock().await;
+    let books = database_service
+        .get_shelf_books(id, shelf)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(Some(
+        Feed::acquisition(&uri.to_string(), shelf, &books, page).render(version),
+    ))
+}
+
+#[options("/opds/users/<_>/recommendations")]
+async fn options_opds_users_token_recommendations() -> &'static str {
+    ""
+}
+
+#[get("/opds/users/<token>/recommendations?<page>")]
+async fn opds_user_recommendations(
+    token: &str,
+    page: Option<usize>,
+    version: OpdsVersion,
+    uri: &Origin<'_>,
+    auth_service: &State<Mutex<AuthService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<(ContentType, String)>, Status> {
+    tracing::info!("TRACING");
+    let Some(id) = feed_owner(token, auth_service).await? else {
+        return Ok(None);
+    };
+    let database_service = database_service.lock().await;
+    let books = database_service.recommend_books(id, None).await;
+    Ok(Some(
+        Feed::acquisition(&uri.to_string(), "Recommended for you", &books, page).render(version),
+    ))
+}
+
+#[options("/feeds/new")]
+async fn options_feeds_new() -> &'static str {
+    ""
+}
+
+#[get("/feeds/new")]
+async fn feed_new_books(
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<(ContentType, String), Status> {
+    tracing::info!("TRACING");
+    let database_service = database_service.lock().await;
+    let books = database_service
+        .get_new_books(opds::NEW_BOOKS)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok((
+        feeds::content_type(),
+        feeds::atom_feed("new", "New books", &format!("{}/new", FEEDS_ROOT), &books),
+    ))
+}
+
+#[options("/feeds/recommendations/<_>")]
+async fn options_feeds_recommendations_token() -> &'static str {
+    ""
+}
+
+#[get("/feeds/recommendations/<token>")]
+async fn feed_recommendations(
+    token: &str,
+    auth_service: &State<Mutex<AuthService>>,
+    database_service: &State<Mutex<DatabaseService>>,
+) -> Result<Option<(ContentType, String)>, Status> {
+    tracing::info!("TRACING");
+    let Some(id) = feed_owner(token, auth_service).await? else {
+        return Ok(None);
+    };
+    let database_service = database_service.lock().await;
+    let books = database_service.recommend_books(id, None).await;
+    Ok(Some((
+        feeds::content_type(),
+        feeds::atom_feed(
+            &format!("users:{}:recommendations", id),
+            "Recommended for you",
+            &format!("{}/recommendations/{}", FEEDS_ROOT, token),
+            &books,
+        ),
+    )))
+}
+
+#[options("/users/<_>/feed-token")]
+async fn options_users_id_feed_token() -> &'static str {
+    ""
+}
+
+#[get("/users/<id>/feed-token")]
+async fn get_feed_token(
+    id: i32,
+    account: Account,
+    auth_service: &State<Mutex<AuthService>>,
+) -> Result<Option<Json<FeedToken>>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let auth_service = auth_service.lock().await;
+    auth_service
+        .feed_token(id)
+        .await
+        .map(|token| token.map(|token| Json(FeedToken::new(token))))
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[post("/users/<id>/feed-token")]
+async fn reset_feed_token(
+    id: i32,
+    account: Account,
+    auth_service: &State<Mutex<AuthService>>,
+) -> Result<Option<Json<FeedToken>>, Status> {
+    tracing::info!("TRACING");
+    account.owns(id)?;
+    let auth_service = auth_service.lock().await;
+    auth_service
+        .reset_feed_token(id)
+        .await
+        .map(|token| token.map(|token| Json(FeedToken::new(token))))
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[shuttle_runtime::main]
+async fn main(
+    #[shuttle_runtime::Secrets] secrets: SecretStore,
     #[shuttle_shared_db::Postgres] pool: PgPool,
 ) -> shuttle_rocket::ShuttleRocket {
     sqlx::query(
@@ -342,7 +2461,11 @@ async fn main(
     .execute(&pool)
     .await
     .expect("COULD NOT CREATE TABLE");
-    let auth_service = Mutex::new(AuthService::new(pool.clone()));
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS feed_token TEXT UNIQUE")
+        .execute(&pool)
+        .await
+        .expect("COULD NOT ALTER TABLE");
+    let auth_service = AuthService::new(pool.clone());
     let neo4j_uri = secrets
         .get("NEO4J_URI")
         .context("NEO4J URI NOT FOUND.")
@@ -355,15 +2478,44 @@ async fn main(
         .get("NEO4J_PASSWORD")
         .context("NEO4J PASSWORD NOT FOUND.")
         .unwrap();
-    let database_service = Mutex::new(
-        DatabaseService::new(&neo4j_uri, &neo4j_user, &neo4j_password)
-            .await
-            .expect("Failed to connect to Neo4j instance"),
+    let database_service = DatabaseService::new(&neo4j_uri, &neo4j_user, &neo4j_password)
+        .await
+        .expect("Failed to connect to Neo4j instance");
+    database_service
+        .migrate()
+        .await
+        .expect("Failed to migrate Neo4j instance");
+    rocket::tokio::spawn(database_service.clone().purge_trash_periodically());
+    rocket::tokio::spawn(
+        database_service
+            .clone()
+            .refresh_community_stats_periodically(),
+    );
+    let job_service = JobService::new(pool.clone());
+    job_service
+        .migrate()
+        .await
+        .expect("Failed to migrate job tables");
+    rocket::tokio::spawn(job_service.clone().resume_imports(database_service.clone()));
+    rocket::tokio::spawn(
+        job_service
+            .clone()
+            .resume_takeouts(auth_service.clone(), database_service.clone()),
+    );
+    rocket::tokio::spawn(job_service.clone().purge_expired_takeouts_periodically());
+    rocket::tokio::spawn(
+        job_service
+            .clone()
+            .run_deletions_periodically(auth_service.clone(), database_service.clone()),
     );
+    let auth_service = Mutex::new(auth_service);
+    let job_service = Mutex::new(job_service);
+    let database_service = Mutex::new(database_service);
     let rocket = rocket::build()
         .attach(CORS)
         .manage(auth_service)
         .manage(database_service)
+        .manage(job_service)
         .mount("/", routes![index])
         .mount(
             "/api",
@@ -382,6 +2534,7 @@ async fn main(
                 get_book,
                 add_book,
                 update_book,
+                patch_book,
                 delete_book,
                 get_all_users,
                 options_users_id,
@@ -389,13 +2542,177 @@ async fn main(
                 options_users,
                 add_user,
                 update_user,
+                patch_user,
                 delete_user,
+                options_users_id_privacy,
+                update_privacy,
+                options_users_id_followers,
+                get_followers,
+                options_users_id_following,
+                get_following,
+                follow_user,
+                options_users_id_following_id,
+                unfollow_user,
+                options_users_id_follow_requests,
+                get_follow_requests,
+                options_users_id_follow_requests_id,
+                approve_follow_request,
+                reject_follow_request,
                 options_users_id_books,
                 get_user_books,
                 add_book_to_user,
                 remove_book_from_user,
+                options_users_id_books_id_rating,
+                rate_book,
+                options_users_id_books_id_review,
+                review_book,
+                options_users_id_feed,
+                get_feed,
+                options_users_id_feed_muted_users,
+                get_muted_users,
+                mute_user,
+                options_users_id_feed_muted_users_id,
+                unmute_user,
+                options_users_id_feed_muted_types,
+                get_muted_event_types,
+                set_muted_event_types,
+                options_users_id_lists,
+                get_lists,
+                create_list,
+                options_lists_id_books_id,
+                add_book_to_list,
+                remove_book_from_list,
+                options_users_id_challenges,
+                get_challenges,
+                start_challenge,
+                options_users_id_stats,
+                get_reading_stats,
                 options_users_id_recommendations,
-                get_book_recommendations
+                get_book_recommendations,
+                options_clubs,
+                get_all_clubs,
+                create_club,
+                options_clubs_id,
+                get_club,
+                options_clubs_id_members,
+                get_club_members,
+                join_club,
+                options_clubs_id_members_id,
+                leave_club,
+                options_clubs_id_book,
+                set_club_book,
+                options_clubs_id_schedule,
+                get_club_schedule,
+                set_club_schedule,
+                options_clubs_id_sections_id_posts,
+                get_section_posts,
+                add_section_post,
+                options_clubs_id_recommendations,
+                get_club_recommendations,
+                options_authors,
+                get_all_authors,
+                options_authors_id,
+                get_author,
+                update_author,
+                options_authors_id_works,
+                get_author_works,
+                options_authors_id_followers,
+                get_author_followers,
+                follow_author,
+                options_authors_id_followers_id,
+                unfollow_author,
+                options_authors_id_merge,
+                merge_author,
+                options_genres,
+                get_genre_tree,
+                add_genre,
+                options_genres_id,
+                update_genre,
+                delete_genre,
+                options_genres_id_merge,
+                merge_genre,
+                options_series,
+                get_all_series,
+                options_series_id,
+                get_series,
+                options_works_id,
+                get_work,
+                options_works_id_merge,
+                merge_work,
+                options_books_duplicates,
+                get_duplicate_books,
+                options_books_id_merge,
+                merge_book,
+                options_books_id_revisions,
+                get_book_revisions,
+                options_books_id_revisions_number,
+                get_book_revision,
+                options_books_id_revisions_number_revert,
+                revert_book,
+                options_trash_books,
+                get_deleted_books,
+                options_trash_books_id_restore,
+                restore_book,
+                options_trash_users,
+                get_deleted_users,
+                options_trash_users_id_restore,
+                restore_user,
+                options_imports,
+                create_import,
+                get_imports,
+                options_imports_id,
+                get_import,
+                options_imports_id_rows,
+                get_import_rows,
+                options_imports_id_retry,
+                retry_import,
+                options_users_id_imports,
+                create_user_import,
+                get_user_imports,
+                options_imports_id_rows_row_resolve,
+                resolve_import_row,
+                options_books_seed,
+                seed_books,
+                options_books_export,
+                export_books,
+                options_users_id_takeouts,
+                create_takeout,
+                get_takeouts,
+                options_takeouts_token,
+                download_takeout,
+                options_users_id_deletion,
+                request_deletion,
+                get_deletion,
+                cancel_deletion,
+                options_opds,
+                opds_root,
+                options_opds_search,
+                opds_search,
+                options_opds_new,
+                opds_new,
+                options_opds_books,
+                opds_books,
+                options_opds_genres,
+                opds_genres,
+                options_opds_genres_id,
+                opds_genre,
+                options_opds_authors,
+                opds_authors,
+                options_opds_users_token,
+                opds_user,
+                options_opds_users_token_books,
+                opds_user_books,
+                options_opds_users_token_shelves_shelf,
+                opds_user_shelf,
+                options_opds_users_token_recommendations,
+                opds_user_recommendations,
+                options_feeds_new,
+                feed_new_books,
+                options_feeds_recommendations_token,
+                feed_recommendations,
+                options_users_id_feed_token,
+                get_feed_token,
+                reset_feed_token
             ],
         );
 
@@ -0,0 +1,63 @@
+use rocket::serde::json::Value;
+
+/// Applies a JSON Merge Patch (RFC 7386) to `target`. Members of a patch
+/// object replace the target's, `null` removes a member, and nested objects
+/// are merged recursively. A patch that is not an object replaces the target.
+pub fn apply(target: &mut Value, patch: &Value) {
+    let Value::Object(members) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in members {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json::json;
+
+    use super::*;
+
+    fn patched(mut target: Value, patch: Value) -> Value {
+        apply(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn replaces_and_removes_members() {
+        assert_eq!(
+            patched(
+                json!({ "title": "Dune", "year": 1965, "pages": 412 }),
+                json!({ "title": "Dune Messiah", "pages": null }),
+            ),
+            json!({ "title": "Dune Messiah", "year": 1965 })
+        );
+    }
+
+    #[test]
+    fn merges_nested_objects_and_replaces_arrays() {
+        assert_eq!(
+            patched(
+                json!({ "a": { "b": 1, "c": 2 }, "tags": ["x", "y"] }),
+                json!({ "a": { "c": null, "d": 3 }, "tags": ["z"] }),
+            ),
+            json!({ "a": { "b": 1, "d": 3 }, "tags": ["z"] })
+        );
+    }
+
+    #[test]
+    fn replaces_the_target_with_a_patch_that_is_not_an_object() {
+        assert_eq!(patched(json!({ "a": 1 }), json!(["a"])), json!(["a"]));
+        assert_eq!(patched(json!("text"), json!({ "a": 1 })), json!({ "a": 1 }));
+    }
+}
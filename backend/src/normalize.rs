@@ -0,0 +1,261 @@
+/// Splits a free-text author field such as "Terry Pratchett & Neil Gaiman" or
+/// "Tolkien, J.R.R." into individual display names. Names separated by `;`,
+/// `&` or "and" are split; a single comma after a one-word surname is read as
+/// "Last, First".
+pub fn split_authors(value: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for group in value.split(';') {
+        let mut current: Vec<&str> = vec![];
+        for word in group.split_whitespace().chain(std::iter::once("&")) {
+            if word == "&" || word.eq_ignore_ascii_case("and") {
+                push_names(&current.join(" "), &mut names);
+                current.clear();
+            } else {
+                current.push(word);
+            }
+        }
+    }
+
+    let mut seen: Vec<String> = vec![];
+    names.retain(|name| {
+        let key = author_key(name);
+        if key.is_empty() || seen.contains(&key) {
+            return false;
+        }
+        seen.push(key);
+        true
+    });
+    names
+}
+
+fn push_names(part: &str, names: &mut Vec<String>) {
+    let pieces: Vec<&str> = part
+        .split(',')
+        .map(str::trim)
+        .filter(|piece| !piece.is_empty())
+        .collect();
+    match pieces.as_slice() {
+        [] => {}
+        [last, first] if !last.contains(' ') => names.push(format!("{} {}", first, last)),
+        _ => names.extend(pieces.iter().map(|piece| piece.to_string())),
+    }
+}
+
+/// Normalizes an author name into the key used to match `Author` nodes.
+/// Case and punctuation are ignored and runs of initials are joined, so
+/// "J.R.R. Tolkien", "J. R. R. Tolkien" and "JRR Tolkien" share the key
+/// "jrr tolkien".
+pub fn author_key(name: &str) -> String {
+    let lower = name.to_lowercase();
+    let mut key: Vec<String> = vec![];
+    let mut initials = String::new();
+    for token in lower
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+    {
+        if token.chars().count() == 1 {
+            initials.push_str(token);
+        } else {
+            if !initials.is_empty() {
+                key.push(std::mem::take(&mut initials));
+            }
+            key.push(token.to_string());
+        }
+    }
+    if !initials.is_empty() {
+        key.push(initials);
+    }
+    key.join(" ")
+}
+
+/// Normalizes a genre name into the key used to match `Genre` nodes, so
+/// "Science-Fiction" and "science fiction" are the same genre.
+pub fn genre_key(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Normalizes a series name into the key used to match `Series` nodes. Series
+/// names are compared the same way as genre names.
+pub fn series_key(name: &str) -> String {
+    genre_key(name)
+}
+
+/// Normalizes a title for comparison, ignoring case and punctuation.
+pub fn title_key(title: &str) -> String {
+    genre_key(title)
+}
+
+/// The key of the first author named in a free-text author field.
+pub fn first_author_key(author: &str) -> String {
+    split_authors(author)
+        .first()
+        .map(|name| author_key(name))
+        .unwrap_or_default()
+}
+
+/// The key used to group editions into a `Work`: the normalized title and the
+/// key of the first credited author.
+pub fn work_key(title: &str, author: &str) -> String {
+    format!("{}|{}", title_key(title), first_author_key(author))
+}
+
+/// Normalizes an ISBN-10 or ISBN-13 to its ISBN-13 form without hyphens or
+/// spaces. Returns `None` if the value is not a well-formed ISBN or its check
+/// digit is wrong.
+pub fn normalize_isbn(value: &str) -> Option<String> {
+    let isbn: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let digits: Vec<u32> = isbn
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            'X' if i == 9 && isbn.len() == 10 => Some(10),
+            _ => c.to_digit(10),
+        })
+        .collect::<Option<Vec<u32>>>()?;
+
+    match digits.len() {
+        10 => {
+            let sum: u32 = digits
+                .iter()
+                .zip((1..=10).rev())
+                .map(|(digit, weight)| digit * weight)
+                .sum();
+            if !sum.is_multiple_of(11) {
+                return None;
+            }
+            let body = format!("978{}", &isbn[..9]);
+            Some(format!("{}{}", body, isbn13_check_digit(&body)))
+        }
+        13 => {
+            let body = &isbn[..12];
+            let valid = (isbn.starts_with("978") || isbn.starts_with("979"))
+                && isbn13_check_digit(body) == digits[12];
+            valid.then_some(isbn)
+        }
+        _ => None,
+    }
+}
+
+fn isbn13_check_digit(body: &str) -> u32 {
+    let sum: u32 = body
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit } else { digit * 3 })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_isbns_to_isbn_13() {
+        for (value, expected) in [
+            ("0306406152", "9780306406157"),
+            ("0-306-40615-2", "9780306406157"),
+            ("0 306 40615 2", "9780306406157"),
+            ("080442957X", "9780804429573"),
+            ("080442957x", "9780804429573"),
+            ("9780306406157", "9780306406157"),
+            ("978-0-306-40615-7", "9780306406157"),
+            (" 979-10-90636-07-1 ", "9791090636071"),
+        ] {
+            assert_eq!(
+                normalize_isbn(value).as_deref(),
+                Some(expected),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_isbns() {
+        for value in [
+            "",
+            "0306406153",
+            "9780306406158",
+            "9770306406158",
+            "X306406152",
+            "03064061520",
+            "978030640615",
+            "0306406152X",
+            "isbn0306406152",
+        ] {
+            assert_eq!(normalize_isbn(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn author_keys_ignore_case_punctuation_and_spaced_initials() {
+        for (name, expected) in [
+            ("J.R.R. Tolkien", "jrr tolkien"),
+            ("J. R. R. Tolkien", "jrr tolkien"),
+            ("JRR Tolkien", "jrr tolkien"),
+            ("Ursula K. Le Guin", "ursula k le guin"),
+            ("  Terry   PRATCHETT ", "terry pratchett"),
+            ("Gabriel García Márquez", "gabriel garcía márquez"),
+            ("", ""),
+        ] {
+            assert_eq!(author_key(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn splits_author_fields_into_names() {
+        for (value, expected) in [
+            ("Terry Pratchett", vec!["Terry Pratchett"]),
+            (
+                "Terry Pratchett & Neil Gaiman",
+                vec!["Terry Pratchett", "Neil Gaiman"],
+            ),
+            (
+                "Terry Pratchett and Neil Gaiman",
+                vec!["Terry Pratchett", "Neil Gaiman"],
+            ),
+            (
+                "Terry Pratchett; Neil Gaiman",
+                vec!["Terry Pratchett", "Neil Gaiman"],
+            ),
+            ("Tolkien, J.R.R.", vec!["J.R.R. Tolkien"]),
+            (
+                "Ursula K. Le Guin, Margaret Atwood",
+                vec!["Ursula K. Le Guin", "Margaret Atwood"],
+            ),
+            ("J.R.R. Tolkien & JRR Tolkien", vec!["J.R.R. Tolkien"]),
+            (" ; & ", vec![]),
+        ] {
+            assert_eq!(split_authors(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn work_keys_use_the_title_and_first_author() {
+        for (title, author, expected) in [
+            (
+                "Good Omens",
+                "Terry Pratchett & Neil Gaiman",
+                "good omens|terry pratchett",
+            ),
+            (
+                "GOOD OMENS!",
+                "Pratchett, Terry",
+                "good omens|terry pratchett",
+            ),
+            ("The Hobbit", "J. R. R. Tolkien", "the hobbit|jrr tolkien"),
+            ("Beowulf", "", "beowulf|"),
+        ] {
+            assert_eq!(work_key(title, author), expected, "{}", title);
+        }
+    }
+}
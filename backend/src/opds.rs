@@ -0,0 +1,572 @@
+use std::convert::Infallible;
+
+use chrono::{SecondsFormat, Utc};
+use rocket::{
+    http::ContentType,
+    request::{FromRequest, Outcome},
+    serde::json::{json, serde_json, Value},
+    Request,
+};
+use serde::Serialize;
+
+use crate::{
+    export::{credits, field, list, xml_escape},
+    feeds::atom_entry,
+};
+
+/// Where the feeds are served, for the links between them.
+pub const OPDS_ROOT: &str = "/api/opds";
+
+/// How many of the latest books the new books feed lists.
+pub const NEW_BOOKS: i64 = 100;
+
+/// Entries per page of a feed.
+const PAGE_SIZE: usize = 50;
+
+const CATALOG_TITLE: &str = "Book Recommender";
+
+/// The relation of the link to what a publication entry hands out. The
+/// catalog holds no book files, so it is open access to the book's record.
+const ACQUISITION_REL: &str = "http://opds-spec.org/acquisition/open-access";
+
+/// The OPDS version a client reads: OPDS 2.0 when its `Accept` header asks
+/// for `application/opds+json`, otherwise the Atom feeds of OPDS 1.2 that
+/// most e-readers expect.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpdsVersion {
+    Atom,
+    Json,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OpdsVersion {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let json = request.accept().is_some_and(|accept| {
+            accept
+                .media_types()
+                .any(|media| media.top() == "application" && media.sub() == "opds+json")
+        });
+        Outcome::Success(match json {
+            true => OpdsVersion::Json,
+            false => OpdsVersion::Atom,
+        })
+    }
+}
+
+/// Whether a feed lists other feeds or books.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedKind {
+    Navigation,
+    Acquisition,
+}
+
+impl FeedKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FeedKind::Navigation => "navigation",
+            FeedKind::Acquisition => "acquisition",
+        }
+    }
+
+    fn content_type(&self, version: OpdsVersion) -> ContentType {
+        match version {
+            OpdsVersion::Atom => ContentType::new("application", "atom+xml")
+                .with_params([("profile", "opds-catalog"), ("kind", self.as_str())]),
+            OpdsVersion::Json => ContentType::new("application", "opds+json"),
+        }
+    }
+
+    fn media_type(&self, version: OpdsVersion) -> String {
+        self.content_type(version).to_string()
+    }
+}
+
+/// A link from a navigation feed to another feed.
+pub struct NavigationEntry {
+    title: String,
+    href: String,
+    kind: FeedKind,
+    summary: Option<String>,
+}
+
+impl NavigationEntry {
+    pub fn new(title: &str, href: String, kind: FeedKind, summary: Option<String>) -> Self {
+        NavigationEntry {
+            title: title.to_string(),
+            href,
+            kind,
+            summary,
+        }
+    }
+}
+
+/// One page of an OPDS feed, rendered as Atom or JSON for the client.
+pub struct Feed {
+    href: String,
+    title: String,
+    kind: FeedKind,
+    navigation: Vec<NavigationEntry>,
+    publications: Vec<Value>,
+    page: usize,
+    has_next: bool,
+    updated: String,
+}
+
+/// The rows to fetch for a page, counting pages from 1: how many to skip,
+/// and how many to return, one more than fit on the page to tell whether
+/// another page follows.
+pub fn page_window(page: Option<usize>) -> (i64, i64) {
+    let page = page.unwrap_or(1).max(1);
+    (((page - 1) * PAGE_SIZE) as i64, PAGE_SIZE as i64 + 1)
+}
+
+/// The entries on a page, given the rows of its [`page_window`], and
+/// whether more follow.
+fn window_page<T>(mut entries: Vec<T>) -> (Vec<T>, bool) {
+    let has_next = entries.len() > PAGE_SIZE;
+    entries.truncate(PAGE_SIZE);
+    (entries, has_next)
+}
+
+/// The entries on a page, counting pages from 1, and whether more follow.
+fn page_of<T>(entries: Vec<T>, page: usize) -> (Vec<T>, bool) {
+    window_page(
+        entries
+            .into_iter()
+            .skip((page - 1) * PAGE_SIZE)
+            .take(PAGE_SIZE + 1)
+            .collect(),
+    )
+}
+
+/// Drops the `page` parameter from the URI a feed was requested with.
+fn without_page(uri: &str) -> String {
+    match uri.split_once('?') {
+        Some((path, query)) => {
+            let query: Vec<&str> = query
+                .split('&')
+                .filter(|pair| !pair.is_empty() && !pair.starts_with("page="))
+                .collect();
+            match query.is_empty() {
+                true => path.to_string(),
+                false => format!("{}?{}", path, query.join("&")),
+            }
+        }
+        None => uri.to_string(),
+    }
+}
+
+fn page_href(href: &str, page: usize) -> String {
+    match (page, href.contains('?')) {
+        (1, _) => href.to_string(),
+        (_, true) => format!("{}&page={}", href, page),
+        (_, false) => format!("{}?page={}", href, page),
+    }
+}
+
+fn image_type(url: &str) -> &'static str {
+    let url = url.to_lowercase();
+    if url.ends_with(".png") {
+        "image/png"
+    } else if url.ends_with(".gif") {
+        "image/gif"
+    } else if url.ends_with(".webp") {
+        "image/webp"
+    } else {
+        "image/jpeg"
+    }
+}
+
+fn publications<T: Serialize>(books: &[T]) -> Vec<Value> {
+    books
+        .iter()
+        .map(|book| serde_json::to_value(book).unwrap_or_default())
+        .collect()
+}
+
+/// The book's record in the API, which is what the catalog hands out.
+fn book_href(book: &Value) -> String {
+    format!("/api/books/{}", field(book, "id").unwrap_or_default())
+}
+
+fn book_urn(book: &Value) -> String {
+    format!(
+        "urn:book-recommender:book:{}",
+        field(book, "id").unwrap_or_default()
+    )
+}
+
+impl Feed {
+    fn new(uri: &str, title: &str, kind: FeedKind, page: usize) -> Self {
+        Feed {
+            href: without_page(uri),
+            title: title.to_string(),
+            kind,
+            navigation: vec![],
+            publications: vec![],
+            page,
+            has_next: false,
+            updated: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    /// A feed of links to other feeds. `uri` is the URI it was requested with.
+    pub fn navigation(
+        uri: &str,
+        title: &str,
+        entries: Vec<NavigationEntry>,
+        page: Option<usize>,
+    ) -> Self {
+        let page = page.unwrap_or(1).max(1);
+        Feed::with_navigation(uri, title, page_of(entries, page), page)
+    }
+
+    /// A page of links to other feeds, given the entries in its
+    /// [`page_window`], for feeds too long to load whole.
+    pub fn navigation_window(
+        uri: &str,
+        title: &str,
+        window: Vec<NavigationEntry>,
+        page: Option<usize>,
+    ) -> Self {
+        let page = page.unwrap_or(1).max(1);
+        Feed::with_navigation(uri, title, window_page(window), page)
+    }
+
+    fn with_navigation(
+        uri: &str,
+        title: &str,
+        (navigation, has_next): (Vec<NavigationEntry>, bool),
+        page: usize,
+    ) -> Self {
+        Feed {
+            navigation,
+            has_next,
+            ..Feed::new(uri, title, FeedKind::Navigation, page)
+        }
+    }
+
+    /// A feed of books. Books with an `added_at` time show it as the time
+    /// their entry was updated.
+    pub fn acquisition<T: Serialize>(
+        uri: &str,
+        title: &str,
+        books: &[T],
+        page: Option<usize>,
+    ) -> Self {
+        let page = page.unwrap_or(1).max(1);
+        Feed::with_publications(uri, title, page_of(publications(books), page), page)
+    }
+
+    /// A page of books, given the books in its [`page_window`], for feeds
+    /// too long to load whole.
+    pub fn acquisition_window<T: Serialize>(
+        uri: &str,
+        title: &str,
+        window: &[T],
+        page: Option<usize>,
+    ) -> Self {
+        let page = page.unwrap_or(1).max(1);
+        Feed::with_publications(uri, title, window_page(publications(window)), page)
+    }
+
+    fn with_publications(
+        uri: &str,
+        title: &str,
+        (publications, has_next): (Vec<Value>, bool),
+        page: usize,
+    ) -> Self {
+        Feed {
+            publications,
+            has_next,
+            ..Feed::new(uri, title, FeedKind::Acquisition, page)
+        }
+    }
+
+    pub fn render(&self, version: OpdsVersion) -> (ContentType, String) {
+        let body = match version {
+            OpdsVersion::Atom => self.to_atom(),
+            OpdsVersion::Json => self.to_json().to_string(),
+        };
+        (self.kind.content_type(version), body)
+    }
+
+    /// The `self`, `start`, `previous` and `next` links of the page.
+    fn links(&self, version: OpdsVersion) -> Vec<(&'static str, String, String)> {
+        let mut links = vec![
+            (
+                "self",
+                page_href(&self.href, self.page),
+                self.kind.media_type(version),
+            ),
+            (
+                "start",
+                OPDS_ROOT.to_string(),
+                FeedKind::Navigation.media_type(version),
+            ),
+        ];
+        if self.page > 1 {
+            links.push((
+                "previous",
+                page_href(&self.href, self.page - 1),
+                self.kind.media_type(version),
+            ));
+        }
+        if self.has_next {
+            links.push((
+                "next",
+                page_href(&self.href, self.page + 1),
+                self.kind.media_type(version),
+            ));
+        }
+        links
+    }
+
+    fn to_atom(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/terms/\" \
+            xmlns:opds=\"http://opds-spec.org/2010/catalog\">\n",
+        );
+        xml.push_str(&format!(
+            "  <id>urn:book-recommender:feed:{}</id>\n  <title>{}</title>\n  <updated>{}</updated>\n",
+            xml_escape(&self.href),
+            xml_escape(&self.title),
+            self.updated
+        ));
+        xml.push_str(&format!(
+            "  <author><name>{}</name></author>\n",
+            CATALOG_TITLE
+        ));
+        for (rel, href, media_type) in self.links(OpdsVersion::Atom) {
+            xml.push_str(&atom_link(rel, &href, &media_type));
+        }
+        xml.push_str(&atom_link(
+            "search",
+            &format!("{}/search.xml", OPDS_ROOT),
+            "application/opensearchdescription+xml",
+        ));
+        for entry in &self.navigation {
+            xml.push_str(&format!(
+                "  <entry>\n    <title>{}</title>\n    <id>urn:book-recommender:feed:{}</id>\n    <updated>{}</updated>\n",
+                xml_escape(&entry.title),
+                xml_escape(&entry.href),
+                self.updated
+            ));
+            if let Some(summary) = &entry.summary {
+                xml.push_str(&format!(
+                    "    <content type=\"text\">{}</content>\n",
+                    xml_escape(summary)
+                ));
+            }
+            xml.push_str(&format!(
+                "  {}  </entry>\n",
+                atom_link(
+                    "subsection",
+                    &entry.href,
+                    &entry.kind.media_type(OpdsVersion::Atom)
+                )
+            ));
+        }
+        for book in &self.publications {
+            xml.push_str(&atom_publication(book, &self.updated));
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    fn to_json(&self) -> Value {
+        let mut links: Vec<Value> = self
+            .links(OpdsVersion::Json)
+            .into_iter()
+            .map(|(rel, href, media_type)| json!({ "rel": rel, "href": href, "type": media_type }))
+            .collect();
+        links.push(json!({
+            "rel": "search",
+            "href": format!("{}/books{{?q}}", OPDS_ROOT),
+            "type": FeedKind::Acquisition.media_type(OpdsVersion::Json),
+            "templated": true,
+        }));
+        let mut feed = json!({
+            "metadata": {
+                "title": self.title,
+                "modified": self.updated,
+                "itemsPerPage": PAGE_SIZE,
+                "currentPage": self.page,
+            },
+            "links": links,
+        });
+        match self.kind {
+            FeedKind::Navigation => {
+                feed["navigation"] = self
+                    .navigation
+                    .iter()
+                    .map(|entry| {
+                        json!({
+                            "title": entry.title,
+                            "href": entry.href,
+                            "type": entry.kind.media_type(OpdsVersion::Json),
+                        })
+                    })
+                    .collect();
+            }
+            FeedKind::Acquisition => {
+                feed["publications"] = self.publications.iter().map(json_publication).collect();
+            }
+        }
+        feed
+    }
+}
+
+fn atom_link(rel: &str, href: &str, media_type: &str) -> String {
+    format!(
+        "  <link rel=\"{}\" href=\"{}\" type=\"{}\"/>\n",
+        xml_escape(rel),
+        xml_escape(href),
+        xml_escape(media_type)
+    )
+}
+
+/// The entry of a book in an OPDS 1.2 feed: the Atom entry with the other
+/// contributors, Dublin Core terms, cover images and acquisition link.
+fn atom_publication(book: &Value, updated: &str) -> String {
+    let mut extra = String::new();
+    for (name, role) in credits(book) {
+        if role != "author" {
+            extra.push_str(&format!(
+                "    <contributor><name>{}</name></contributor>\n",
+                xml_escape(&name)
+            ));
+        }
+    }
+    let terms = [
+        ("dc:language", field(book, "language")),
+        ("dc:publisher", field(book, "publisher")),
+        ("dc:issued", field(book, "year")),
+        (
+            "dc:identifier",
+            field(book, "isbn").map(|isbn| format!("urn:isbn:{}", isbn)),
+        ),
+    ];
+    for (element, value) in terms {
+        if let Some(value) = value {
+            extra.push_str(&format!(
+                "    <{e}>{}</{e}>\n",
+                xml_escape(&value),
+                e = element
+            ));
+        }
+    }
+    if let Some(cover) = field(book, "cover") {
+        for rel in [
+            "http://opds-spec.org/image",
+            "http://opds-spec.org/image/thumbnail",
+        ] {
+            extra.push_str(&format!("  {}", atom_link(rel, &cover, image_type(&cover))));
+        }
+    }
+    extra.push_str(&format!(
+        "  {}",
+        atom_link(ACQUISITION_REL, &book_href(book), "application/json")
+    ));
+    atom_entry(&book_urn(book), book, updated, &extra)
+}
+
+fn json_publication(book: &Value) -> Value {
+    let mut metadata = json!({
+        "@type": "http://schema.org/Book",
+        "identifier": field(book, "isbn")
+            .map(|isbn| format!("urn:isbn:{}", isbn))
+            .unwrap_or_else(|| book_urn(book)),
+        "title": field(book, "title").unwrap_or_default(),
+    });
+    for (name, role) in credits(book) {
+        match metadata[&role].as_array_mut() {
+            Some(names) => names.push(json!({ "name": name })),
+            None => metadata[&role] = json!([{ "name": name }]),
+        }
+    }
+    for (key, value) in [
+        ("language", field(book, "language")),
+        ("publisher", field(book, "publisher")),
+        ("published", field(book, "year")),
+        ("description", field(book, "description")),
+    ] {
+        if let Some(value) = value {
+            metadata[key] = json!(value);
+        }
+    }
+    let subjects = [list(book, "genres"), list(book, "subjects")].concat();
+    if !subjects.is_empty() {
+        metadata["subject"] = json!(subjects);
+    }
+    let images: Vec<Value> = field(book, "cover")
+        .map(|cover| json!({ "href": cover, "type": image_type(&cover) }))
+        .into_iter()
+        .collect();
+    json!({
+        "metadata": metadata,
+        "links": [
+            { "rel": "self", "href": book_href(book), "type": "application/json" },
+            { "rel": ACQUISITION_REL, "href": book_href(book), "type": "application/json" },
+        ],
+        "images": images,
+    })
+}
+
+/// The OpenSearch description that tells OPDS 1.2 clients how to search the
+/// catalog.
+pub fn open_search_description() -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <OpenSearchDescription xmlns=\"http://a9.com/-/spec/opensearch/1.1/\">\n\
+        \x20 <ShortName>{title}</ShortName>\n\
+        \x20 <Description>Search the {title} catalog by title or author</Description>\n\
+        \x20 <InputEncoding>UTF-8</InputEncoding>\n\
+        \x20 <OutputEncoding>UTF-8</OutputEncoding>\n\
+        \x20 <Url type=\"{media_type}\" template=\"{root}/books?q={{searchTerms}}\"/>\n\
+        </OpenSearchDescription>\n",
+        title = CATALOG_TITLE,
+        media_type = xml_escape(&FeedKind::Acquisition.media_type(OpdsVersion::Atom)),
+        root = OPDS_ROOT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetches_one_more_row_than_fits_on_a_page() {
+        assert_eq!(page_window(None), (0, 51));
+        assert_eq!(page_window(Some(0)), (0, 51));
+        assert_eq!(page_window(Some(3)), (100, 51));
+        assert_eq!(window_page((0..51).collect()), ((0..50).collect(), true));
+        assert_eq!(window_page((0..50).collect()), ((0..50).collect(), false));
+    }
+
+    #[test]
+    fn links_publications_to_acquire_their_record() {
+        let book = json!({
+            "id": "b1",
+            "title": "Dune",
+            "contributors": [
+                { "name": "Frank Herbert", "role": "author" },
+                { "name": "John Schoenherr", "role": "illustrator" },
+            ],
+        });
+        let entry = atom_publication(&book, "2026-01-01T00:00:00Z");
+        assert!(entry.contains("<id>urn:book-recommender:book:b1</id>"));
+        assert!(entry.contains("<author><name>Frank Herbert</name></author>"));
+        assert!(entry.contains("<contributor><name>John Schoenherr</name></contributor>"));
+        assert!(entry.contains(
+            "<link rel=\"http://opds-spec.org/acquisition/open-access\" href=\"/api/books/b1\" type=\"application/json\"/>"
+        ));
+        assert_eq!(
+            json_publication(&book)["links"][1]["rel"],
+            "http://opds-spec.org/acquisition/open-access"
+        );
+    }
+}
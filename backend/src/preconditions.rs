@@ -0,0 +1,220 @@
+use std::convert::Infallible;
+
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    response::{self, status::BadRequest, Responder},
+    serde::json::Json,
+    Request, Response,
+};
+use serde::Serialize;
+
+use crate::database::FieldError;
+
+/// The `ETag` of an entity version, e.g. `"3"`.
+fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Whether a comma-separated list of entity tags names `version`. Weak tags
+/// (`W/"3"`) only count for weak comparison, which `If-None-Match` uses.
+fn names_version(header: &str, version: i64, weak: bool) -> bool {
+    let current = etag(version);
+    header.split(',').map(str::trim).any(|tag| {
+        tag == "*" || tag == current || (weak && tag.strip_prefix("W/") == Some(current.as_str()))
+    })
+}
+
+/// The `If-Match` header of a request.
+pub struct IfMatch(Option<String>);
+
+/// The `If-None-Match` header of a request.
+pub struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfMatch(
+            request.headers().get_one("If-Match").map(str::to_string),
+        ))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(
+            request
+                .headers()
+                .get_one("If-None-Match")
+                .map(str::to_string),
+        ))
+    }
+}
+
+impl IfMatch {
+    /// Lets a write through only if `If-Match` names the current version of
+    /// the entity: 428 when the header is missing, 412 when it is stale and
+    /// 404 when the entity does not exist. Returns the version the write must
+    /// still find, so a change made in between is refused as well.
+    pub fn check(&self, version: Option<i64>) -> Result<i64, Status> {
+        let header = self.0.as_deref().ok_or(Status::PreconditionRequired)?;
+        let version = version.ok_or(Status::NotFound)?;
+        if names_version(header, version, false) {
+            Ok(version)
+        } else {
+            Err(Status::PreconditionFailed)
+        }
+    }
+}
+
+/// A JSON body sent with the `ETag` of its version, or an empty 304 when the
+/// client's `If-None-Match` already names that version.
+pub enum Tagged<T> {
+    Body(T, Option<i64>),
+    NotModified(i64),
+}
+
+impl<T> Tagged<T> {
+    pub fn new(body: T, version: Option<i64>, if_none_match: &IfNoneMatch) -> Self {
+        match (version, if_none_match.0.as_deref()) {
+            (Some(version), Some(header)) if names_version(header, version, true) => {
+                Tagged::NotModified(version)
+            }
+            _ => Tagged::Body(body, version),
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Tagged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Tagged::Body(body, version) => {
+                let mut response = Json(body).respond_to(request)?;
+                if let Some(version) = version {
+                    response.set_raw_header("ETag", etag(version));
+                }
+                Ok(response)
+            }
+            Tagged::NotModified(version) => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag(version))
+                .ok(),
+        }
+    }
+}
+
+/// Why a write to a versioned entity was refused.
+#[derive(Responder)]
+pub enum Rejected {
+    Invalid(BadRequest<Json<Vec<FieldError>>>),
+    Precondition(Status),
+}
+
+impl From<Vec<FieldError>> for Rejected {
+    fn from(errors: Vec<FieldError>) -> Self {
+        Rejected::Invalid(BadRequest(Json(errors)))
+    }
+}
+
+impl From<Status> for Rejected {
+    fn from(status: Status) -> Self {
+        Rejected::Precondition(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::local::blocking::Client;
+
+    use super::*;
+
+    fn if_match(header: Option<&str>) -> IfMatch {
+        IfMatch(header.map(str::to_string))
+    }
+
+    fn if_none_match(header: Option<&str>) -> IfNoneMatch {
+        IfNoneMatch(header.map(str::to_string))
+    }
+
+    #[test]
+    fn names_versions_in_entity_tag_lists() {
+        assert!(names_version("\"3\"", 3, false));
+        assert!(names_version("*", 3, false));
+        assert!(names_version("\"1\", \"3\"", 3, false));
+        assert!(names_version("\"1\",\"3\"", 3, false));
+        assert!(!names_version("\"1\", \"2\"", 3, false));
+        assert!(!names_version("3", 3, false));
+        assert!(!names_version("\"33\"", 3, false));
+    }
+
+    #[test]
+    fn weak_tags_only_match_in_weak_comparison() {
+        assert!(!names_version("W/\"3\"", 3, false));
+        assert!(names_version("W/\"3\"", 3, true));
+        assert!(names_version("W/\"1\", W/\"3\"", 3, true));
+        assert!(!names_version("W/\"2\"", 3, true));
+    }
+
+    #[test]
+    fn if_match_lets_current_versions_through() {
+        assert_eq!(if_match(Some("\"3\"")).check(Some(3)), Ok(3));
+        assert_eq!(if_match(Some("\"2\", \"3\"")).check(Some(3)), Ok(3));
+        assert_eq!(if_match(Some("*")).check(Some(3)), Ok(3));
+    }
+
+    #[test]
+    fn if_match_refuses_missing_stale_and_weak_tags() {
+        assert_eq!(
+            if_match(None).check(Some(3)),
+            Err(Status::PreconditionRequired)
+        );
+        assert_eq!(
+            if_match(Some("\"2\"")).check(Some(3)),
+            Err(Status::PreconditionFailed)
+        );
+        assert_eq!(
+            if_match(Some("W/\"3\"")).check(Some(3)),
+            Err(Status::PreconditionFailed)
+        );
+        assert_eq!(if_match(Some("\"3\"")).check(None), Err(Status::NotFound));
+        assert_eq!(
+            if_match(None).check(None),
+            Err(Status::PreconditionRequired)
+        );
+    }
+
+    #[test]
+    fn tagged_bodies_carry_their_etag() {
+        let client = Client::debug_with(vec![]).unwrap();
+        let request = client.get("/");
+        let response = Tagged::new("Dune", Some(3), &if_none_match(Some("\"2\"")))
+            .respond_to(request.inner())
+            .unwrap();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some("\"3\""));
+    }
+
+    #[test]
+    fn tagged_bodies_are_not_modified_when_if_none_match_names_them() {
+        let client = Client::debug_with(vec![]).unwrap();
+        let request = client.get("/");
+        for header in ["\"3\"", "W/\"3\"", "\"1\", W/\"3\"", "*"] {
+            let response = Tagged::new("Dune", Some(3), &if_none_match(Some(header)))
+                .respond_to(request.inner())
+                .unwrap();
+            assert_eq!(response.status(), Status::NotModified);
+            assert_eq!(response.headers().get_one("ETag"), Some("\"3\""));
+        }
+    }
+
+    #[test]
+    fn missing_entities_are_never_not_modified() {
+        let tagged = Tagged::new(None::<&str>, None, &if_none_match(Some("*")));
+        assert!(matches!(tagged, Tagged::Body(None, None)));
+    }
+}
@@ -4,6 +4,7 @@ import { RegisterComponent } from './components/register/register.component';
 import { HomeComponent } from './components/home/home.component';
 import { BooksComponent } from './components/books/books.component';
 import { UsersComponent } from './components/users/users.component';
+import { LibraryImportComponent } from './components/library-import/library-import.component';
 
 export const routes: Routes = [
     { path: "login", component: LoginComponent },
@@ -11,5 +12,6 @@ export const routes: Routes = [
     { path: "home", component: HomeComponent },
     { path: "books", component: BooksComponent },
     { path: "users", component: UsersComponent },
+    { path: "import", component: LibraryImportComponent },
     { path: "", component: HomeComponent }
 ];
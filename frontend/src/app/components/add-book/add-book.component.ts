@@ -24,13 +24,12 @@ export class AddBookComponent {
   private _snackbar = inject(MatSnackBar);
 
   onAdd(title: string, author: string, genre: string, cover: string) {
-    let id = uuidv4();
     const myHeaders = new Headers();
     myHeaders.append("Content-Type", "application/json");
     myHeaders.append("Accept", "application/json");
+    myHeaders.append("Idempotency-Key", uuidv4());
 
     const payload = {
-      id: id,
       title: title,
       author: author,
       genre: genre,
@@ -16,6 +16,7 @@ interface Book {
   author: string;
   genre: string;
   cover: string;
+  version: number;
 }
 
 @Component({
@@ -79,6 +80,7 @@ export class BooksComponent {
     const headers = new Headers();
     headers.append("Content-Type", "application/json");
     headers.append("Accept", "application/json");
+    headers.append("If-Match", `"${book.version}"`);
 
     fetch(`${this.api}/books/${book.id}`, {
       method: "DELETE",
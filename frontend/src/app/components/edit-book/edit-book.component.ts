@@ -17,6 +17,7 @@ interface Book {
   author: string;
   genre: string;
   cover: string;
+  version: number;
 }
 
 @Component({
@@ -36,6 +37,7 @@ export class EditBookComponent {
     const myHeaders = new Headers();
     myHeaders.append("Content-Type", "application/json");
     myHeaders.append("Accept", "application/json");
+    myHeaders.append("If-Match", `"${this.book.version}"`);
 
     const requestOptions = {
       method: "PUT",
@@ -0,0 +1,23 @@
+import { ComponentFixture, TestBed } from '@angular/core/testing';
+
+import { LibraryImportComponent } from './library-import.component';
+
+describe('LibraryImportComponent', () => {
+  let component: LibraryImportComponent;
+  let fixture: ComponentFixture<LibraryImportComponent>;
+
+  beforeEach(async () => {
+    await TestBed.configureTestingModule({
+      imports: [LibraryImportComponent]
+    })
+    .compileComponents();
+
+    fixture = TestBed.createComponent(LibraryImportComponent);
+    component = fixture.componentInstance;
+    fixture.detectChanges();
+  });
+
+  it('should create', () => {
+    expect(component).toBeTruthy();
+  });
+});
@@ -0,0 +1,147 @@
+import { Component, computed, inject } from '@angular/core';
+import { FormsModule } from '@angular/forms';
+import { AuthService } from '../../services/auth.service';
+import { environment } from '../../../environments/environment';
+import { MatCardModule } from '@angular/material/card';
+import { MatButtonModule } from '@angular/material/button';
+import { MatSelectModule } from '@angular/material/select';
+import { MatProgressBarModule } from '@angular/material/progress-bar';
+import { MatSnackBar } from '@angular/material/snack-bar';
+
+interface ImportJob {
+  id: number;
+  format: string;
+  dry_run: boolean;
+  status: string;
+  error: string | null;
+  total: number;
+  processed: number;
+  matched: number;
+  created: number;
+  unmatched: number;
+  skipped: number;
+  failed: number;
+}
+
+interface Candidate {
+  book_id: string;
+  title: string;
+  author: string;
+  similarity: number;
+}
+
+interface ImportRow {
+  row: number;
+  book: { title: string; author: string };
+  candidates: Candidate[];
+}
+
+@Component({
+  selector: 'app-library-import',
+  imports: [FormsModule, MatCardModule, MatButtonModule, MatSelectModule, MatProgressBarModule],
+  templateUrl: './library-import.component.html',
+  styleUrl: './library-import.component.css'
+})
+export class LibraryImportComponent {
+  api = environment.api;
+  format = "goodreads";
+  file: File | null = null;
+  job: ImportJob | null = null;
+  unmatched: ImportRow[] = [];
+  private authService = inject(AuthService);
+  private _snackbar = inject(MatSnackBar);
+  currentUser = computed(() => this.authService.getUser());
+
+  constructor() { }
+
+  ngOnInit(): void {
+    const user = this.authService.getUser();
+    if (!user) {
+      return;
+    }
+    fetch(`${this.api}/users/${user.id}/imports`)
+      .then((response) => response.json())
+      .then((json) => {
+        if (json.length > 0) {
+          this.follow(json[0].id);
+        }
+      })
+      .catch((error) => console.error(`There was an error fetching imports: ${error}`));
+  }
+
+  selectFile(event: Event): void {
+    this.file = (event.target as HTMLInputElement).files?.item(0) ?? null;
+  }
+
+  upload(): void {
+    const user = this.authService.getUser();
+    if (!user || !this.file) {
+      return;
+    }
+    fetch(`${this.api}/users/${user.id}/imports?format=${this.format}`, {
+      method: "POST",
+      body: this.file
+    })
+      .then((response) => response.json().then((json) => ({ ok: response.ok, json })))
+      .then(({ ok, json }) => {
+        if (ok) {
+          this.unmatched = [];
+          this.follow(json.id);
+        } else {
+          this._snackbar.open(json.map((error: { message: string }) => error.message).join("; "), "OK", {
+            duration: 5000
+          });
+        }
+      })
+      .catch((error) => console.error(`There was an error uploading the export: ${error}`));
+  }
+
+  follow(id: number): void {
+    fetch(`${this.api}/imports/${id}`)
+      .then((response) => response.json())
+      .then((json) => {
+        this.job = json;
+        if (json.status === "completed") {
+          this.getUnmatched(id);
+        } else if (json.status !== "failed") {
+          setTimeout(() => this.follow(id), 1000);
+        }
+      })
+      .catch((error) => console.error(`There was an error fetching the import: ${error}`));
+  }
+
+  getUnmatched(id: number): void {
+    fetch(`${this.api}/imports/${id}/rows?outcome=unmatched`)
+      .then((response) => response.json())
+      .then((json) => this.unmatched = json)
+      .catch((error) => console.error(`There was an error fetching unmatched rows: ${error}`));
+  }
+
+  resolve(row: ImportRow, resolution: { action: string; book_id?: string }): void {
+    if (!this.job) {
+      return;
+    }
+    const id = this.job.id;
+    const headers = new Headers();
+    headers.append("Content-Type", "application/json");
+    headers.append("Accept", "application/json");
+
+    fetch(`${this.api}/imports/${id}/rows/${row.row}/resolve`, {
+      method: "POST",
+      headers: headers,
+      body: JSON.stringify(resolution)
+    })
+      .then((response) => response.json())
+      .then((json) => {
+        if (json === true) {
+          this.unmatched = this.unmatched.filter((other) => other.row !== row.row);
+          this.follow(id);
+        } else {
+          this._snackbar.open("Could not resolve row.", "OK", {
+            duration: 3000
+          });
+        }
+      })
+      .catch((error) => console.error(`There was an error resolving the row: ${error}`));
+  }
+}